@@ -0,0 +1,98 @@
+//! Throughput of the wire encodings on a trading day's typical traffic:
+//! price-only market data updates, full snapshots and fills.
+//!
+//! `cargo bench --bench encoding`
+
+use std::hint::black_box;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use stock_side::brokers::Fill;
+use stock_side::codec::Encoding;
+use stock_side::fees::{FeeSchedule, Liquidity};
+use stock_side::market_data::MarketData;
+use stock_side::money::Money;
+
+const MESSAGES: usize = 1000;
+const ENCODINGS: [Encoding; 2] = [Encoding::Json, Encoding::Msgpack];
+
+fn symbol(index: usize) -> String {
+    format!("SYM{}", index % 55)
+}
+
+fn updates() -> Vec<MarketData> {
+    (0..MESSAGES)
+        .map(|index| MarketData::Update {
+            symbol: symbol(index),
+            sequence: index as u64 + 2,
+            price: Some(Money::from_f64(100.0 + index as f64 * 0.37)),
+            available_quantity: None,
+        })
+        .collect()
+}
+
+fn snapshots() -> Vec<MarketData> {
+    (0..MESSAGES)
+        .map(|index| MarketData::Snapshot {
+            symbol: symbol(index),
+            sequence: index as u64 + 1,
+            price: Money::from_f64(100.0 + index as f64 * 0.37),
+            available_quantity: 100,
+        })
+        .collect()
+}
+
+fn fills() -> Vec<Fill> {
+    let fee_schedule = FeeSchedule::default();
+    (0..MESSAGES)
+        .map(|index| {
+            let price = Money::from_f64(100.0 + index as f64 * 0.37);
+            let quantity = index % 5 + 1;
+            Fill {
+                activity_id: format!("bench-{}", index),
+                broker_id: (index % 3) as u32 + 1,
+                stock_id: symbol(index),
+                action: "Buy".to_string(),
+                quantity,
+                price,
+                liquidity: Liquidity::Taker,
+                fees: fee_schedule.calculate(price, quantity, Liquidity::Taker),
+            }
+        })
+        .collect()
+}
+
+fn bench_messages<T: Serialize + DeserializeOwned>(c: &mut Criterion, name: &str, messages: &[T]) {
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Elements(messages.len() as u64));
+    for encoding in ENCODINGS {
+        let encoded: Vec<Vec<u8>> = messages.iter().map(|message| encoding.encode(message)).collect();
+        let bytes: usize = encoded.iter().map(Vec::len).sum();
+        println!("{}/{:?}: {:.1} bytes per message", name, encoding, bytes as f64 / messages.len() as f64);
+
+        group.bench_with_input(BenchmarkId::new("encode", format!("{:?}", encoding)), messages, |b, messages| {
+            b.iter(|| {
+                for message in messages {
+                    black_box(encoding.encode(message));
+                }
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("decode", format!("{:?}", encoding)), &encoded, |b, encoded| {
+            b.iter(|| {
+                for data in encoded {
+                    black_box(encoding.decode::<T>(data).unwrap());
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn encodings(c: &mut Criterion) {
+    bench_messages(c, "market_data_updates", &updates());
+    bench_messages(c, "market_data_snapshots", &snapshots());
+    bench_messages(c, "fills", &fills());
+}
+
+criterion_group!(benches, encodings);
+criterion_main!(benches);
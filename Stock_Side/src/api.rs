@@ -0,0 +1,258 @@
+//! REST API on the `[web]` server: instrument reference data, current
+//! prices, depth and this session's trades, all as JSON. The OpenAPI
+//! document at `/api/openapi.json` is generated from the handlers and types
+//! below, so it cannot drift from what is served.
+
+use std::sync::Arc;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use log::error;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use crate::instrument::Instrument;
+use crate::money::Money;
+use crate::order_entry::OrderEntry;
+use crate::stock::Stock;
+use crate::store::TradeRecord;
+
+const DEFAULT_TRADE_LIMIT: usize = 100;
+const MAX_TRADE_LIMIT: usize = 1000;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Stock_Side", description = "Market reference data, prices and trades."),
+    paths(instruments, instrument, stocks, stock, depth, trades)
+)]
+struct ApiDoc;
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ApiError {
+    error: String,
+}
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+
+fn api_error(status: StatusCode, error: String) -> (StatusCode, Json<ApiError>) {
+    (status, Json(ApiError { error }))
+}
+
+fn unknown_symbol(symbol: &str) -> (StatusCode, Json<ApiError>) {
+    api_error(StatusCode::NOT_FOUND, format!("unknown symbol {}", symbol))
+}
+
+/// One price level; a `null` quantity means any size is taken.
+#[derive(Debug, Serialize, ToSchema)]
+struct Level {
+    price: Money,
+    quantity: Option<usize>,
+}
+
+/// The market keeps no resting orders: it sells up to the available
+/// quantity and buys any quantity, both at the current price, so each side
+/// has at most one level.
+#[derive(Debug, Serialize, ToSchema)]
+struct Depth {
+    symbol: String,
+    bids: Vec<Level>,
+    asks: Vec<Level>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct TradesQuery {
+    /// Most recent trades to return; 100 by default, at most 1000.
+    limit: Option<usize>,
+}
+
+pub fn router(order_entry: Arc<OrderEntry>) -> Router {
+    Router::new()
+        .route("/api/openapi.json", get(openapi))
+        .route("/api/instruments", get(instruments))
+        .route("/api/instruments/{symbol}", get(instrument))
+        .route("/api/stocks", get(stocks))
+        .route("/api/stocks/{symbol}", get(stock))
+        .route("/api/stocks/{symbol}/depth", get(depth))
+        .route("/api/stocks/{symbol}/trades", get(trades))
+        .with_state(order_entry)
+}
+
+async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// Every tradable instrument, by symbol.
+#[utoipa::path(get, path = "/api/instruments", responses((status = 200, body = Vec<Instrument>)))]
+async fn instruments(State(order_entry): State<Arc<OrderEntry>>) -> Json<Vec<Instrument>> {
+    let mut instruments: Vec<Instrument> = order_entry.instruments.values().cloned().collect();
+    instruments.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+    Json(instruments)
+}
+
+/// Reference data for one symbol.
+#[utoipa::path(
+    get,
+    path = "/api/instruments/{symbol}",
+    params(("symbol" = String, Path)),
+    responses((status = 200, body = Instrument), (status = 404, body = ApiError))
+)]
+async fn instrument(State(order_entry): State<Arc<OrderEntry>>, Path(symbol): Path<String>) -> ApiResult<Instrument> {
+    match order_entry.instruments.get(&symbol) {
+        Some(instrument) => Ok(Json(instrument.clone())),
+        None => Err(unknown_symbol(&symbol)),
+    }
+}
+
+/// Current price and available quantity of every stock, by symbol.
+#[utoipa::path(get, path = "/api/stocks", responses((status = 200, body = Vec<Stock>)))]
+async fn stocks(State(order_entry): State<Arc<OrderEntry>>) -> Json<Vec<Stock>> {
+    let mut stocks: Vec<Stock> = order_entry.stocks.lock().await.values().cloned().collect();
+    stocks.sort_by(|a, b| a.id.cmp(&b.id));
+    Json(stocks)
+}
+
+/// Current price and available quantity of one stock.
+#[utoipa::path(
+    get,
+    path = "/api/stocks/{symbol}",
+    params(("symbol" = String, Path)),
+    responses((status = 200, body = Stock), (status = 404, body = ApiError))
+)]
+async fn stock(State(order_entry): State<Arc<OrderEntry>>, Path(symbol): Path<String>) -> ApiResult<Stock> {
+    match order_entry.stocks.lock().await.get(&symbol) {
+        Some(stock) => Ok(Json(stock.clone())),
+        None => Err(unknown_symbol(&symbol)),
+    }
+}
+
+/// What the market will trade in one stock right now.
+#[utoipa::path(
+    get,
+    path = "/api/stocks/{symbol}/depth",
+    params(("symbol" = String, Path)),
+    responses((status = 200, body = Depth), (status = 404, body = ApiError))
+)]
+async fn depth(State(order_entry): State<Arc<OrderEntry>>, Path(symbol): Path<String>) -> ApiResult<Depth> {
+    let stocks = order_entry.stocks.lock().await;
+    let Some(stock) = stocks.get(&symbol) else {
+        return Err(unknown_symbol(&symbol));
+    };
+    let asks = if stock.available_quantity > 0 {
+        vec![Level { price: stock.price, quantity: Some(stock.available_quantity) }]
+    } else {
+        Vec::new()
+    };
+    Ok(Json(Depth {
+        symbol,
+        bids: vec![Level { price: stock.price, quantity: None }],
+        asks,
+    }))
+}
+
+/// This session's trades in one stock, newest first.
+#[utoipa::path(
+    get,
+    path = "/api/stocks/{symbol}/trades",
+    params(("symbol" = String, Path), TradesQuery),
+    responses(
+        (status = 200, body = Vec<TradeRecord>),
+        (status = 404, body = ApiError),
+        (status = 500, body = ApiError)
+    )
+)]
+async fn trades(
+    State(order_entry): State<Arc<OrderEntry>>,
+    Path(symbol): Path<String>,
+    Query(query): Query<TradesQuery>,
+) -> ApiResult<Vec<TradeRecord>> {
+    if !order_entry.instruments.contains_key(&symbol) {
+        return Err(unknown_symbol(&symbol));
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_TRADE_LIMIT).min(MAX_TRADE_LIMIT);
+    order_entry.store.trades(&symbol, limit).map(Json).map_err(|e| {
+        error!("Failed to read trades: {:?}", e);
+        api_error(StatusCode::INTERNAL_SERVER_ERROR, "failed to read trades".to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use serde_json::Value;
+    use tower::ServiceExt;
+    use crate::brokers::BrokerActivity;
+
+    async fn get_json(router: &Router, uri: &str) -> (StatusCode, Value) {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn serves_reference_data_prices_and_depth() {
+        let journal_dir = env::temp_dir().join(format!("stock_side_api_test_prices_{}", process::id()));
+        let router = router(OrderEntry::for_tests(&journal_dir));
+
+        let (status, instruments) = get_json(&router, "/api/instruments").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(instruments[0]["symbol"], "AAPL");
+        assert_eq!(instruments[0]["max_quantity"], 100);
+
+        let (status, stock) = get_json(&router, "/api/stocks/AAPL").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(stock["available_quantity"], 1000);
+
+        let (status, depth) = get_json(&router, "/api/stocks/AAPL/depth").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(depth["asks"][0]["quantity"], 1000);
+        assert!(depth["bids"][0]["quantity"].is_null());
+
+        let (status, error) = get_json(&router, "/api/stocks/MSFT").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(error["error"], "unknown symbol MSFT");
+
+        let (status, openapi) = get_json(&router, "/api/openapi.json").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(openapi["paths"]["/api/stocks/{symbol}/trades"].is_object());
+
+        let _ = std::fs::remove_dir_all(journal_dir);
+    }
+
+    #[tokio::test]
+    async fn lists_the_session_trades_newest_first() {
+        let journal_dir = env::temp_dir().join(format!("stock_side_api_test_trades_{}", process::id()));
+        let order_entry = OrderEntry::for_tests(&journal_dir);
+        for (activity_id, quantity) in [("a", 10), ("b", 20)] {
+            let activity = BrokerActivity {
+                activity_id: activity_id.to_string(),
+                broker_id: 7,
+                stock_id: "AAPL".to_string(),
+                action: "Buy".to_string(),
+                quantity,
+                price: None,
+            };
+            order_entry.submit(activity).await;
+        }
+        let router = router(order_entry);
+
+        let (status, trades) = get_json(&router, "/api/stocks/AAPL/trades").await;
+        assert_eq!(status, StatusCode::OK);
+        let quantities: Vec<&Value> = trades.as_array().unwrap().iter().map(|trade| &trade["quantity"]).collect();
+        assert_eq!(quantities, [20, 10]);
+
+        let (_, trades) = get_json(&router, "/api/stocks/AAPL/trades?limit=1").await;
+        assert_eq!(trades.as_array().unwrap().len(), 1);
+
+        let (status, _) = get_json(&router, "/api/stocks/MSFT/trades").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let _ = std::fs::remove_dir_all(journal_dir);
+    }
+}
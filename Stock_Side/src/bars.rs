@@ -0,0 +1,217 @@
+//! OHLCV bar aggregation.
+//!
+//! Every price tick and fill is folded into one open bar per symbol and
+//! interval. A bar starts at the tick that opens its bucket (market time
+//! floored to the interval) and completes when a tick lands in a later
+//! bucket or the market closes. Fills only move high/low/close and add
+//! volume; they never open a bar.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use chrono::{NaiveTime, Timelike};
+use serde::{Deserialize, Serialize};
+use crate::money::Money;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BarInterval {
+    #[serde(rename = "1m")]
+    OneMinute,
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    #[serde(rename = "30m")]
+    ThirtyMinutes,
+    #[serde(rename = "1d")]
+    OneDay,
+}
+
+/// Intervals aggregated unless `market.bar_intervals` says otherwise; none
+/// is shorter than the default 30-minute cycle.
+pub const DEFAULT_BAR_INTERVALS: [BarInterval; 2] = [BarInterval::ThirtyMinutes, BarInterval::OneDay];
+
+impl BarInterval {
+    pub fn minutes(self) -> u32 {
+        match self {
+            BarInterval::OneMinute => 1,
+            BarInterval::FiveMinutes => 5,
+            BarInterval::ThirtyMinutes => 30,
+            BarInterval::OneDay => 24 * 60,
+        }
+    }
+
+    /// Start of the bucket `time` falls in.
+    pub fn bucket_start(self, time: NaiveTime) -> NaiveTime {
+        let minute_of_day = time.hour() * 60 + time.minute();
+        let start = minute_of_day - minute_of_day % self.minutes();
+        NaiveTime::from_hms_opt(start / 60, start % 60, 0).expect("bucket start is within the day")
+    }
+}
+
+impl fmt::Display for BarInterval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            BarInterval::OneMinute => "1m",
+            BarInterval::FiveMinutes => "5m",
+            BarInterval::ThirtyMinutes => "30m",
+            BarInterval::OneDay => "1d",
+        };
+        f.write_str(label)
+    }
+}
+
+impl FromStr for BarInterval {
+    type Err = String;
+
+    fn from_str(label: &str) -> Result<Self, Self::Err> {
+        match label {
+            "1m" => Ok(BarInterval::OneMinute),
+            "5m" => Ok(BarInterval::FiveMinutes),
+            "30m" => Ok(BarInterval::ThirtyMinutes),
+            "1d" => Ok(BarInterval::OneDay),
+            _ => Err(format!("unknown bar interval {}; expected 1m, 5m, 30m or 1d", label)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bar {
+    pub symbol: String,
+    pub interval: BarInterval,
+    pub start: NaiveTime,
+    pub open: Money,
+    pub high: Money,
+    pub low: Money,
+    pub close: Money,
+    /// Shares filled during the bar.
+    pub volume: usize,
+}
+
+impl Bar {
+    fn new(symbol: &str, interval: BarInterval, start: NaiveTime, price: Money) -> Self {
+        Bar {
+            symbol: symbol.to_string(),
+            interval,
+            start,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume: 0,
+        }
+    }
+
+    fn update(&mut self, price: Money) {
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+        self.close = price;
+    }
+}
+
+pub struct BarAggregator {
+    intervals: Vec<BarInterval>,
+    open_bars: HashMap<(String, BarInterval), Bar>,
+}
+
+impl BarAggregator {
+    pub fn new(intervals: &[BarInterval]) -> Self {
+        BarAggregator {
+            intervals: intervals.to_vec(),
+            open_bars: HashMap::new(),
+        }
+    }
+
+    /// Folds a price tick into the symbol's bars and returns any bars the
+    /// tick completed.
+    pub fn record_tick(&mut self, symbol: &str, time: NaiveTime, price: Money) -> Vec<Bar> {
+        let mut completed = Vec::new();
+        for &interval in &self.intervals {
+            let start = interval.bucket_start(time);
+            let key = (symbol.to_string(), interval);
+            match self.open_bars.get_mut(&key) {
+                Some(bar) if bar.start == start => bar.update(price),
+                _ => {
+                    if let Some(bar) = self.open_bars.insert(key, Bar::new(symbol, interval, start, price)) {
+                        completed.push(bar);
+                    }
+                }
+            }
+        }
+        completed
+    }
+
+    pub fn record_fill(&mut self, symbol: &str, price: Money, quantity: usize) {
+        for &interval in &self.intervals {
+            if let Some(bar) = self.open_bars.get_mut(&(symbol.to_string(), interval)) {
+                bar.update(price);
+                bar.volume += quantity;
+            }
+        }
+    }
+
+    /// Completes every open bar, e.g. at market close.
+    pub fn close_all(&mut self) -> Vec<Bar> {
+        let mut bars: Vec<Bar> = self.open_bars.drain().map(|(_, bar)| bar).collect();
+        bars.sort_by(|a, b| a.symbol.cmp(&b.symbol).then(a.interval.minutes().cmp(&b.interval.minutes())));
+        bars
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn dollars(amount: i64) -> Money {
+        Money::from_cents(amount * 100)
+    }
+
+    #[test]
+    fn floors_times_to_the_start_of_their_bucket() {
+        assert_eq!(BarInterval::FiveMinutes.bucket_start(at(9, 34)), at(9, 30));
+        assert_eq!(BarInterval::ThirtyMinutes.bucket_start(at(9, 30)), at(9, 30));
+        assert_eq!(BarInterval::ThirtyMinutes.bucket_start(at(9, 59)), at(9, 30));
+        assert_eq!(BarInterval::ThirtyMinutes.bucket_start(at(10, 0)), at(10, 0));
+        assert_eq!(BarInterval::OneDay.bucket_start(at(15, 45)), at(0, 0));
+    }
+
+    #[test]
+    fn folds_ticks_and_fills_into_ohlcv() {
+        let mut bars = BarAggregator::new(&[BarInterval::ThirtyMinutes]);
+        assert!(bars.record_tick("AAPL", at(9, 30), dollars(100)).is_empty());
+        bars.record_fill("AAPL", dollars(104), 10);
+        assert!(bars.record_tick("AAPL", at(9, 45), dollars(97)).is_empty());
+        bars.record_fill("AAPL", dollars(99), 5);
+
+        let [bar] = bars.close_all().try_into().unwrap();
+        assert_eq!((bar.symbol.as_str(), bar.interval, bar.start), ("AAPL", BarInterval::ThirtyMinutes, at(9, 30)));
+        assert_eq!((bar.open, bar.high, bar.low, bar.close), (dollars(100), dollars(104), dollars(97), dollars(99)));
+        assert_eq!(bar.volume, 15);
+    }
+
+    #[test]
+    fn completes_a_bar_when_a_tick_lands_in_the_next_bucket() {
+        let mut bars = BarAggregator::new(&[BarInterval::ThirtyMinutes, BarInterval::OneDay]);
+        bars.record_tick("AAPL", at(9, 30), dollars(100));
+        bars.record_tick("AAPL", at(9, 59), dollars(101));
+
+        let completed = bars.record_tick("AAPL", at(10, 0), dollars(102));
+        assert_eq!(completed.len(), 1);
+        assert_eq!((completed[0].start, completed[0].close), (at(9, 30), dollars(101)));
+
+        // The day bar stays open until the close
+        let open = bars.close_all();
+        assert_eq!(open.len(), 2);
+        assert_eq!((open[0].interval, open[0].start, open[0].open), (BarInterval::ThirtyMinutes, at(10, 0), dollars(102)));
+        assert_eq!((open[1].interval, open[1].open, open[1].close), (BarInterval::OneDay, dollars(100), dollars(102)));
+    }
+
+    #[test]
+    fn fills_do_not_open_a_bar() {
+        let mut bars = BarAggregator::new(&[BarInterval::ThirtyMinutes]);
+        bars.record_fill("AAPL", dollars(100), 10);
+        assert!(bars.close_all().is_empty());
+    }
+}
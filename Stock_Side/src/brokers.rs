@@ -1,52 +1,295 @@
 use std::collections::HashMap;
 use crate::stock::Stock; // Use the Stock struct from stock.rs
+use crate::instrument::Instrument;
+use crate::money::Money;
+use crate::fees::{FeeBreakdown, FeeSchedule, Liquidity};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BrokerActivity {
+    /// Unique per activity and kept on redelivery, so it can be applied once.
+    pub activity_id: String,
     pub broker_id: u32,
     pub stock_id: String,
     pub action: String,
     pub quantity: usize,
+    /// Limit price; `None` for a market order. Either way the fill is at
+    /// the market price.
+    #[serde(default)]
+    pub price: Option<Money>,
+}
+
+/// Machine-readable reason attached to every rejected activity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RejectReason {
+    UnknownSymbol,
+    InvalidAction,
+    QuantityBelowMinimum,
+    QuantityAboveMaximum,
+    InvalidLotSize,
+    InvalidTickSize,
+    OutsidePriceBand,
+    /// The limit price is worse than the market: below it for a buy, above
+    /// it for a sell.
+    LimitNotMarketable,
+    InsufficientQuantity,
+}
+
+impl RejectReason {
+    pub fn code(&self) -> &'static str {
+        match self {
+            RejectReason::UnknownSymbol => "UNKNOWN_SYMBOL",
+            RejectReason::InvalidAction => "INVALID_ACTION",
+            RejectReason::QuantityBelowMinimum => "QUANTITY_BELOW_MINIMUM",
+            RejectReason::QuantityAboveMaximum => "QUANTITY_ABOVE_MAXIMUM",
+            RejectReason::InvalidLotSize => "INVALID_LOT_SIZE",
+            RejectReason::InvalidTickSize => "INVALID_TICK_SIZE",
+            RejectReason::OutsidePriceBand => "OUTSIDE_PRICE_BAND",
+            RejectReason::LimitNotMarketable => "LIMIT_NOT_MARKETABLE",
+            RejectReason::InsufficientQuantity => "INSUFFICIENT_QUANTITY",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "UNKNOWN_SYMBOL" => Some(RejectReason::UnknownSymbol),
+            "INVALID_ACTION" => Some(RejectReason::InvalidAction),
+            "QUANTITY_BELOW_MINIMUM" => Some(RejectReason::QuantityBelowMinimum),
+            "QUANTITY_ABOVE_MAXIMUM" => Some(RejectReason::QuantityAboveMaximum),
+            "INVALID_LOT_SIZE" => Some(RejectReason::InvalidLotSize),
+            "INVALID_TICK_SIZE" => Some(RejectReason::InvalidTickSize),
+            "OUTSIDE_PRICE_BAND" => Some(RejectReason::OutsidePriceBand),
+            "LIMIT_NOT_MARKETABLE" => Some(RejectReason::LimitNotMarketable),
+            "INSUFFICIENT_QUANTITY" => Some(RejectReason::InsufficientQuantity),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActivityRejection {
+    /// The rejected activity's id, so the sender can settle it exactly.
+    pub activity_id: String,
+    pub broker_id: u32,
+    pub stock_id: String,
+    pub action: String,
+    pub quantity: usize,
+    pub price: Option<Money>,
+    pub reason: RejectReason,
+}
+
+impl ActivityRejection {
+    fn new(activity: &BrokerActivity, reason: RejectReason) -> Self {
+        ActivityRejection {
+            activity_id: activity.activity_id.clone(),
+            broker_id: activity.broker_id,
+            stock_id: activity.stock_id.clone(),
+            action: activity.action.clone(),
+            quantity: activity.quantity,
+            price: activity.price,
+            reason,
+        }
+    }
+}
+
+/// Execution report for an accepted activity, including the fees charged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fill {
+    /// The filled activity's id, so the sender can settle it exactly.
+    pub activity_id: String,
+    pub broker_id: u32,
+    pub stock_id: String,
+    pub action: String,
+    pub quantity: usize,
+    pub price: Money,
+    pub liquidity: Liquidity,
+    pub fees: FeeBreakdown,
+}
+
+impl Fill {
+    fn new(activity: &BrokerActivity, price: Money, liquidity: Liquidity, fee_schedule: &FeeSchedule) -> Self {
+        Fill {
+            activity_id: activity.activity_id.clone(),
+            broker_id: activity.broker_id,
+            stock_id: activity.stock_id.clone(),
+            action: activity.action.clone(),
+            quantity: activity.quantity,
+            price,
+            liquidity,
+            fees: fee_schedule.calculate(price, activity.quantity, liquidity),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ActivityOutcome {
+    Filled(Fill),
+    Rejected(ActivityRejection),
+}
+
+/// What became of an activity, by id. `Unknown` means Stock_Side never
+/// applied it, so it is safe to send again.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum OrderStatus {
+    Filled { quantity: usize, price: Money },
+    Rejected { reason: RejectReason },
+    Unknown,
+}
+
+impl From<&ActivityOutcome> for OrderStatus {
+    fn from(outcome: &ActivityOutcome) -> Self {
+        match outcome {
+            ActivityOutcome::Filled(fill) => OrderStatus::Filled { quantity: fill.quantity, price: fill.price },
+            ActivityOutcome::Rejected(rejection) => OrderStatus::Rejected { reason: rejection.reason },
+        }
+    }
+}
+
+/// Market orders, and limits priced through the market, take liquidity. A
+/// limit at exactly the market price rests at the touch until it fills, so
+/// it adds liquidity on either side.
+fn liquidity(activity: &BrokerActivity, market_price: Money) -> Liquidity {
+    match activity.price {
+        Some(limit) if limit == market_price => Liquidity::Maker,
+        _ => Liquidity::Taker,
+    }
 }
 
 pub fn process_broker_activities(
     broker_activities: Vec<BrokerActivity>,
     stocks: &mut HashMap<String, Stock>,
-) {
+    instruments: &HashMap<String, Instrument>,
+    fee_schedule: &FeeSchedule,
+) -> Vec<ActivityOutcome> {
+    let mut outcomes = Vec::new();
+
     for activity in broker_activities {
-        if let Some(stock) = stocks.get_mut(&activity.stock_id) {
-            match activity.action.as_str() {
-                "Buy" => {
-                    if stock.available_quantity >= activity.quantity {
-                        stock.available_quantity -= activity.quantity;
-                        println!(
-                            "Broker {} bought {} shares of {}.",
-                            activity.broker_id, activity.quantity, activity.stock_id
-                        );
-                    } else {
-                        println!(
-                            "Broker {} failed to buy shares of {} (not enough available).",
-                            activity.broker_id, activity.stock_id
-                        );
-                    }
-                }
-                "Sell" => {
-                    stock.available_quantity += activity.quantity;
+        let (stock, instrument) = match (
+            stocks.get_mut(&activity.stock_id),
+            instruments.get(&activity.stock_id),
+        ) {
+            (Some(stock), Some(instrument)) => (stock, instrument),
+            _ => {
+                println!(
+                    "Broker {} attempted to trade an unknown stock: {}.",
+                    activity.broker_id, activity.stock_id
+                );
+                outcomes.push(ActivityOutcome::Rejected(ActivityRejection::new(&activity, RejectReason::UnknownSymbol)));
+                continue;
+            }
+        };
+
+        if let Err(reason) = instrument.validate(&activity, stock.price) {
+            println!(
+                "Broker {} {} of {} x{} rejected: {}.",
+                activity.broker_id, activity.action, activity.stock_id, activity.quantity, reason.code()
+            );
+            outcomes.push(ActivityOutcome::Rejected(ActivityRejection::new(&activity, reason)));
+            continue;
+        }
+
+        // A price is a limit: trade at the market if it is at least as good
+        if let Some(limit) = activity.price {
+            let marketable = match activity.action.as_str() {
+                "Buy" => stock.price <= limit,
+                "Sell" => stock.price >= limit,
+                _ => true,
+            };
+            if !marketable {
+                println!(
+                    "Broker {} {} of {} at {} not marketable at {}.",
+                    activity.broker_id, activity.action, activity.stock_id, limit, stock.price
+                );
+                outcomes.push(ActivityOutcome::Rejected(ActivityRejection::new(&activity, RejectReason::LimitNotMarketable)));
+                continue;
+            }
+        }
+
+        let price = stock.price;
+        let liquidity = liquidity(&activity, price);
+        match activity.action.as_str() {
+            "Buy" => {
+                if stock.available_quantity >= activity.quantity {
+                    stock.available_quantity -= activity.quantity;
                     println!(
-                        "Broker {} sold {} shares of {}.",
+                        "Broker {} bought {} shares of {}.",
                         activity.broker_id, activity.quantity, activity.stock_id
                     );
-                }
-                _ => {
-                    println!("Invalid action: {}", activity.action);
+                    outcomes.push(ActivityOutcome::Filled(Fill::new(&activity, price, liquidity, fee_schedule)));
+                } else {
+                    println!(
+                        "Broker {} failed to buy shares of {} (not enough available).",
+                        activity.broker_id, activity.stock_id
+                    );
+                    outcomes.push(ActivityOutcome::Rejected(ActivityRejection::new(&activity, RejectReason::InsufficientQuantity)));
                 }
             }
-        } else {
-            println!(
-                "Broker {} attempted to trade an unknown stock: {}.",
-                activity.broker_id, activity.stock_id
-            );
+            "Sell" => {
+                stock.available_quantity += activity.quantity;
+                println!(
+                    "Broker {} sold {} shares of {}.",
+                    activity.broker_id, activity.quantity, activity.stock_id
+                );
+                outcomes.push(ActivityOutcome::Filled(Fill::new(&activity, price, liquidity, fee_schedule)));
+            }
+            _ => {
+                println!("Invalid action: {}", activity.action);
+                outcomes.push(ActivityOutcome::Rejected(ActivityRejection::new(&activity, RejectReason::InvalidAction)));
+            }
+        }
+    }
+
+    outcomes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instrument::initialize_instruments;
+
+    fn fill(action: &str, limit: Option<Money>) -> Fill {
+        let mut stocks = HashMap::from([(
+            "AAPL".to_string(),
+            Stock { id: "AAPL".to_string(), price: Money::from_cents(5_000), available_quantity: 1_000 },
+        )]);
+        let instruments = initialize_instruments(&stocks, &[]);
+        let activity = BrokerActivity {
+            activity_id: "test-1".to_string(),
+            broker_id: 1,
+            stock_id: "AAPL".to_string(),
+            action: action.to_string(),
+            quantity: 10,
+            price: limit,
+        };
+        match process_broker_activities(vec![activity], &mut stocks, &instruments, &FeeSchedule::default()).pop() {
+            Some(ActivityOutcome::Filled(fill)) => fill,
+            other => panic!("expected a fill, got {:?}", other),
         }
     }
+
+    #[test]
+    fn market_orders_take_liquidity_on_either_side() {
+        for action in ["Buy", "Sell"] {
+            let fill = fill(action, None);
+            assert_eq!(fill.liquidity, Liquidity::Taker, "{}", action);
+            assert!(fill.fees.exchange_fee > Money::ZERO);
+        }
+    }
+
+    #[test]
+    fn limits_at_the_market_add_liquidity_on_either_side() {
+        for action in ["Buy", "Sell"] {
+            let fill = fill(action, Some(Money::from_cents(5_000)));
+            assert_eq!(fill.liquidity, Liquidity::Maker, "{}", action);
+            assert!(fill.fees.exchange_fee < Money::ZERO);
+        }
+    }
+
+    #[test]
+    fn limits_through_the_market_take_liquidity() {
+        assert_eq!(fill("Buy", Some(Money::from_cents(5_010))).liquidity, Liquidity::Taker);
+        assert_eq!(fill("Sell", Some(Money::from_cents(4_990))).liquidity, Liquidity::Taker);
+    }
 }
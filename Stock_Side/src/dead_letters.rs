@@ -0,0 +1,150 @@
+//! `Stock_Side dead-letters list | replay [COUNT]`
+//!
+//! Inspects the dead-letter queue without starting the market. `list` prints
+//! every dead letter with the error and origin from its headers and leaves
+//! the queue untouched; MessagePack payloads are printed as JSON. `replay` republishes the oldest COUNT dead letters
+//! (all by default) to the exchange and routing key they originally came
+//! from, without the dead-letter headers, and removes each one once the
+//! broker confirms the republish.
+
+use std::error::Error;
+use lapin::message::Delivery;
+use lapin::options::{BasicAckOptions, BasicGetOptions, BasicNackOptions, BasicPublishOptions, ConfirmSelectOptions};
+use lapin::types::{AMQPValue, FieldTable};
+use lapin::{Channel, Connection, ConnectionProperties};
+use crate::codec::Encoding;
+use crate::config::AmqpConfig;
+use crate::link::declare_topology;
+use crate::messaging::{
+    DEAD_LETTERED_AT_HEADER, DEAD_LETTERED_BY_HEADER, ERROR_HEADER, ORIGINAL_EXCHANGE_HEADER,
+    ORIGINAL_ROUTING_KEY_HEADER,
+};
+
+pub const COMMAND: &str = "dead-letters";
+
+const USAGE: &str = "usage: Stock_Side dead-letters list | replay [COUNT]";
+const PAYLOAD_PREVIEW_CHARS: usize = 200;
+
+/// Stripped before replaying.
+const DEAD_LETTER_HEADERS: [&str; 5] = [
+    ERROR_HEADER,
+    DEAD_LETTERED_BY_HEADER,
+    DEAD_LETTERED_AT_HEADER,
+    ORIGINAL_EXCHANGE_HEADER,
+    ORIGINAL_ROUTING_KEY_HEADER,
+];
+
+enum Command {
+    List,
+    Replay(usize),
+}
+
+pub async fn run(amqp: &AmqpConfig, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let command = match args {
+        [command] if command == "list" => Command::List,
+        [command] if command == "replay" => Command::Replay(usize::MAX),
+        [command, count] if command == "replay" => Command::Replay(count.parse().map_err(|_| USAGE)?),
+        _ => return Err(USAGE.into()),
+    };
+
+    let connection = Connection::connect(&amqp.uri, ConnectionProperties::default()).await?;
+    let channel = connection.create_channel().await?;
+    channel.confirm_select(ConfirmSelectOptions::default()).await?;
+    declare_topology(&channel, amqp).await?;
+
+    match command {
+        Command::List => list(&channel, &amqp.dead_letter_queue).await?,
+        Command::Replay(count) => replay(&channel, &amqp.dead_letter_queue, count).await?,
+    }
+    connection.close(0, "done").await?;
+    Ok(())
+}
+
+async fn list(channel: &Channel, queue: &str) -> Result<(), Box<dyn Error>> {
+    let mut last_tag = None;
+    let mut count = 0;
+    while let Some(message) = channel.basic_get(queue, BasicGetOptions::default()).await? {
+        count += 1;
+        let delivery = &message.delivery;
+        println!(
+            "#{} from {:?} via exchange {:?}, dead-lettered by {} at {}",
+            count,
+            header(delivery, ORIGINAL_ROUTING_KEY_HEADER).unwrap_or_default(),
+            header(delivery, ORIGINAL_EXCHANGE_HEADER).unwrap_or_default(),
+            header(delivery, DEAD_LETTERED_BY_HEADER).unwrap_or_else(|| "?".to_string()),
+            header(delivery, DEAD_LETTERED_AT_HEADER).unwrap_or_else(|| "?".to_string()),
+        );
+        println!("    error:   {}", header(delivery, ERROR_HEADER).unwrap_or_default());
+        // MessagePack payloads are shown as JSON
+        let payload = match Encoding::of(&delivery.properties) {
+            Ok(Encoding::Msgpack) => match Encoding::Msgpack.decode::<serde_json::Value>(&delivery.data) {
+                Ok(value) => value.to_string(),
+                Err(e) => format!("{} bytes of MessagePack ({})", delivery.data.len(), e),
+            },
+            _ => String::from_utf8_lossy(&delivery.data).into_owned(),
+        };
+        let preview: String = payload.chars().take(PAYLOAD_PREVIEW_CHARS).collect();
+        let ellipsis = if payload.chars().count() > PAYLOAD_PREVIEW_CHARS { "..." } else { "" };
+        println!("    payload: {}{}", preview, ellipsis);
+        last_tag = Some(delivery.delivery_tag);
+    }
+
+    // Put everything back in its original order
+    if let Some(tag) = last_tag {
+        let options = BasicNackOptions { multiple: true, requeue: true };
+        channel.basic_nack(tag, options).await?;
+    }
+    println!("{} dead letters in {}", count, queue);
+    Ok(())
+}
+
+async fn replay(channel: &Channel, queue: &str, count: usize) -> Result<(), Box<dyn Error>> {
+    let mut replayed = 0;
+    let mut skipped = Vec::new();
+    while replayed + skipped.len() < count {
+        let Some(message) = channel.basic_get(queue, BasicGetOptions::default()).await? else {
+            break;
+        };
+        let delivery = message.delivery;
+        let Some(routing_key) = header(&delivery, ORIGINAL_ROUTING_KEY_HEADER) else {
+            skipped.push(delivery.delivery_tag);
+            continue;
+        };
+        let exchange = header(&delivery, ORIGINAL_EXCHANGE_HEADER).unwrap_or_default();
+
+        let headers = delivery.properties.headers().clone().unwrap_or_default();
+        let mut original_headers = FieldTable::default();
+        for (name, value) in headers.inner() {
+            if !DEAD_LETTER_HEADERS.contains(&name.as_str()) {
+                original_headers.insert(name.clone(), value.clone());
+            }
+        }
+        let properties = delivery.properties.clone().with_headers(original_headers);
+
+        let confirmation = channel
+            .basic_publish(&exchange, &routing_key, BasicPublishOptions::default(), &delivery.data, properties)
+            .await?
+            .await?;
+        if confirmation.is_nack() {
+            return Err(format!("broker nacked the replay to {:?}; stopping", routing_key).into());
+        }
+        delivery.ack(BasicAckOptions::default()).await?;
+        println!("Replayed to {:?} via exchange {:?}", routing_key, exchange);
+        replayed += 1;
+    }
+
+    // Dead letters without an origin stay in the queue
+    for tag in &skipped {
+        channel.basic_nack(*tag, BasicNackOptions { multiple: false, requeue: true }).await?;
+    }
+    println!("Replayed {} dead letters, left {} without an origin", replayed, skipped.len());
+    Ok(())
+}
+
+fn header(delivery: &Delivery, name: &str) -> Option<String> {
+    match delivery.properties.headers().as_ref()?.inner().get(name)? {
+        AMQPValue::LongString(value) => Some(value.to_string()),
+        AMQPValue::ShortString(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
@@ -0,0 +1,301 @@
+//! End-of-session export of orders, trades and prices as CSV and Parquet.
+//!
+//! Every table is written twice into `<dir>/stock_side/session_<id>/`, as
+//! `<table>.csv` and `<table>.parquet`, with the columns listed below in that
+//! order. Money is exact: decimal text in CSV and `Decimal128(18, 4)` in
+//! Parquet. Timestamps are RFC 3339 UTC text and `market_time` is `HH:MM`.
+//!
+//! Trading_Side exports its own tables through the same `Table`,
+//! `session_rows` and `write_table`.
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use arrow_array::{ArrayRef, Decimal128Array, Int64Array, RecordBatch, StringArray};
+use arrow_schema::{DataType, Field, Schema};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use rusqlite::types::Type;
+use rusqlite::{params, Connection};
+use rust_decimal::Decimal;
+use crate::store::Store;
+
+pub const DEFAULT_EXPORT_DIR: &str = "exports";
+
+const DECIMAL_PRECISION: u8 = 18;
+const DECIMAL_SCALE: u32 = 4;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ColumnType {
+    Integer,
+    Text,
+    Decimal,
+}
+
+pub struct Column {
+    pub name: &'static str,
+    pub column_type: ColumnType,
+    pub nullable: bool,
+}
+
+pub const fn column(name: &'static str, column_type: ColumnType) -> Column {
+    Column { name, column_type, nullable: false }
+}
+
+pub const fn nullable(name: &'static str, column_type: ColumnType) -> Column {
+    Column { name, column_type, nullable: true }
+}
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Integer(i64),
+    Text(String),
+    Decimal(Decimal),
+    Null,
+}
+
+/// An exported table: its file name, column schema and the query that
+/// selects its rows for one session (bound as `?1`).
+pub struct Table {
+    pub name: &'static str,
+    pub columns: &'static [Column],
+    pub query: &'static str,
+}
+
+const TABLES: &[Table] = &[
+    Table {
+        name: "orders",
+        columns: &[
+            column("session_id", ColumnType::Integer),
+            column("received_at", ColumnType::Text),
+            column("broker_id", ColumnType::Integer),
+            column("stock_id", ColumnType::Text),
+            column("action", ColumnType::Text),
+            column("quantity", ColumnType::Integer),
+            nullable("price", ColumnType::Decimal),
+        ],
+        query: "SELECT session_id, received_at, broker_id, stock_id, action, quantity, price
+                FROM orders WHERE session_id = ?1 ORDER BY id",
+    },
+    Table {
+        name: "trades",
+        columns: &[
+            column("session_id", ColumnType::Integer),
+            column("filled_at", ColumnType::Text),
+            column("broker_id", ColumnType::Integer),
+            column("stock_id", ColumnType::Text),
+            column("action", ColumnType::Text),
+            column("quantity", ColumnType::Integer),
+            column("price", ColumnType::Decimal),
+            column("liquidity", ColumnType::Text),
+            column("commission", ColumnType::Decimal),
+            column("exchange_fee", ColumnType::Decimal),
+            column("total_fee", ColumnType::Decimal),
+        ],
+        query: "SELECT session_id, filled_at, broker_id, stock_id, action, quantity, price,
+                       liquidity, commission, exchange_fee, total_fee
+                FROM fills WHERE session_id = ?1 ORDER BY id",
+    },
+    Table {
+        name: "prices",
+        columns: &[
+            column("session_id", ColumnType::Integer),
+            column("market_time", ColumnType::Text),
+            column("stock_id", ColumnType::Text),
+            column("price", ColumnType::Decimal),
+            column("available_quantity", ColumnType::Integer),
+        ],
+        query: "SELECT session_id, market_time, stock_id, price, available_quantity
+                FROM price_ticks WHERE session_id = ?1 ORDER BY id",
+    },
+];
+
+/// Writes every table for the store's session and returns the directory
+/// the files went to.
+pub fn export_session(store: &Store, dir: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let session_dir = Path::new(dir)
+        .join("stock_side")
+        .join(format!("session_{}", store.session_id()));
+    fs::create_dir_all(&session_dir)?;
+
+    for table in TABLES {
+        let rows = store.session_rows(table.query, table.columns)?;
+        write_table(&session_dir, table, &rows)?;
+    }
+    Ok(session_dir)
+}
+
+/// Runs `query` with `session_id` bound as `?1` and reads each row as
+/// `columns`. Money columns are parsed back from their decimal text.
+pub fn session_rows(
+    conn: &Connection,
+    session_id: i64,
+    query: &str,
+    columns: &[Column],
+) -> rusqlite::Result<Vec<Vec<Value>>> {
+    let mut statement = conn.prepare(query)?;
+    let rows = statement.query_map(params![session_id], |row| {
+        columns
+            .iter()
+            .enumerate()
+            .map(|(index, column)| {
+                let value = match column.column_type {
+                    ColumnType::Integer => row.get::<_, Option<i64>>(index)?.map(Value::Integer),
+                    ColumnType::Text => row.get::<_, Option<String>>(index)?.map(Value::Text),
+                    ColumnType::Decimal => match row.get::<_, Option<String>>(index)? {
+                        Some(text) => Some(Value::Decimal(Decimal::from_str(&text).map_err(|e| {
+                            rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(e))
+                        })?)),
+                        None => None,
+                    },
+                };
+                Ok(value.unwrap_or(Value::Null))
+            })
+            .collect()
+    })?;
+    rows.collect()
+}
+
+/// Writes `rows` of `table` to `<dir>/<table>.csv` and `<dir>/<table>.parquet`.
+pub fn write_table(dir: &Path, table: &Table, rows: &[Vec<Value>]) -> Result<(), Box<dyn Error>> {
+    write_csv(&dir.join(format!("{}.csv", table.name)), table.columns, rows)?;
+    write_parquet(&dir.join(format!("{}.parquet", table.name)), table.columns, rows)
+}
+
+fn write_csv(path: &Path, columns: &[Column], rows: &[Vec<Value>]) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(columns.iter().map(|column| column.name))?;
+    for row in rows {
+        writer.write_record(row.iter().map(|value| match value {
+            Value::Integer(value) => value.to_string(),
+            Value::Text(value) => value.clone(),
+            Value::Decimal(value) => value.to_string(),
+            Value::Null => String::new(),
+        }))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_parquet(path: &Path, columns: &[Column], rows: &[Vec<Value>]) -> Result<(), Box<dyn Error>> {
+    let fields: Vec<Field> = columns
+        .iter()
+        .map(|column| {
+            let data_type = match column.column_type {
+                ColumnType::Integer => DataType::Int64,
+                ColumnType::Text => DataType::Utf8,
+                ColumnType::Decimal => DataType::Decimal128(DECIMAL_PRECISION, DECIMAL_SCALE as i8),
+            };
+            Field::new(column.name, data_type, column.nullable)
+        })
+        .collect();
+    let schema = Arc::new(Schema::new(fields));
+
+    let mut arrays: Vec<ArrayRef> = Vec::with_capacity(columns.len());
+    for (index, column) in columns.iter().enumerate() {
+        let values = rows.iter().map(|row| &row[index]);
+        let array: ArrayRef = match column.column_type {
+            ColumnType::Integer => Arc::new(Int64Array::from_iter(values.map(|value| match value {
+                Value::Integer(value) => Some(*value),
+                _ => None,
+            }))),
+            ColumnType::Text => Arc::new(StringArray::from_iter(values.map(|value| match value {
+                Value::Text(value) => Some(value.as_str()),
+                _ => None,
+            }))),
+            ColumnType::Decimal => Arc::new(
+                Decimal128Array::from_iter(values.map(|value| match value {
+                    Value::Decimal(value) => Some(scaled(*value)),
+                    _ => None,
+                }))
+                .with_precision_and_scale(DECIMAL_PRECISION, DECIMAL_SCALE as i8)?,
+            ),
+        };
+        arrays.push(array);
+    }
+
+    let batch = RecordBatch::try_new(Arc::clone(&schema), arrays)?;
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut writer = ArrowWriter::try_new(File::create(path)?, schema, Some(properties))?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+/// Unscaled integer of `value` at the export scale.
+fn scaled(value: Decimal) -> i128 {
+    let mut value = value.round_dp(DECIMAL_SCALE);
+    value.rescale(DECIMAL_SCALE);
+    value.mantissa()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    const TABLE: Table = Table {
+        name: "trades",
+        columns: &[
+            column("quantity", ColumnType::Integer),
+            column("stock_id", ColumnType::Text),
+            column("price", ColumnType::Decimal),
+            nullable("limit_price", ColumnType::Decimal),
+        ],
+        query: "",
+    };
+
+    #[test]
+    fn writes_parquet_and_csv_that_read_back() {
+        let dir = env::temp_dir().join(format!("stock_side_export_test_{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rows = vec![
+            vec![
+                Value::Integer(10),
+                Value::Text("AAPL".to_string()),
+                Value::Decimal(Decimal::new(15_025, 2)),
+                Value::Decimal(Decimal::new(151, 0)),
+            ],
+            vec![Value::Integer(5), Value::Text("MSFT".to_string()), Value::Decimal(Decimal::new(31_000_123, 5)), Value::Null],
+        ];
+        write_table(&dir, &TABLE, &rows).unwrap();
+
+        let file = File::open(dir.join("trades.parquet")).unwrap();
+        let batches: Vec<RecordBatch> = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let [batch] = batches.as_slice() else {
+            panic!("expected one batch, got {}", batches.len());
+        };
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(batch.schema().field(2).data_type(), &DataType::Decimal128(18, 4));
+        assert!(!batch.schema().field(2).is_nullable() && batch.schema().field(3).is_nullable());
+
+        let quantity = batch.column(0).as_any().downcast_ref::<Int64Array>().unwrap();
+        let symbol = batch.column(1).as_any().downcast_ref::<StringArray>().unwrap();
+        let price = batch.column(2).as_any().downcast_ref::<Decimal128Array>().unwrap();
+        let limit = batch.column(3).as_any().downcast_ref::<Decimal128Array>().unwrap();
+        assert_eq!((quantity.value(0), symbol.value(0)), (10, "AAPL"));
+        assert_eq!((quantity.value(1), symbol.value(1)), (5, "MSFT"));
+        // Four decimal places, the fifth rounded
+        assert_eq!((price.value(0), price.value(1)), (1_502_500, 3_100_012));
+        assert_eq!(limit.value(0), 1_510_000);
+        assert!(limit.is_null(1));
+
+        let csv = fs::read_to_string(dir.join("trades.csv")).unwrap();
+        assert_eq!(csv, "quantity,stock_id,price,limit_price\n10,AAPL,150.25,151\n5,MSFT,310.00123,\n");
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,66 @@
+//! In-process fan-out of what the market does, for the WebSocket server:
+//! stock changes each cycle, every trade from any order gateway, and
+//! completed bars. Subscribers that fall too far behind miss events rather
+//! than hold up the market.
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+use crate::bars::Bar;
+use crate::brokers::Fill;
+use crate::money::Money;
+use crate::stock::Stock;
+
+const FEED_CAPACITY: usize = 4096;
+
+/// A trade without the broker behind it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Trade {
+    pub symbol: String,
+    pub action: String,
+    pub quantity: usize,
+    pub price: Money,
+}
+
+impl From<&Fill> for Trade {
+    fn from(fill: &Fill) -> Self {
+        Trade {
+            symbol: fill.stock_id.clone(),
+            action: fill.action.clone(),
+            quantity: fill.quantity,
+            price: fill.price,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FeedEvent {
+    Stock(Stock),
+    Trade(Trade),
+    Bar(Bar),
+}
+
+pub struct Feed {
+    sender: broadcast::Sender<FeedEvent>,
+}
+
+impl Default for Feed {
+    fn default() -> Self {
+        Feed { sender: broadcast::Sender::new(FEED_CAPACITY) }
+    }
+}
+
+impl Feed {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends `event` to every current subscriber; nobody listening is fine.
+    pub fn publish(&self, event: FeedEvent) {
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<FeedEvent> {
+        self.sender.subscribe()
+    }
+}
@@ -0,0 +1,111 @@
+use std::fmt;
+use std::str::FromStr;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use crate::money::Money;
+
+/// Whether a fill added liquidity (maker, earns the rebate) or removed it
+/// (taker, pays the fee). Decided by the order, not by its side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Liquidity {
+    Maker,
+    Taker,
+}
+
+impl fmt::Display for Liquidity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Liquidity::Maker => "Maker",
+            Liquidity::Taker => "Taker",
+        })
+    }
+}
+
+impl FromStr for Liquidity {
+    type Err = String;
+
+    fn from_str(label: &str) -> Result<Self, Self::Err> {
+        match label {
+            "Maker" => Ok(Liquidity::Maker),
+            "Taker" => Ok(Liquidity::Taker),
+            _ => Err(format!("unknown liquidity {}; expected Maker or Taker", label)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct FeeBreakdown {
+    pub commission: Money,
+    /// Taker fee, or a negative amount for a maker rebate.
+    pub exchange_fee: Money,
+    pub total: Money,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeSchedule {
+    pub commission_per_share: Money,
+    /// Commission as a fraction of notional, charged on top of the per-share rate.
+    pub commission_rate: Decimal,
+    pub minimum_commission: Money,
+    pub taker_fee_per_share: Money,
+    pub maker_rebate_per_share: Money,
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        FeeSchedule {
+            commission_per_share: Money::new(Decimal::new(5, 3)),
+            commission_rate: Decimal::new(1, 4),
+            minimum_commission: Money::from_cents(100),
+            taker_fee_per_share: Money::new(Decimal::new(3, 3)),
+            maker_rebate_per_share: Money::new(Decimal::new(2, 3)),
+        }
+    }
+}
+
+impl FeeSchedule {
+    pub fn calculate(&self, price: Money, quantity: usize, liquidity: Liquidity) -> FeeBreakdown {
+        let notional = price.times(quantity);
+        let commission = (self.commission_per_share.times(quantity) + notional.apply_rate(self.commission_rate))
+            .round_cents()
+            .max(self.minimum_commission);
+        let exchange_fee = match liquidity {
+            Liquidity::Taker => self.taker_fee_per_share.times(quantity),
+            Liquidity::Maker => -self.maker_rebate_per_share.times(quantity),
+        }
+        .round_cents();
+
+        FeeBreakdown {
+            commission,
+            exchange_fee,
+            total: commission + exchange_fee,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takers_pay_the_fee_and_makers_earn_the_rebate() {
+        let schedule = FeeSchedule::default();
+        let price = Money::from_cents(5_000);
+
+        let taker = schedule.calculate(price, 100, Liquidity::Taker);
+        assert_eq!(taker.commission, Money::from_cents(100));
+        assert_eq!(taker.exchange_fee, Money::from_cents(30));
+        assert_eq!(taker.total, Money::from_cents(130));
+
+        let maker = schedule.calculate(price, 100, Liquidity::Maker);
+        assert_eq!(maker.commission, Money::from_cents(100));
+        assert_eq!(maker.exchange_fee, Money::from_cents(-20));
+        assert_eq!(maker.total, Money::from_cents(80));
+    }
+
+    #[test]
+    fn small_tickets_pay_the_minimum_commission() {
+        let fees = FeeSchedule::default().calculate(Money::from_cents(1_000), 1, Liquidity::Taker);
+        assert_eq!(fees.commission, FeeSchedule::default().minimum_commission);
+    }
+}
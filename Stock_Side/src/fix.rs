@@ -0,0 +1,826 @@
+//! FIX 4.4 order entry.
+//!
+//! A TCP acceptor for external order management systems. A client logs on
+//! with a SenderCompID listed in `[fix]` sessions, which fixes the broker ID
+//! its orders trade for, and our CompID as TargetCompID. After that:
+//!
+//! - Heartbeat and TestRequest keep the session alive at the client's
+//!   HeartBtInt; a client silent for twice that is disconnected.
+//! - NewOrderSingle (market or limit, Side 1 or 2) becomes a `BrokerActivity`
+//!   with the id `fix:<SenderCompID>:<ClOrdID>` and goes through the same
+//!   `OrderEntry` as RabbitMQ activities. The outcome comes back as one
+//!   ExecutionReport: Trade/Filled with the fees as Commission, or
+//!   Rejected with the reject code as Text. Resending a ClOrdID is not
+//!   applied again; it gets the original outcome's report with PossResend
+//!   set, or a duplicate order reject if that outcome cannot be found.
+//! - OrderCancelRequest is answered with OrderCancelReject, since orders are
+//!   filled or rejected on arrival and are never left open.
+//! - Logout ends the session.
+//!
+//! A SenderCompID can be logged on once at a time; a second Logon while its
+//! session is open is refused. ExecIDs are numbered in sequence after the
+//! time the acceptor started, so each report has its own.
+//!
+//! Sequence numbers live only as long as the connection: we start at 1 on
+//! every logon, accept the client's starting number, and do not resend. A
+//! gap in the client's numbers is logged and skipped.
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use chrono::Utc;
+use log::{error, info, warn};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep, sleep_until, timeout, Duration, Instant};
+use crate::brokers::{ActivityOutcome, ActivityRejection, BrokerActivity, Fill, RejectReason};
+use crate::config::FixConfig;
+use crate::money::Money;
+use crate::order_entry::{OrderEntry, Submission};
+
+const SOH: u8 = 0x01;
+const BEGIN_STRING: &str = "FIX.4.4";
+const MAX_MESSAGE_BYTES: usize = 64 * 1024;
+/// How long a new connection has to send its Logon.
+const LOGON_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest HeartBtInt we accept, which keeps the session timers in range.
+const MAX_HEARTBEAT_SECS: u64 = 3600;
+
+// Tags
+const AVG_PX: u32 = 6;
+const BEGIN_STRING_TAG: u32 = 8;
+const BODY_LENGTH: u32 = 9;
+const CL_ORD_ID: u32 = 11;
+const COMMISSION: u32 = 12;
+const COMM_TYPE: u32 = 13;
+const CUM_QTY: u32 = 14;
+const EXEC_ID: u32 = 17;
+const LAST_PX: u32 = 31;
+const LAST_QTY: u32 = 32;
+const MSG_SEQ_NUM: u32 = 34;
+const MSG_TYPE: u32 = 35;
+const ORDER_ID: u32 = 37;
+const ORDER_QTY: u32 = 38;
+const ORD_STATUS: u32 = 39;
+const ORD_TYPE: u32 = 40;
+const ORIG_CL_ORD_ID: u32 = 41;
+const POSS_DUP_FLAG: u32 = 43;
+const PRICE: u32 = 44;
+const REF_SEQ_NUM: u32 = 45;
+const SENDER_COMP_ID: u32 = 49;
+const SENDING_TIME: u32 = 52;
+const SIDE: u32 = 54;
+const SYMBOL: u32 = 55;
+const TARGET_COMP_ID: u32 = 56;
+const TEXT: u32 = 58;
+const TRANSACT_TIME: u32 = 60;
+const POSS_RESEND: u32 = 97;
+const ENCRYPT_METHOD: u32 = 98;
+const CXL_REJ_REASON: u32 = 102;
+const ORD_REJ_REASON: u32 = 103;
+const HEART_BT_INT: u32 = 108;
+const TEST_REQ_ID: u32 = 112;
+const RESET_SEQ_NUM_FLAG: u32 = 141;
+const EXEC_TYPE: u32 = 150;
+const LEAVES_QTY: u32 = 151;
+const REF_MSG_TYPE: u32 = 372;
+const SESSION_REJECT_REASON: u32 = 373;
+const CXL_REJ_RESPONSE_TO: u32 = 434;
+
+// MsgType values
+const HEARTBEAT: &str = "0";
+const TEST_REQUEST: &str = "1";
+const REJECT: &str = "3";
+const LOGOUT: &str = "5";
+const EXECUTION_REPORT: &str = "8";
+const ORDER_CANCEL_REJECT: &str = "9";
+const LOGON: &str = "A";
+const NEW_ORDER_SINGLE: &str = "D";
+const ORDER_CANCEL_REQUEST: &str = "F";
+
+// OrdStatus and ExecType values
+const STATUS_FILLED: &str = "2";
+const STATUS_REJECTED: &str = "8";
+const EXEC_TYPE_TRADE: &str = "F";
+
+/// One FIX message as tag/value pairs in wire order, without the standard
+/// header's BeginString and BodyLength or the trailer.
+#[derive(Debug, Clone)]
+struct Message {
+    fields: Vec<(u32, String)>,
+}
+
+impl Message {
+    fn new(msg_type: &str) -> Self {
+        Message { fields: vec![(MSG_TYPE, msg_type.to_string())] }
+    }
+
+    fn with(mut self, tag: u32, value: impl ToString) -> Self {
+        self.fields.push((tag, value.to_string()));
+        self
+    }
+
+    fn get(&self, tag: u32) -> Option<&str> {
+        self.fields.iter().find(|(t, _)| *t == tag).map(|(_, value)| value.as_str())
+    }
+
+    fn msg_type(&self) -> &str {
+        self.get(MSG_TYPE).unwrap_or_default()
+    }
+
+    /// Frames the message with our header, its body length and checksum.
+    fn encode(&self, sender_comp_id: &str, target_comp_id: &str, seq_num: u64) -> Vec<u8> {
+        let sending_time = Utc::now().format("%Y%m%d-%H:%M:%S%.3f").to_string();
+        let mut body = Vec::new();
+        let mut push = |tag: u32, value: &str| {
+            body.extend_from_slice(format!("{}={}", tag, value).as_bytes());
+            body.push(SOH);
+        };
+        push(MSG_TYPE, self.msg_type());
+        push(SENDER_COMP_ID, sender_comp_id);
+        push(TARGET_COMP_ID, target_comp_id);
+        push(MSG_SEQ_NUM, &seq_num.to_string());
+        push(SENDING_TIME, &sending_time);
+        for (tag, value) in self.fields.iter().filter(|(tag, _)| *tag != MSG_TYPE) {
+            push(*tag, value);
+        }
+
+        let mut frame = format!("{}={}\x01{}={}\x01", BEGIN_STRING_TAG, BEGIN_STRING, BODY_LENGTH, body.len()).into_bytes();
+        frame.extend_from_slice(&body);
+        let checksum = checksum(&frame);
+        frame.extend_from_slice(format!("10={:03}\x01", checksum).as_bytes());
+        frame
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+enum Frame {
+    /// More bytes are needed.
+    Incomplete,
+    Message(Message),
+    /// A well-framed message with a bad checksum or field, already dropped
+    /// from the buffer.
+    Garbled(String),
+}
+
+/// Takes one message off the front of `buffer`. Errors mean the stream can
+/// no longer be framed.
+fn take_message(buffer: &mut Vec<u8>) -> Result<Frame, String> {
+    let Some(first) = buffer.iter().position(|&byte| byte == SOH) else {
+        return if buffer.len() > 32 { Err("missing BeginString".to_string()) } else { Ok(Frame::Incomplete) };
+    };
+    if buffer[..first] != *format!("{}={}", BEGIN_STRING_TAG, BEGIN_STRING).as_bytes() {
+        return Err(format!("expected BeginString {}", BEGIN_STRING));
+    }
+    let Some(second) = buffer[first + 1..].iter().position(|&byte| byte == SOH).map(|i| first + 1 + i) else {
+        return if buffer.len() > first + 32 { Err("missing BodyLength".to_string()) } else { Ok(Frame::Incomplete) };
+    };
+    let body_length = std::str::from_utf8(&buffer[first + 1..second])
+        .ok()
+        .and_then(|field| field.strip_prefix("9="))
+        .and_then(|length| length.parse::<usize>().ok())
+        .filter(|&length| length <= MAX_MESSAGE_BYTES)
+        .ok_or("invalid BodyLength")?;
+
+    let body_end = second + 1 + body_length;
+    let frame_end = body_end + "10=000\x01".len();
+    if buffer.len() < frame_end {
+        return Ok(Frame::Incomplete);
+    }
+    let trailer = &buffer[body_end..frame_end];
+    if !trailer.starts_with(b"10=") || trailer[trailer.len() - 1] != SOH {
+        return Err("BodyLength does not match the message".to_string());
+    }
+
+    let frame: Vec<u8> = buffer.drain(..frame_end).collect();
+    let expected = format!("{:03}", checksum(&frame[..body_end]));
+    if frame[body_end + 3..frame_end - 1] != *expected.as_bytes() {
+        return Ok(Frame::Garbled("bad CheckSum".to_string()));
+    }
+
+    let mut fields = Vec::new();
+    for field in frame[second + 1..body_end].split(|&byte| byte == SOH).filter(|field| !field.is_empty()) {
+        let field = String::from_utf8_lossy(field);
+        let Some((tag, value)) = field.split_once('=') else {
+            return Ok(Frame::Garbled(format!("malformed field {:?}", field)));
+        };
+        let Ok(tag) = tag.parse() else {
+            return Ok(Frame::Garbled(format!("malformed tag {:?}", tag)));
+        };
+        fields.push((tag, value.to_string()));
+    }
+    if fields.first().map(|(tag, _)| *tag) != Some(MSG_TYPE) {
+        return Ok(Frame::Garbled("MsgType must follow BodyLength".to_string()));
+    }
+    Ok(Frame::Message(Message { fields }))
+}
+
+/// Session settings taken from an acceptable Logon.
+#[derive(Debug, PartialEq)]
+struct AcceptedLogon {
+    broker_id: u32,
+    heartbeat: Duration,
+    next_incoming: u64,
+}
+
+/// Checks a client's first message. The error is the reason sent back in
+/// the Logout.
+fn check_logon(logon: &Message, config: &FixConfig) -> Result<AcceptedLogon, String> {
+    if logon.msg_type() != LOGON {
+        return Err("first message must be Logon".to_string());
+    }
+    if logon.get(TARGET_COMP_ID) != Some(config.comp_id.as_str()) {
+        return Err(format!("TargetCompID must be {}", config.comp_id));
+    }
+    if logon.get(ENCRYPT_METHOD).is_some_and(|method| method != "0") {
+        return Err("EncryptMethod must be 0".to_string());
+    }
+    let heartbeat = logon
+        .get(HEART_BT_INT)
+        .and_then(|secs| secs.parse::<u64>().ok())
+        .filter(|secs| (1..=MAX_HEARTBEAT_SECS).contains(secs))
+        .ok_or_else(|| format!("HeartBtInt must be between 1 and {} seconds", MAX_HEARTBEAT_SECS))?;
+    let next_incoming = match logon.get(MSG_SEQ_NUM) {
+        Some(seq) => seq.parse::<u64>().ok().and_then(|seq| seq.checked_add(1)).ok_or("MsgSeqNum out of range")?,
+        None => 2,
+    };
+    let sender_comp_id = logon.get(SENDER_COMP_ID).unwrap_or_default();
+    let broker_id = config
+        .broker_id(sender_comp_id)
+        .ok_or_else(|| format!("unknown SenderCompID {:?}", sender_comp_id))?;
+    Ok(AcceptedLogon {
+        broker_id,
+        heartbeat: Duration::from_secs(heartbeat),
+        next_incoming,
+    })
+}
+
+/// State shared by every session of one acceptor.
+struct Acceptor {
+    /// SenderCompIDs with a session logged on.
+    logged_on: Mutex<HashSet<String>>,
+    /// Start time prefixed to ExecIDs, so a restart does not repeat them.
+    started: String,
+    next_exec_id: AtomicU64,
+}
+
+impl Acceptor {
+    fn new() -> Self {
+        Acceptor {
+            logged_on: Mutex::new(HashSet::new()),
+            started: Utc::now().format("%Y%m%d%H%M%S%3f").to_string(),
+            next_exec_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Marks `comp_id` logged on. Returns false if it already is.
+    fn log_on(&self, comp_id: &str) -> bool {
+        self.logged_on.lock().unwrap().insert(comp_id.to_string())
+    }
+
+    fn log_off(&self, comp_id: &str) {
+        self.logged_on.lock().unwrap().remove(comp_id);
+    }
+
+    fn exec_id(&self) -> String {
+        format!("{}-{}", self.started, self.next_exec_id.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Accepts FIX sessions until the process exits.
+pub async fn serve(listener: TcpListener, config: FixConfig, order_entry: Arc<OrderEntry>) {
+    let config = Arc::new(config);
+    let acceptor = Arc::new(Acceptor::new());
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                error!("FIX accept failed: {:?}", e);
+                sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let config = Arc::clone(&config);
+        let order_entry = Arc::clone(&order_entry);
+        let acceptor = Arc::clone(&acceptor);
+        tokio::spawn(async move {
+            match Session::logon(stream, &config, &order_entry, &acceptor).await {
+                Ok(Some(mut session)) => {
+                    if let Err(e) = session.run().await {
+                        warn!("FIX session {} from {} failed: {}", session.client_comp_id, peer, e);
+                    }
+                    info!("FIX session {} from {} closed", session.client_comp_id, peer);
+                }
+                Ok(None) => info!("FIX logon from {} refused", peer),
+                Err(e) => warn!("FIX logon from {} failed: {}", peer, e),
+            }
+        });
+    }
+}
+
+/// What a session remembers about an order, for cancel requests.
+struct OrderRecord {
+    order_id: String,
+    status: &'static str,
+}
+
+struct Session<'a> {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    config: &'a FixConfig,
+    order_entry: &'a OrderEntry,
+    acceptor: &'a Acceptor,
+    client_comp_id: String,
+    /// Whether `client_comp_id` is marked logged on with the acceptor.
+    logged_on: bool,
+    broker_id: u32,
+    heartbeat: Duration,
+    next_outgoing: u64,
+    next_incoming: u64,
+    last_sent: Instant,
+    last_received: Instant,
+    test_request_sent: bool,
+    orders: HashMap<String, OrderRecord>,
+}
+
+impl<'a> Session<'a> {
+    /// Waits for a Logon and answers it. Returns `None` if the logon was
+    /// refused; the client has been told why.
+    async fn logon(
+        stream: TcpStream,
+        config: &'a FixConfig,
+        order_entry: &'a OrderEntry,
+        acceptor: &'a Acceptor,
+    ) -> io::Result<Option<Self>> {
+        let now = Instant::now();
+        let mut session = Session {
+            stream,
+            buffer: Vec::new(),
+            config,
+            order_entry,
+            acceptor,
+            client_comp_id: String::new(),
+            logged_on: false,
+            broker_id: 0,
+            heartbeat: Duration::ZERO,
+            next_outgoing: 1,
+            next_incoming: 1,
+            last_sent: now,
+            last_received: now,
+            test_request_sent: false,
+            orders: HashMap::new(),
+        };
+        let logon = match timeout(LOGON_TIMEOUT, session.read_message()).await {
+            Ok(Ok(Some(message))) => message,
+            Ok(Ok(None)) => return Ok(None),
+            Ok(Err(e)) => return Err(e),
+            Err(_) => return Err(io::Error::new(io::ErrorKind::TimedOut, "no Logon received")),
+        };
+        session.client_comp_id = logon.get(SENDER_COMP_ID).unwrap_or_default().to_string();
+
+        let checked = check_logon(&logon, config).and_then(|accepted| {
+            if acceptor.log_on(&session.client_comp_id) {
+                Ok(accepted)
+            } else {
+                Err("session already logged on".to_string())
+            }
+        });
+        let accepted = match checked {
+            Ok(accepted) => accepted,
+            Err(reason) => {
+                warn!("Refusing FIX logon from {:?}: {}", session.client_comp_id, reason);
+                session.send(Message::new(LOGOUT).with(TEXT, reason)).await?;
+                return Ok(None);
+            }
+        };
+        session.logged_on = true;
+        session.broker_id = accepted.broker_id;
+        session.heartbeat = accepted.heartbeat;
+        session.next_incoming = accepted.next_incoming;
+        let mut reply = Message::new(LOGON).with(ENCRYPT_METHOD, 0).with(HEART_BT_INT, session.heartbeat.as_secs());
+        if logon.get(RESET_SEQ_NUM_FLAG) == Some("Y") {
+            reply = reply.with(RESET_SEQ_NUM_FLAG, "Y");
+        }
+        session.send(reply).await?;
+        info!(
+            "FIX session {} logged on for broker {} (heartbeat {}s)",
+            session.client_comp_id,
+            session.broker_id,
+            session.heartbeat.as_secs()
+        );
+        Ok(Some(session))
+    }
+
+    async fn run(&mut self) -> io::Result<()> {
+        loop {
+            let deadline = if self.test_request_sent {
+                self.last_received + self.heartbeat * 2
+            } else {
+                (self.last_sent + self.heartbeat).min(self.last_received + self.heartbeat + self.heartbeat / 5)
+            };
+            let message = tokio::select! {
+                message = self.read_message() => message?,
+                _ = sleep_until(deadline) => {
+                    self.on_timer().await?;
+                    continue;
+                }
+            };
+            let Some(message) = message else {
+                return Ok(());
+            };
+            if !self.accept(&message).await? {
+                return Ok(());
+            }
+
+            match message.msg_type() {
+                HEARTBEAT => {}
+                TEST_REQUEST => {
+                    let test_req_id = message.get(TEST_REQ_ID).unwrap_or_default().to_string();
+                    self.send(Message::new(HEARTBEAT).with(TEST_REQ_ID, test_req_id)).await?;
+                }
+                LOGOUT => {
+                    self.send(Message::new(LOGOUT)).await?;
+                    return Ok(());
+                }
+                NEW_ORDER_SINGLE => self.new_order(&message).await?,
+                ORDER_CANCEL_REQUEST => self.cancel_order(&message).await?,
+                other => {
+                    let reject = Message::new(REJECT)
+                        .with(REF_SEQ_NUM, message.get(MSG_SEQ_NUM).unwrap_or_default())
+                        .with(REF_MSG_TYPE, other)
+                        .with(SESSION_REJECT_REASON, 11)
+                        .with(TEXT, "unsupported MsgType");
+                    self.send(reject).await?;
+                }
+            }
+        }
+    }
+
+    /// Sends heartbeats, tests a quiet client, and gives up on a silent one.
+    async fn on_timer(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        if self.test_request_sent && now >= self.last_received + self.heartbeat * 2 {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "no reply to TestRequest"));
+        }
+        if !self.test_request_sent && now >= self.last_received + self.heartbeat + self.heartbeat / 5 {
+            let test_req_id = format!("TEST{}", self.next_outgoing);
+            self.send(Message::new(TEST_REQUEST).with(TEST_REQ_ID, test_req_id)).await?;
+            self.test_request_sent = true;
+        } else if now >= self.last_sent + self.heartbeat {
+            self.send(Message::new(HEARTBEAT)).await?;
+        }
+        Ok(())
+    }
+
+    /// Checks the header of a message from a logged-on client. Returns false
+    /// if the session has to end.
+    async fn accept(&mut self, message: &Message) -> io::Result<bool> {
+        self.last_received = Instant::now();
+        self.test_request_sent = false;
+
+        if message.get(SENDER_COMP_ID) != Some(self.client_comp_id.as_str())
+            || message.get(TARGET_COMP_ID) != Some(self.config.comp_id.as_str())
+        {
+            self.send(Message::new(LOGOUT).with(TEXT, "CompID mismatch")).await?;
+            return Ok(false);
+        }
+        let Some(seq_num) = message.get(MSG_SEQ_NUM).and_then(|seq| seq.parse::<u64>().ok()) else {
+            self.send(Message::new(LOGOUT).with(TEXT, "missing MsgSeqNum")).await?;
+            return Ok(false);
+        };
+        if seq_num < self.next_incoming {
+            if message.get(POSS_DUP_FLAG) == Some("Y") {
+                return Ok(true);
+            }
+            let text = format!("MsgSeqNum too low, expecting {} but received {}", self.next_incoming, seq_num);
+            self.send(Message::new(LOGOUT).with(TEXT, text)).await?;
+            return Ok(false);
+        }
+        if seq_num > self.next_incoming {
+            warn!(
+                "FIX session {} skipped from MsgSeqNum {} to {}",
+                self.client_comp_id, self.next_incoming, seq_num
+            );
+        }
+        let Some(next_incoming) = seq_num.checked_add(1) else {
+            self.send(Message::new(LOGOUT).with(TEXT, "MsgSeqNum out of range")).await?;
+            return Ok(false);
+        };
+        self.next_incoming = next_incoming;
+        Ok(true)
+    }
+
+    async fn new_order(&mut self, message: &Message) -> io::Result<()> {
+        let cl_ord_id = message.get(CL_ORD_ID).unwrap_or_default().to_string();
+        let activity = match self.parse_order(message) {
+            Ok(activity) => activity,
+            Err(text) => {
+                let report = order_report(message, "NONE", &self.acceptor.exec_id(), STATUS_REJECTED)
+                    .with(ORD_REJ_REASON, 99)
+                    .with(TEXT, text);
+                return self.send(report).await;
+            }
+        };
+
+        let order_id = activity.activity_id.clone();
+        let (outcomes, resent) = match self.order_entry.submit(activity).await {
+            Submission::Applied(outcomes) => (outcomes, false),
+            Submission::Duplicate(Some(outcome)) => (vec![outcome], true),
+            Submission::Duplicate(None) => {
+                let report = order_report(message, &order_id, &self.acceptor.exec_id(), STATUS_REJECTED)
+                    .with(ORD_REJ_REASON, 6)
+                    .with(TEXT, "duplicate ClOrdID");
+                return self.send(report).await;
+            }
+        };
+
+        for outcome in outcomes {
+            let exec_id = self.acceptor.exec_id();
+            let (mut report, status) = match outcome {
+                ActivityOutcome::Filled(fill) => (fill_report(message, &order_id, &exec_id, &fill), STATUS_FILLED),
+                ActivityOutcome::Rejected(rejection) => {
+                    (reject_report(message, &order_id, &exec_id, &rejection), STATUS_REJECTED)
+                }
+            };
+            if resent {
+                report = report.with(POSS_RESEND, "Y");
+            }
+            self.orders.insert(cl_ord_id.clone(), OrderRecord { order_id: order_id.clone(), status });
+            self.send(report).await?;
+        }
+        info!("FIX order {} from {} processed", cl_ord_id, self.client_comp_id);
+        Ok(())
+    }
+
+    /// Translates a NewOrderSingle into the activity it stands for.
+    fn parse_order(&self, message: &Message) -> Result<BrokerActivity, String> {
+        let cl_ord_id = message.get(CL_ORD_ID).filter(|id| !id.is_empty()).ok_or("missing ClOrdID")?;
+        let symbol = message.get(SYMBOL).filter(|symbol| !symbol.is_empty()).ok_or("missing Symbol")?;
+        let action = match message.get(SIDE) {
+            Some("1") => "Buy",
+            Some("2") => "Sell",
+            _ => return Err("Side must be 1 (buy) or 2 (sell)".to_string()),
+        };
+        let quantity = message
+            .get(ORDER_QTY)
+            .and_then(|qty| Decimal::from_str(qty).ok())
+            .filter(|qty| qty.fract().is_zero() && *qty > Decimal::ZERO)
+            .and_then(|qty| qty.to_usize())
+            .ok_or("OrderQty must be a positive whole number")?;
+        let price = match message.get(ORD_TYPE) {
+            Some("1") => None,
+            Some("2") => Some(
+                message
+                    .get(PRICE)
+                    .and_then(|price| Decimal::from_str(price).ok())
+                    .filter(|price| *price > Decimal::ZERO)
+                    .map(Money::new)
+                    .ok_or("limit orders need a positive Price")?,
+            ),
+            _ => return Err("OrdType must be 1 (market) or 2 (limit)".to_string()),
+        };
+
+        Ok(BrokerActivity {
+            activity_id: format!("fix:{}:{}", self.client_comp_id, cl_ord_id),
+            broker_id: self.broker_id,
+            stock_id: symbol.to_string(),
+            action: action.to_string(),
+            quantity,
+            price,
+        })
+    }
+
+    async fn cancel_order(&mut self, message: &Message) -> io::Result<()> {
+        let orig_cl_ord_id = message.get(ORIG_CL_ORD_ID).unwrap_or_default();
+        let (order_id, status, reason, text) = match self.orders.get(orig_cl_ord_id) {
+            Some(order) => (order.order_id.clone(), order.status, 0, "order is no longer open"),
+            None => ("NONE".to_string(), STATUS_REJECTED, 1, "unknown order"),
+        };
+        let reject = Message::new(ORDER_CANCEL_REJECT)
+            .with(ORDER_ID, order_id)
+            .with(CL_ORD_ID, message.get(CL_ORD_ID).unwrap_or_default())
+            .with(ORIG_CL_ORD_ID, orig_cl_ord_id)
+            .with(ORD_STATUS, status)
+            .with(CXL_REJ_RESPONSE_TO, 1)
+            .with(CXL_REJ_REASON, reason)
+            .with(TEXT, text);
+        self.send(reject).await
+    }
+
+    /// Reads the next message, skipping garbled ones. `None` means the client
+    /// closed the connection.
+    async fn read_message(&mut self) -> io::Result<Option<Message>> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            match take_message(&mut self.buffer).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))? {
+                Frame::Message(message) => return Ok(Some(message)),
+                Frame::Garbled(reason) => {
+                    warn!("Dropped garbled FIX message from {:?}: {}", self.client_comp_id, reason);
+                    continue;
+                }
+                Frame::Incomplete => {}
+            }
+            let read = self.stream.read(&mut chunk).await?;
+            if read == 0 {
+                return Ok(None);
+            }
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+
+    async fn send(&mut self, message: Message) -> io::Result<()> {
+        let frame = message.encode(&self.config.comp_id, &self.client_comp_id, self.next_outgoing);
+        self.stream.write_all(&frame).await?;
+        self.next_outgoing += 1;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+}
+
+impl Drop for Session<'_> {
+    fn drop(&mut self) {
+        if self.logged_on {
+            self.acceptor.log_off(&self.client_comp_id);
+        }
+    }
+}
+
+/// An ExecutionReport for the order in `request`, with nothing executed.
+fn order_report(request: &Message, order_id: &str, exec_id: &str, status: &str) -> Message {
+    Message::new(EXECUTION_REPORT)
+        .with(ORDER_ID, order_id)
+        .with(CL_ORD_ID, request.get(CL_ORD_ID).unwrap_or_default())
+        .with(EXEC_ID, exec_id)
+        .with(EXEC_TYPE, status)
+        .with(ORD_STATUS, status)
+        .with(SYMBOL, request.get(SYMBOL).unwrap_or_default())
+        .with(SIDE, request.get(SIDE).unwrap_or_default())
+        .with(ORDER_QTY, request.get(ORDER_QTY).unwrap_or_default())
+        .with(LEAVES_QTY, 0)
+        .with(CUM_QTY, 0)
+        .with(AVG_PX, 0)
+        .with(TRANSACT_TIME, Utc::now().format("%Y%m%d-%H:%M:%S%.3f"))
+}
+
+fn fill_report(request: &Message, order_id: &str, exec_id: &str, fill: &Fill) -> Message {
+    let mut report = order_report(request, order_id, exec_id, STATUS_FILLED);
+    for (tag, value) in report.fields.iter_mut() {
+        match *tag {
+            EXEC_TYPE => *value = EXEC_TYPE_TRADE.to_string(),
+            CUM_QTY => *value = fill.quantity.to_string(),
+            AVG_PX => *value = fill.price.to_string(),
+            _ => {}
+        }
+    }
+    report
+        .with(LAST_QTY, fill.quantity)
+        .with(LAST_PX, fill.price)
+        .with(COMMISSION, fill.fees.total)
+        // Absolute amount
+        .with(COMM_TYPE, 3)
+}
+
+fn reject_report(request: &Message, order_id: &str, exec_id: &str, rejection: &ActivityRejection) -> Message {
+    let reason = match rejection.reason {
+        RejectReason::UnknownSymbol => 1,
+        RejectReason::QuantityAboveMaximum => 3,
+        _ => 99,
+    };
+    order_report(request, order_id, exec_id, STATUS_REJECTED)
+        .with(ORD_REJ_REASON, reason)
+        .with(TEXT, rejection.reason.code())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FixSessionConfig;
+
+    fn config() -> FixConfig {
+        FixConfig {
+            sessions: vec![FixSessionConfig { sender_comp_id: "CLIENT".to_string(), broker_id: 7 }],
+            ..FixConfig::default()
+        }
+    }
+
+    fn logon() -> Message {
+        Message::new(LOGON)
+            .with(SENDER_COMP_ID, "CLIENT")
+            .with(TARGET_COMP_ID, "STOCK_SIDE")
+            .with(MSG_SEQ_NUM, 1)
+            .with(ENCRYPT_METHOD, 0)
+            .with(HEART_BT_INT, 30)
+    }
+
+    fn frame() -> Vec<u8> {
+        Message::new(HEARTBEAT).encode("CLIENT", "STOCK_SIDE", 1)
+    }
+
+    #[test]
+    fn takes_a_message_and_leaves_the_next_in_the_buffer() {
+        let mut buffer = [frame(), frame()].concat();
+        let Ok(Frame::Message(message)) = take_message(&mut buffer) else {
+            panic!("expected a message");
+        };
+        assert_eq!(message.msg_type(), HEARTBEAT);
+        assert_eq!(message.get(SENDER_COMP_ID), Some("CLIENT"));
+        assert!(matches!(take_message(&mut buffer), Ok(Frame::Message(_))));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_partial_frame() {
+        let whole = frame();
+        for end in [0, 5, 12, whole.len() / 2, whole.len() - 1] {
+            let mut buffer = whole[..end].to_vec();
+            assert!(matches!(take_message(&mut buffer), Ok(Frame::Incomplete)), "split at {}", end);
+            assert_eq!(buffer.len(), end);
+        }
+    }
+
+    #[test]
+    fn drops_a_frame_with_a_bad_checksum() {
+        let mut buffer = frame();
+        let checksum_start = buffer.len() - 4;
+        buffer[checksum_start..checksum_start + 3].copy_from_slice(b"999");
+        buffer.extend(frame());
+        assert!(matches!(take_message(&mut buffer), Ok(Frame::Garbled(_))));
+        assert!(matches!(take_message(&mut buffer), Ok(Frame::Message(_))));
+    }
+
+    #[test]
+    fn rejects_a_wrong_body_length() {
+        let whole = frame();
+        let text = String::from_utf8(whole.clone()).unwrap();
+        let length: usize = text.split('\x01').nth(1).unwrap()[2..].parse().unwrap();
+
+        let short = text.replacen(&format!("9={}", length), &format!("9={}", length - 3), 1);
+        assert!(take_message(&mut short.into_bytes()).is_err());
+
+        let huge = text.replacen(&format!("9={}", length), &format!("9={}", MAX_MESSAGE_BYTES + 1), 1);
+        assert!(take_message(&mut huge.into_bytes()).is_err());
+    }
+
+    #[test]
+    fn rejects_another_begin_string() {
+        let mut buffer = String::from_utf8(frame()).unwrap().replacen("FIX.4.4", "FIX.4.2", 1).into_bytes();
+        assert!(take_message(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn accepts_a_valid_logon() {
+        assert_eq!(
+            check_logon(&logon(), &config()),
+            Ok(AcceptedLogon { broker_id: 7, heartbeat: Duration::from_secs(30), next_incoming: 2 })
+        );
+    }
+
+    #[test]
+    fn refuses_bad_logons() {
+        let with = |tag: u32, value: &str| {
+            let mut logon = logon();
+            logon.fields.retain(|(t, _)| *t != tag);
+            logon.with(tag, value)
+        };
+        let refused = [
+            Message::new(HEARTBEAT),
+            with(TARGET_COMP_ID, "SOMEONE_ELSE"),
+            with(SENDER_COMP_ID, "STRANGER"),
+            with(ENCRYPT_METHOD, "1"),
+            with(HEART_BT_INT, "0"),
+            with(HEART_BT_INT, "3601"),
+            with(HEART_BT_INT, &u64::MAX.to_string()),
+            with(MSG_SEQ_NUM, &u64::MAX.to_string()),
+            with(MSG_SEQ_NUM, "one"),
+        ];
+        for logon in refused {
+            assert!(check_logon(&logon, &config()).is_err(), "accepted {:?}", logon);
+        }
+    }
+
+    #[test]
+    fn numbers_exec_ids_in_sequence() {
+        let acceptor = Acceptor::new();
+        let first = acceptor.exec_id();
+        let second = acceptor.exec_id();
+        assert_ne!(first, second);
+        assert!(first.ends_with("-1") && second.ends_with("-2"), "{} {}", first, second);
+    }
+
+    #[test]
+    fn logs_on_a_comp_id_once_at_a_time() {
+        let acceptor = Acceptor::new();
+        assert!(acceptor.log_on("CLIENT"));
+        assert!(!acceptor.log_on("CLIENT"));
+        assert!(acceptor.log_on("OTHER"));
+        acceptor.log_off("CLIENT");
+        assert!(acceptor.log_on("CLIENT"));
+    }
+}
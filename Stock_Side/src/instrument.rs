@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use rust_decimal::Decimal;
+use utoipa::ToSchema;
+use crate::brokers::{BrokerActivity, RejectReason};
+use crate::config::{InstrumentConfig, StockConfig};
+use crate::money::Money;
+use crate::stock::Stock;
+
+/// Reference data for a tradable symbol. Every incoming broker activity is
+/// checked against these rules before it touches the stock's quantity.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Instrument {
+    pub symbol: String,
+    pub tick_size: Money,
+    pub lot_size: usize,
+    pub min_quantity: usize,
+    pub max_quantity: usize,
+    /// Maximum distance of an order price from the reference price, in percent.
+    pub price_band_percent: Decimal,
+}
+
+impl Instrument {
+    pub fn round_to_tick(&self, price: Money) -> Money {
+        price.round_to_tick(self.tick_size)
+    }
+
+    pub fn is_on_tick(&self, price: Money) -> bool {
+        price.is_multiple_of(self.tick_size)
+    }
+
+    pub fn price_band(&self, reference_price: Money) -> (Money, Money) {
+        let width = Money::new(reference_price.amount() * self.price_band_percent / Decimal::ONE_HUNDRED);
+        (reference_price - width, reference_price + width)
+    }
+
+    pub fn validate(&self, activity: &BrokerActivity, reference_price: Money) -> Result<(), RejectReason> {
+        if activity.quantity < self.min_quantity {
+            return Err(RejectReason::QuantityBelowMinimum);
+        }
+        if activity.quantity > self.max_quantity {
+            return Err(RejectReason::QuantityAboveMaximum);
+        }
+        if !activity.quantity.is_multiple_of(self.lot_size) {
+            return Err(RejectReason::InvalidLotSize);
+        }
+        if let Some(price) = activity.price {
+            if !self.is_on_tick(price) {
+                return Err(RejectReason::InvalidTickSize);
+            }
+            let (low, high) = self.price_band(reference_price);
+            if price < low || price > high {
+                return Err(RejectReason::OutsidePriceBand);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Instruments for `stocks`, with the limits `configs` gives each symbol and
+/// the default limits for any symbol it leaves out.
+pub fn initialize_instruments(stocks: &HashMap<String, Stock>, configs: &[StockConfig]) -> HashMap<String, Instrument> {
+    let default = InstrumentConfig::default();
+    stocks
+        .keys()
+        .map(|symbol| {
+            let config = configs
+                .iter()
+                .find(|config| config.symbol == *symbol)
+                .map_or(&default, |config| &config.instrument);
+            (
+                symbol.clone(),
+                Instrument {
+                    symbol: symbol.clone(),
+                    tick_size: config.tick_size,
+                    lot_size: config.lot_size,
+                    min_quantity: config.min_quantity,
+                    max_quantity: config.max_quantity,
+                    price_band_percent: config.price_band_percent,
+                },
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instrument() -> Instrument {
+        Instrument {
+            symbol: "AAPL".to_string(),
+            tick_size: Money::from_cents(5),
+            lot_size: 10,
+            min_quantity: 10,
+            max_quantity: 1_000,
+            price_band_percent: Decimal::TEN,
+        }
+    }
+
+    fn validate(quantity: usize, price: Option<Money>) -> Result<(), RejectReason> {
+        let activity = BrokerActivity {
+            activity_id: "test-1".to_string(),
+            broker_id: 1,
+            stock_id: "AAPL".to_string(),
+            action: "buy".to_string(),
+            quantity,
+            price,
+        };
+        instrument().validate(&activity, Money::from_cents(10_000))
+    }
+
+    #[test]
+    fn accepts_market_and_limit_orders_within_the_limits() {
+        assert_eq!(validate(10, None), Ok(()));
+        assert_eq!(validate(1_000, Some(Money::from_cents(10_995))), Ok(()));
+    }
+
+    #[test]
+    fn rejects_quantities_outside_the_limits() {
+        assert_eq!(validate(0, None), Err(RejectReason::QuantityBelowMinimum));
+        assert_eq!(validate(1_010, None), Err(RejectReason::QuantityAboveMaximum));
+        assert_eq!(validate(15, None), Err(RejectReason::InvalidLotSize));
+    }
+
+    #[test]
+    fn rejects_limit_prices_off_tick_or_outside_the_band() {
+        assert_eq!(validate(10, Some(Money::from_cents(10_001))), Err(RejectReason::InvalidTickSize));
+        assert_eq!(validate(10, Some(Money::from_cents(11_005))), Err(RejectReason::OutsidePriceBand));
+        assert_eq!(validate(10, Some(Money::from_cents(8_995))), Err(RejectReason::OutsidePriceBand));
+    }
+}
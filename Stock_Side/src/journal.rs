@@ -0,0 +1,276 @@
+//! Append-only event journal for the stock universe.
+//!
+//! Every state change is written as one JSON line before it is published.
+//! Every `snapshot_interval` events the full state is written to a snapshot
+//! file and the journal is truncated. On startup the snapshot is loaded and
+//! the journal replayed on top of it, so a restarted Stock_Side resumes from
+//! exactly where it stopped. A `MarketClosed` event ends the session and the
+//! next start begins a fresh day.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+use crate::money::Money;
+use crate::stock::Stock;
+
+pub const DEFAULT_JOURNAL_DIR: &str = "stock_side_journal";
+pub const DEFAULT_SNAPSHOT_INTERVAL: u64 = 100;
+
+const JOURNAL_FILE: &str = "events.jsonl";
+const SNAPSHOT_FILE: &str = "snapshot.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Event {
+    SessionStarted { market_time: NaiveTime, stocks: Vec<Stock> },
+    PricesUpdated { market_time: NaiveTime, prices: Vec<(String, Money)> },
+    QuantityChanged { stock_id: String, available_quantity: usize },
+    ClockAdvanced { market_time: NaiveTime },
+    MarketClosed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Record {
+    sequence: u64,
+    event: Event,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MarketState {
+    pub sequence: u64,
+    pub market_time: Option<NaiveTime>,
+    /// Market time of the last price update, so a resumed session does not
+    /// move prices twice in the same cycle.
+    pub last_priced_at: Option<NaiveTime>,
+    pub stocks: HashMap<String, Stock>,
+    pub closed: bool,
+}
+
+impl MarketState {
+    pub fn apply(&mut self, event: &Event) {
+        match event {
+            Event::SessionStarted { market_time, stocks } => {
+                self.market_time = Some(*market_time);
+                self.last_priced_at = None;
+                self.stocks = stocks.iter().map(|stock| (stock.id.clone(), stock.clone())).collect();
+                self.closed = false;
+            }
+            Event::PricesUpdated { market_time, prices } => {
+                self.last_priced_at = Some(*market_time);
+                for (stock_id, price) in prices {
+                    if let Some(stock) = self.stocks.get_mut(stock_id) {
+                        stock.price = *price;
+                    }
+                }
+            }
+            Event::QuantityChanged { stock_id, available_quantity } => {
+                if let Some(stock) = self.stocks.get_mut(stock_id) {
+                    stock.available_quantity = *available_quantity;
+                }
+            }
+            Event::ClockAdvanced { market_time } => self.market_time = Some(*market_time),
+            Event::MarketClosed => self.closed = true,
+        }
+    }
+}
+
+pub struct Journal {
+    dir: PathBuf,
+    file: File,
+    state: MarketState,
+    events_since_snapshot: u64,
+    snapshot_interval: u64,
+}
+
+impl Journal {
+    /// Opens the journal in `dir`, rebuilding the last state from the
+    /// snapshot and journal. Returns `None` for the state if there is no
+    /// unfinished session to resume.
+    pub fn open(dir: &str, snapshot_interval: u64) -> io::Result<(Journal, Option<MarketState>)> {
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir)?;
+
+        let mut state = read_snapshot(&dir.join(SNAPSHOT_FILE))?.unwrap_or_default();
+        for record in read_records(&dir.join(JOURNAL_FILE))? {
+            if record.sequence > state.sequence {
+                state.apply(&record.event);
+                state.sequence = record.sequence;
+            }
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(dir.join(JOURNAL_FILE))?;
+        let resumable = state.market_time.is_some() && !state.closed;
+        let recovered = resumable.then(|| state.clone());
+        let mut journal = Journal {
+            dir,
+            file,
+            state,
+            events_since_snapshot: 0,
+            snapshot_interval,
+        };
+        // Compact straight away so a torn last line never has records
+        // appended after it
+        journal.snapshot()?;
+        Ok((journal, recovered))
+    }
+
+    pub fn append(&mut self, event: Event) -> io::Result<()> {
+        let record = Record {
+            sequence: self.state.sequence + 1,
+            event,
+        };
+        let mut line = serde_json::to_vec(&record).map_err(io::Error::other)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.sync_data()?;
+
+        self.state.apply(&record.event);
+        self.state.sequence = record.sequence;
+        self.events_since_snapshot += 1;
+        if self.events_since_snapshot >= self.snapshot_interval {
+            self.snapshot()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the current state atomically and truncates the journal.
+    pub fn snapshot(&mut self) -> io::Result<()> {
+        let snapshot_path = self.dir.join(SNAPSHOT_FILE);
+        let temp_path = self.dir.join(format!("{}.tmp", SNAPSHOT_FILE));
+        let payload = serde_json::to_vec(&self.state).map_err(io::Error::other)?;
+        {
+            let mut temp = File::create(&temp_path)?;
+            temp.write_all(&payload)?;
+            temp.sync_all()?;
+        }
+        fs::rename(&temp_path, &snapshot_path)?;
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(self.dir.join(JOURNAL_FILE))?;
+        self.events_since_snapshot = 0;
+        Ok(())
+    }
+}
+
+fn read_snapshot(path: &Path) -> io::Result<Option<MarketState>> {
+    match fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes).map(Some).map_err(io::Error::other),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads journal records, stopping at the first line that does not parse:
+/// a crash mid-write can only leave a torn final line.
+fn read_records(path: &Path) -> io::Result<Vec<Record>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut records = Vec::new();
+    for line in BufReader::new(file).lines() {
+        match serde_json::from_str::<Record>(&line?) {
+            Ok(record) => records.push(record),
+            Err(_) => break,
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    /// An empty journal directory for one test.
+    fn journal_dir(test: &str) -> String {
+        let dir = env::temp_dir().join(format!("stock_side_journal_test_{}_{}", test, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.to_str().unwrap().to_string()
+    }
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn session_started() -> Event {
+        Event::SessionStarted {
+            market_time: time(9, 0),
+            stocks: vec![Stock { id: "AAPL".to_string(), price: Money::from_f64(150.0), available_quantity: 1000 }],
+        }
+    }
+
+    #[test]
+    fn replays_the_journal_on_top_of_the_snapshot() {
+        let dir = journal_dir("replay");
+        {
+            let (mut journal, recovered) = Journal::open(&dir, 3).unwrap();
+            assert!(recovered.is_none());
+            journal.append(session_started()).unwrap();
+            let prices = vec![("AAPL".to_string(), Money::from_f64(151.0))];
+            journal.append(Event::PricesUpdated { market_time: time(9, 0), prices }).unwrap();
+            // The third event takes a snapshot; the rest are only in the journal
+            journal.append(Event::ClockAdvanced { market_time: time(9, 30) }).unwrap();
+            journal.append(Event::QuantityChanged { stock_id: "AAPL".to_string(), available_quantity: 990 }).unwrap();
+            journal.append(Event::ClockAdvanced { market_time: time(10, 0) }).unwrap();
+        }
+
+        let (_, recovered) = Journal::open(&dir, 3).unwrap();
+        let state = recovered.expect("an unfinished session");
+        assert_eq!(state.sequence, 5);
+        assert_eq!(state.market_time, Some(time(10, 0)));
+        assert_eq!(state.last_priced_at, Some(time(9, 0)));
+        let stock = &state.stocks["AAPL"];
+        assert_eq!(stock.price, Money::from_f64(151.0));
+        assert_eq!(stock.available_quantity, 990);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ignores_a_torn_last_line() {
+        let dir = journal_dir("torn");
+        {
+            let (mut journal, _) = Journal::open(&dir, DEFAULT_SNAPSHOT_INTERVAL).unwrap();
+            journal.append(session_started()).unwrap();
+            journal.append(Event::QuantityChanged { stock_id: "AAPL".to_string(), available_quantity: 995 }).unwrap();
+        }
+        let mut file = OpenOptions::new().append(true).open(Path::new(&dir).join(JOURNAL_FILE)).unwrap();
+        file.write_all(br#"{"sequence":3,"event":{"QuantityChanged":{"stock_id":"AA"#).unwrap();
+        drop(file);
+
+        let (mut journal, recovered) = Journal::open(&dir, DEFAULT_SNAPSHOT_INTERVAL).unwrap();
+        let state = recovered.expect("an unfinished session");
+        assert_eq!(state.sequence, 2);
+        assert_eq!(state.stocks["AAPL"].available_quantity, 995);
+
+        // Records written after recovery replay as usual
+        journal.append(Event::QuantityChanged { stock_id: "AAPL".to_string(), available_quantity: 980 }).unwrap();
+        drop(journal);
+        let (_, recovered) = Journal::open(&dir, DEFAULT_SNAPSHOT_INTERVAL).unwrap();
+        let state = recovered.expect("an unfinished session");
+        assert_eq!(state.sequence, 3);
+        assert_eq!(state.stocks["AAPL"].available_quantity, 980);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_closed_market_starts_a_fresh_session() {
+        let dir = journal_dir("closed");
+        {
+            let (mut journal, _) = Journal::open(&dir, DEFAULT_SNAPSHOT_INTERVAL).unwrap();
+            journal.append(session_started()).unwrap();
+            journal.append(Event::MarketClosed).unwrap();
+        }
+
+        let (_, recovered) = Journal::open(&dir, DEFAULT_SNAPSHOT_INTERVAL).unwrap();
+        assert!(recovered.is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,32 @@
+//! Stock_Side as a library, so Trading_Side can run a market in the same
+//! process on a shared `memory::MemoryBroker`. The binary in `main.rs` runs
+//! it on RabbitMQ. The message types and the helpers that route and
+//! dead-letter them are public too, so Trading_Side shares them instead of
+//! keeping copies, and for the encoding benchmarks in `benches/`.
+
+mod stock;
+pub mod brokers;
+mod utils;
+pub mod messaging;
+pub mod instrument;
+pub mod money;
+pub mod fees;
+pub mod store;
+mod journal;
+mod order_entry;
+pub mod rpc;
+mod fix;
+mod feed;
+mod web;
+mod api;
+pub mod bars;
+mod replay;
+pub mod export;
+pub mod config;
+pub mod codec;
+pub mod link;
+pub mod market_data;
+pub mod dead_letters;
+pub mod memory;
+pub mod transport;
+pub mod market;
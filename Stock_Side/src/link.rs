@@ -0,0 +1,485 @@
+//! Supervised AMQP connection.
+//!
+//! `AmqpLink::start` spawns a task that keeps one connection and channel
+//! open. It connects with exponential backoff, declares the topology, and
+//! starts over the same way whenever the connection or channel reports an
+//! error. The channel is in confirm mode and a publish only counts once the
+//! broker acks it. Messages published while disconnected, or nacked, are
+//! buffered in order (up to `MAX_BUFFERED_MESSAGES`, oldest dropped first)
+//! and flushed on the next connect; `publish_with` tells the caller which
+//! happened. Consumers obtained from `consumer` or `subscriber` are created
+//! on the current channel; callers ask for a new one when their stream ends.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use chrono::{DateTime, Utc};
+use lapin::{
+    options::*, types::FieldTable, BasicProperties, Channel, Connection, ConnectionProperties,
+    Consumer, ExchangeKind,
+};
+use log::{error, info, warn};
+use rand::Rng;
+use tokio::sync::{watch, Mutex};
+use tokio::time::{sleep, Duration};
+use crate::config::AmqpConfig;
+
+pub const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+pub const MAX_BACKOFF: Duration = Duration::from_secs(30);
+pub const MAX_BUFFERED_MESSAGES: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkState {
+    Connecting { attempt: u32 },
+    Connected,
+}
+
+#[derive(Debug, Clone)]
+pub struct HealthStatus {
+    pub state: LinkState,
+    /// When the link last connected or disconnected.
+    pub since: DateTime<Utc>,
+    /// Successful connects after the first.
+    pub reconnects: u32,
+    /// Messages waiting in the outbox.
+    pub buffered: usize,
+    /// Buffered messages dropped because the outbox was full.
+    pub dropped: u64,
+    pub last_error: Option<String>,
+}
+
+impl fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.state {
+            LinkState::Connected => write!(f, "connected")?,
+            LinkState::Connecting { attempt } => write!(f, "connecting (attempt {})", attempt)?,
+        }
+        write!(f, " since {}, {} reconnects, {} buffered", self.since.format("%H:%M:%S"), self.reconnects, self.buffered)?;
+        if self.dropped > 0 {
+            write!(f, ", {} dropped", self.dropped)?;
+        }
+        if let Some(e) = &self.last_error {
+            write!(f, ", last error: {}", e)?;
+        }
+        Ok(())
+    }
+}
+
+/// What became of a published message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Published {
+    /// The broker confirmed it.
+    Confirmed,
+    /// Buffered until the link reconnects: the link is down, the broker
+    /// nacked it, or earlier messages are still waiting.
+    Buffered,
+    /// Buffered, but the outbox was full, so the oldest buffered message was
+    /// dropped to make room and will never be sent.
+    DroppedOldest,
+}
+
+struct Outgoing {
+    exchange: String,
+    routing_key: String,
+    payload: Vec<u8>,
+    properties: BasicProperties,
+}
+
+pub struct AmqpLink {
+    amqp: AmqpConfig,
+    /// The current channel with the generation of its connection.
+    channel: watch::Sender<Option<(u64, Channel)>>,
+    outbox: Mutex<VecDeque<Outgoing>>,
+    health: std::sync::Mutex<HealthStatus>,
+    /// Bumped on every connect so stale error callbacks are ignored.
+    generation: AtomicU64,
+    /// The latest generation reported lost. Every error on a connection
+    /// reports it, but only the first moves this on, so the supervisor
+    /// reconnects once per lost connection however many errors it saw.
+    lost: watch::Sender<u64>,
+}
+
+impl AmqpLink {
+    /// Creates the link and spawns its supervisor. Returns immediately;
+    /// publishes are buffered until the first connect succeeds.
+    pub fn start(amqp: AmqpConfig) -> Arc<Self> {
+        let link = Arc::new(AmqpLink {
+            amqp,
+            channel: watch::Sender::new(None),
+            outbox: Mutex::new(VecDeque::new()),
+            health: std::sync::Mutex::new(HealthStatus {
+                state: LinkState::Connecting { attempt: 0 },
+                since: Utc::now(),
+                reconnects: 0,
+                buffered: 0,
+                dropped: 0,
+                last_error: None,
+            }),
+            generation: AtomicU64::new(0),
+            lost: watch::Sender::new(0),
+        });
+        tokio::spawn(Arc::clone(&link).supervise());
+        link
+    }
+
+    pub fn amqp(&self) -> &AmqpConfig {
+        &self.amqp
+    }
+
+    pub fn health(&self) -> HealthStatus {
+        self.health.lock().unwrap().clone()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.health.lock().unwrap().state == LinkState::Connected
+    }
+
+    /// Publishes to `routing_key` on `exchange` and waits for the broker's
+    /// confirm, or buffers the message if the link is down, the broker nacks
+    /// it, or earlier messages are still buffered.
+    pub async fn publish_with(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        payload: Vec<u8>,
+        properties: BasicProperties,
+    ) -> Result<Published, lapin::Error> {
+        let mut outbox = self.outbox.lock().await;
+        if outbox.is_empty() {
+            let current = self.channel.borrow().clone();
+            if let Some((generation, channel)) = current {
+                match send(&channel, exchange, routing_key, &payload, properties.clone()).await {
+                    Ok(()) => return Ok(Published::Confirmed),
+                    Err(e) => {
+                        warn!("Publish to {} failed, buffering: {}", routing_key, e);
+                        self.connection_lost(generation, e.to_string());
+                    }
+                }
+            }
+        }
+
+        let mut published = Published::Buffered;
+        if outbox.len() >= MAX_BUFFERED_MESSAGES {
+            outbox.pop_front();
+            self.health.lock().unwrap().dropped += 1;
+            warn!("Outbox full, dropped the oldest buffered message");
+            published = Published::DroppedOldest;
+        }
+        outbox.push_back(Outgoing {
+            exchange: exchange.to_string(),
+            routing_key: routing_key.to_string(),
+            payload,
+            properties,
+        });
+        self.health.lock().unwrap().buffered = outbox.len();
+        Ok(published)
+    }
+
+    /// Waits for a channel and starts consuming `queue` on it.
+    pub async fn consumer(&self, queue: &str, consumer_tag: &str) -> Consumer {
+        let mut channels = self.channel.subscribe();
+        loop {
+            let current = channels.borrow_and_update().clone();
+            if let Some((_, channel)) = current {
+                match channel
+                    .basic_consume(queue, consumer_tag, BasicConsumeOptions::default(), FieldTable::default())
+                    .await
+                {
+                    Ok(consumer) => return consumer,
+                    Err(e) => error!("Failed to create consumer on {}: {:?}", queue, e),
+                }
+            }
+            if channels.changed().await.is_err() {
+                // The link is gone; nothing will ever connect again
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+
+    /// Like `consumer`, but first declares a private server-named queue on
+    /// each new channel and binds it to `exchange` under `binding_keys`. The
+    /// queue is deleted by the broker when this consumer goes away. Returns
+    /// the queue's name with the consumer.
+    pub async fn subscriber(&self, exchange: &str, binding_keys: &[String], consumer_tag: &str) -> (String, Consumer) {
+        let mut channels = self.channel.subscribe();
+        loop {
+            let current = channels.borrow_and_update().clone();
+            if let Some((_, channel)) = current {
+                match subscribe(&channel, exchange, binding_keys, consumer_tag).await {
+                    Ok(subscribed) => return subscribed,
+                    Err(e) => error!("Failed to subscribe to {}: {:?}", exchange, e),
+                }
+            }
+            if channels.changed().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+
+    async fn supervise(self: Arc<Self>) {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 0;
+        let mut connected_before = false;
+        loop {
+            attempt += 1;
+            self.set_state(LinkState::Connecting { attempt });
+            let (connection, channel) = match self.connect().await {
+                Ok(connected) => connected,
+                Err(e) => {
+                    // Up to 10% jitter so both sides do not retry in lockstep
+                    let delay = backoff.mul_f64(1.0 + rand::thread_rng().gen_range(0.0..0.1));
+                    warn!("AMQP connect attempt {} failed: {}; retrying in {:.1?}", attempt, e, delay);
+                    self.health.lock().unwrap().last_error = Some(e.to_string());
+                    sleep(delay).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    continue;
+                }
+            };
+
+            let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+            let weak = Arc::downgrade(&self);
+            connection.on_error(move |e| lost(&weak, generation, e));
+            let weak = Arc::downgrade(&self);
+            channel.on_error(move |e| lost(&weak, generation, e));
+            self.channel.send_replace(Some((generation, channel.clone())));
+
+            match self.flush(&channel).await {
+                Ok(flushed) => {
+                    if connected_before {
+                        self.health.lock().unwrap().reconnects += 1;
+                    }
+                    connected_before = true;
+                    backoff = INITIAL_BACKOFF;
+                    attempt = 0;
+                    self.set_state(LinkState::Connected);
+                    info!("AMQP link connected, flushed {} buffered messages", flushed);
+                }
+                Err(e) => {
+                    self.connection_lost(generation, e.to_string());
+                }
+            }
+
+            // Closed only when the link is dropped, which ends this task too
+            let _ = self.lost.subscribe().wait_for(|lost| *lost >= generation).await;
+            self.channel.send_replace(None);
+            if let Err(e) = connection.close(0, "reconnecting").await {
+                warn!("Failed to close lost AMQP connection: {:?}", e);
+            }
+        }
+    }
+
+    async fn connect(&self) -> Result<(Connection, Channel), lapin::Error> {
+        let connection = Connection::connect(&self.amqp.uri, ConnectionProperties::default()).await?;
+        let channel = connection.create_channel().await?;
+        channel.confirm_select(ConfirmSelectOptions::default()).await?;
+        declare_topology(&channel, &self.amqp).await?;
+        Ok((connection, channel))
+    }
+
+    /// Sends buffered messages in order, leaving any that fail in place.
+    async fn flush(&self, channel: &Channel) -> Result<usize, SendError> {
+        let mut outbox = self.outbox.lock().await;
+        let mut flushed = 0;
+        while let Some(message) = outbox.front() {
+            send(channel, &message.exchange, &message.routing_key, &message.payload, message.properties.clone()).await?;
+            outbox.pop_front();
+            flushed += 1;
+        }
+        self.health.lock().unwrap().buffered = 0;
+        Ok(flushed)
+    }
+
+    /// Reports the connection of `generation` lost. Returns whether this
+    /// was the first report for the current connection.
+    fn connection_lost(&self, generation: u64, reason: String) -> bool {
+        // Errors from an earlier connection must not tear down the current one
+        if generation != self.generation.load(Ordering::SeqCst) {
+            return false;
+        }
+        let first = self.lost.send_if_modified(|lost| {
+            let first = *lost < generation;
+            *lost = generation;
+            first
+        });
+        let mut health = self.health.lock().unwrap();
+        if first && health.state == LinkState::Connected {
+            warn!("AMQP link lost: {}", reason);
+        }
+        health.last_error = Some(reason);
+        first
+    }
+
+    /// Updates the state, restarting `since` only when moving between
+    /// connected and connecting.
+    fn set_state(&self, state: LinkState) {
+        let mut health = self.health.lock().unwrap();
+        if mem::discriminant(&health.state) != mem::discriminant(&state) {
+            health.since = Utc::now();
+        }
+        health.state = state;
+    }
+}
+
+enum SendError {
+    Amqp(lapin::Error),
+    Nacked,
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Amqp(e) => write!(f, "{}", e),
+            SendError::Nacked => write!(f, "message nacked by the broker"),
+        }
+    }
+}
+
+impl From<lapin::Error> for SendError {
+    fn from(e: lapin::Error) -> Self {
+        SendError::Amqp(e)
+    }
+}
+
+/// Publishes one message and waits for its confirm.
+async fn send(
+    channel: &Channel,
+    exchange: &str,
+    routing_key: &str,
+    payload: &[u8],
+    properties: BasicProperties,
+) -> Result<(), SendError> {
+    let confirmation = channel
+        .basic_publish(exchange, routing_key, BasicPublishOptions::default(), payload, properties)
+        .await?
+        .await?;
+    if confirmation.is_nack() {
+        return Err(SendError::Nacked);
+    }
+    Ok(())
+}
+
+async fn subscribe(
+    channel: &Channel,
+    exchange: &str,
+    binding_keys: &[String],
+    consumer_tag: &str,
+) -> Result<(String, Consumer), lapin::Error> {
+    let options = QueueDeclareOptions {
+        exclusive: true,
+        auto_delete: true,
+        ..QueueDeclareOptions::default()
+    };
+    let queue = channel.queue_declare("", options, FieldTable::default()).await?;
+    for key in binding_keys {
+        channel
+            .queue_bind(queue.name().as_str(), exchange, key, QueueBindOptions::default(), FieldTable::default())
+            .await?;
+    }
+    let consumer = channel
+        .basic_consume(queue.name().as_str(), consumer_tag, BasicConsumeOptions::default(), FieldTable::default())
+        .await?;
+    Ok((queue.name().to_string(), consumer))
+}
+
+fn lost(link: &Weak<AmqpLink>, generation: u64, e: lapin::Error) {
+    if let Some(link) = link.upgrade() {
+        link.connection_lost(generation, e.to_string());
+    }
+}
+
+/// Declares the market data topic exchange, the dead-letter exchange with
+/// its queue, and every queue, and with a named exchange binds each queue to
+/// it under its own name. Safe to repeat from either side.
+pub async fn declare_topology(channel: &Channel, amqp: &AmqpConfig) -> Result<(), lapin::Error> {
+    channel
+        .exchange_declare(
+            &amqp.market_data_exchange,
+            ExchangeKind::Topic,
+            ExchangeDeclareOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+
+    channel
+        .exchange_declare(
+            &amqp.dead_letter_exchange,
+            ExchangeKind::Fanout,
+            ExchangeDeclareOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+    channel
+        .queue_declare(&amqp.dead_letter_queue, QueueDeclareOptions::default(), FieldTable::default())
+        .await?;
+    channel
+        .queue_bind(
+            &amqp.dead_letter_queue,
+            &amqp.dead_letter_exchange,
+            "",
+            QueueBindOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+    if !amqp.exchange.is_empty() {
+        channel
+            .exchange_declare(
+                &amqp.exchange,
+                ExchangeKind::Direct,
+                ExchangeDeclareOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+    }
+
+    for queue in amqp.queues.all() {
+        channel
+            .queue_declare(queue, QueueDeclareOptions::default(), FieldTable::default())
+            .await?;
+        if !amqp.exchange.is_empty() {
+            channel
+                .queue_bind(queue, &amqp.exchange, queue, QueueBindOptions::default(), FieldTable::default())
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A link to a port nothing listens on, so it never connects.
+    fn unreachable_link() -> Arc<AmqpLink> {
+        AmqpLink::start(AmqpConfig { uri: "amqp://127.0.0.1:1/%2f".to_string(), ..AmqpConfig::default() })
+    }
+
+    #[tokio::test]
+    async fn publishes_while_disconnected_are_buffered_until_the_outbox_overflows() {
+        let link = unreachable_link();
+        for _ in 0..MAX_BUFFERED_MESSAGES {
+            let published = link.publish_with("", "queue", vec![1], BasicProperties::default()).await.unwrap();
+            assert_eq!(published, Published::Buffered);
+        }
+        let published = link.publish_with("", "queue", vec![2], BasicProperties::default()).await.unwrap();
+        assert_eq!(published, Published::DroppedOldest);
+
+        let health = link.health();
+        assert_eq!((health.buffered, health.dropped), (MAX_BUFFERED_MESSAGES, 1));
+    }
+
+    #[tokio::test]
+    async fn only_the_first_error_of_a_connection_reports_it_lost() {
+        let link = unreachable_link();
+        link.generation.store(1, Ordering::SeqCst);
+        assert!(link.connection_lost(1, "channel error".to_string()));
+        assert!(!link.connection_lost(1, "connection error".to_string()));
+        assert!(!link.connection_lost(0, "error from an earlier connection".to_string()));
+
+        // The next connection is not mistaken for lost
+        link.generation.store(2, Ordering::SeqCst);
+        assert_eq!(*link.lost.borrow(), 1);
+        assert!(link.connection_lost(2, "channel error".to_string()));
+    }
+}
@@ -0,0 +1,424 @@
+//! One market session: prices, broker activities, market data and bars,
+//! from the opening bell (or the journaled point of a crash) to the close.
+//! Runs on any `Transport`, so the binary and an in-process harness share it.
+
+use crate::messaging::{dead_letter, DEAD_LETTERED_BY, send_market_data, send_activity_rejection, send_fill, send_bars, consume_messages};
+use crate::stock::{apply_price_fluctuations, Stock};
+use crate::brokers::{ActivityOutcome, BrokerActivity};
+use crate::fees::FeeSchedule;
+use crate::store::Store;
+use crate::journal::{Event, Journal, DEFAULT_SNAPSHOT_INTERVAL};
+use crate::order_entry::{OrderEntry, Submission};
+use crate::rpc;
+use crate::fix;
+use crate::feed::{Feed, FeedEvent};
+use crate::web;
+use crate::bars::{Bar, BarAggregator};
+use crate::replay::PriceReplay;
+use crate::export::{export_session, DEFAULT_EXPORT_DIR};
+use crate::config::Config;
+use crate::transport::Transport;
+use crate::market_data::{MarketDataPublisher, SnapshotRequest};
+use crate::instrument::initialize_instruments;
+use crate::utils::print_stock_list;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration};
+use log::{info, warn, error};
+use futures_util::StreamExt;
+
+/// Runs the market until it closes, then exports the session.
+pub async fn run_market(config: Config, transport: Arc<Transport>) -> Result<(), Box<dyn Error>> {
+    let market = config.market.clone();
+
+    // Session database
+    let store = Arc::new(Store::open(&config.database_path)?);
+    info!("Recording session {} to {}", store.session_id(), config.database_path);
+
+    // Optional historical prices in place of random fluctuations
+    let mut replay = match &config.replay_csv {
+        Some(path) => {
+            let universe = config.initial_stocks();
+            let replay = PriceReplay::from_csv(path, &universe, market.cycle_minutes, market.cycles_per_session())?;
+            let replayed = replay.symbols().count();
+            info!("Replaying prices for {} symbols from {}", replayed, path);
+            if replayed < universe.len() {
+                warn!("{} symbols have no history in {} and will hold their price", universe.len() - replayed, path);
+            }
+            Some(replay)
+        }
+        None => None,
+    };
+
+    // Event journal: resume an unfinished session or start a fresh day
+    let (mut journal, recovered) = Journal::open(&config.journal_dir, DEFAULT_SNAPSHOT_INTERVAL)?;
+    let (initial_stocks, mut current_time, mut already_priced) = match recovered {
+        Some(state) => {
+            let market_time = state
+                .market_time
+                .ok_or_else(|| format!("journal in {} resumes a session without a market time", config.journal_dir))?;
+            info!(
+                "Resuming session at {} from journal (event {})",
+                market_time.format("%I:%M %p"),
+                state.sequence
+            );
+            let already_priced = state.last_priced_at == Some(market_time);
+            if let Some(replay) = replay.as_mut() {
+                let elapsed = ((market_time - market.open).num_minutes() / market.cycle_minutes) as usize;
+                replay.skip(elapsed + usize::from(already_priced));
+            }
+            (state.stocks, market_time, already_priced)
+        }
+        None => {
+            let market_time = market.open;
+            let mut stocks = config.initial_stocks();
+            if let Some(replay) = &replay {
+                for (symbol, price) in replay.opening_prices() {
+                    if let Some(stock) = stocks.get_mut(symbol) {
+                        stock.price = price;
+                    }
+                }
+            }
+            journal.append(Event::SessionStarted {
+                market_time,
+                stocks: stocks.values().cloned().collect(),
+            })?;
+            (stocks, market_time, false)
+        }
+    };
+    let journal = Arc::new(Mutex::new(journal));
+
+    // Initialize stocks
+    let stocks = Arc::new(Mutex::new(initial_stocks));
+
+    // Instrument reference data is fixed for the session
+    let instruments = {
+        let stocks_guard = stocks.lock().await;
+        Arc::new(initialize_instruments(&stocks_guard, &config.stocks))
+    };
+    
+    // Print initial stock list
+    {
+        let stocks_guard = stocks.lock().await;
+        print_stock_list(&stocks_guard);
+    }
+
+    // Broker activities from RabbitMQ and, if configured, FIX and WebSocket sessions
+    let bars = Arc::new(Mutex::new(BarAggregator::new(&market.bar_intervals)));
+    let feed = Arc::new(Feed::new());
+    let order_entry = Arc::new(OrderEntry {
+        stocks: Arc::clone(&stocks),
+        instruments: Arc::clone(&instruments),
+        fee_schedule: FeeSchedule::default(),
+        store: Arc::clone(&store),
+        journal: Arc::clone(&journal),
+        bars: Arc::clone(&bars),
+        feed: Arc::clone(&feed),
+    });
+    let transport_clone = Arc::clone(&transport);
+    let order_entry_clone = Arc::clone(&order_entry);
+    let _broker_handle = tokio::spawn(async move {
+        process_broker_activities_from_queue(&transport_clone, &order_entry_clone).await;
+    });
+
+    if !config.fix.listen.is_empty() {
+        let listener = TcpListener::bind(&config.fix.listen).await?;
+        info!("FIX acceptor listening on {} as {}", config.fix.listen, config.fix.comp_id);
+        tokio::spawn(fix::serve(listener, config.fix.clone(), Arc::clone(&order_entry)));
+    }
+
+    if !config.web.listen.is_empty() {
+        let listener = TcpListener::bind(&config.web.listen).await?;
+        info!("Web server listening on {} (WebSocket at /ws, REST under /api)", config.web.listen);
+        tokio::spawn(web::serve(listener, config.web.clone(), Arc::clone(&order_entry)));
+    }
+
+    // Answer quote, order status and instrument queries
+    tokio::spawn(rpc::serve(Arc::clone(&transport), Arc::clone(&order_entry)));
+
+    // Answer snapshot requests from subscribers that detected a gap
+    let market_data = Arc::new(Mutex::new(MarketDataPublisher::new()));
+    let snapshot_transport = Arc::clone(&transport);
+    let snapshot_stocks = Arc::clone(&stocks);
+    let snapshot_market_data = Arc::clone(&market_data);
+    tokio::spawn(async move {
+        serve_snapshot_requests(&snapshot_transport, &snapshot_stocks, &snapshot_market_data).await;
+    });
+    let mut cycles_published = 0;
+
+    // Main market simulation loop
+    loop {
+        info!("Market time: {}", current_time.format("%I:%M %p"));
+        if !transport.is_connected() {
+            warn!("RabbitMQ link {}", transport.health());
+        }
+
+        // Update stocks and prices
+        let completed_bars = {
+            let mut stocks_guard = stocks.lock().await;
+            // A resumed session may already have moved prices this cycle
+            if !already_priced {
+                match replay.as_mut() {
+                    Some(replay) => {
+                        if !replay.apply_next_prices(&mut stocks_guard, &instruments) {
+                            info!("Replay data exhausted, holding last prices");
+                        }
+                    }
+                    None => apply_price_fluctuations(&mut stocks_guard, &instruments),
+                }
+                let prices = stocks_guard
+                    .values()
+                    .map(|stock| (stock.id.clone(), stock.price))
+                    .collect();
+                if let Err(e) = journal.lock().await.append(Event::PricesUpdated { market_time: current_time, prices }) {
+                    error!("Failed to journal price update: {:?}", e);
+                }
+            }
+            already_priced = false;
+            print_stock_list(&stocks_guard);
+            if let Err(e) = store.record_price_ticks(current_time, stocks_guard.values()) {
+                error!("Failed to record price ticks: {:?}", e);
+            }
+
+            let mut bars_guard = bars.lock().await;
+            stocks_guard
+                .values()
+                .flat_map(|stock| bars_guard.record_tick(&stock.id, current_time, stock.price))
+                .collect::<Vec<Bar>>()
+        };
+        publish_bars(&transport, &store, &feed, &completed_bars).await;
+
+        // Send changes (or a periodic full snapshot) to Trading Side
+        let full = cycles_published % market.snapshot_interval_cycles == 0;
+        let messages = {
+            let stocks_guard = stocks.lock().await;
+            let messages = market_data.lock().await.cycle(&stocks_guard, full);
+            for message in &messages {
+                if let Some(stock) = stocks_guard.get(message.symbol()) {
+                    feed.publish(FeedEvent::Stock(stock.clone()));
+                }
+            }
+            messages
+        };
+        cycles_published += 1;
+        if let Err(e) = send_market_data(&transport, &messages).await {
+            error!("Failed to send market data: {:?}", e);
+        } else if full {
+            info!("Market data snapshot sent for {} symbols", messages.len());
+        } else {
+            info!("Market data updates sent for {} symbols", messages.len());
+        }
+
+        // Wait for next cycle
+        sleep(Duration::from_secs(market.tick_interval_secs)).await;
+
+        // Advance market time
+        current_time = current_time
+            .overflowing_add_signed(chrono::Duration::minutes(market.cycle_minutes))
+            .0;
+
+        // Check market close
+        if current_time >= market.close {
+            if let Err(e) = journal.lock().await.append(Event::MarketClosed) {
+                error!("Failed to journal market close: {:?}", e);
+            }
+            let closing_bars = bars.lock().await.close_all();
+            publish_bars(&transport, &store, &feed, &closing_bars).await;
+            info!("Market closed at {}", market.close.format("%I:%M %p"));
+            break;
+        }
+        if let Err(e) = journal.lock().await.append(Event::ClockAdvanced { market_time: current_time }) {
+            error!("Failed to journal clock advance: {:?}", e);
+        }
+    }
+
+    let health = transport.health();
+    if health.buffered > 0 {
+        warn!("{} messages were never delivered: RabbitMQ link {}", health.buffered, health);
+    }
+
+    match export_session(&store, DEFAULT_EXPORT_DIR) {
+        Ok(dir) => info!("Exported session data to {}", dir.display()),
+        Err(e) => error!("Failed to export session data: {:?}", e),
+    }
+
+    Ok(())
+}
+
+/// Persists completed bars and publishes them to subscribers.
+async fn publish_bars(transport: &Transport, store: &Store, feed: &Feed, bars: &[Bar]) {
+    if bars.is_empty() {
+        return;
+    }
+    if let Err(e) = store.record_bars(bars) {
+        error!("Failed to record bars: {:?}", e);
+    }
+    for bar in bars {
+        feed.publish(FeedEvent::Bar(bar.clone()));
+    }
+    if let Err(e) = send_bars(transport, bars).await {
+        error!("Failed to send bars: {:?}", e);
+    } else {
+        info!("Published {} completed bars", bars.len());
+    }
+}
+
+async fn serve_snapshot_requests(
+    transport: &Transport,
+    stocks: &Mutex<HashMap<String, Stock>>,
+    market_data: &Mutex<MarketDataPublisher>,
+) {
+    loop {
+        let mut consumer = consume_messages(transport, &transport.amqp().queues.snapshot_requests, "snapshot_consumer").await;
+
+        while let Some(delivery) = consumer.next().await {
+            let message = match delivery {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!("Snapshot request consumer failed: {:?}", e);
+                    break;
+                }
+            };
+            match message.decode::<SnapshotRequest>() {
+                Ok(request) => {
+                    let snapshots = {
+                        let stocks_guard = stocks.lock().await;
+                        market_data.lock().await.snapshots(&stocks_guard, &request.symbols)
+                    };
+                    if let Err(e) = send_market_data(transport, &snapshots).await {
+                        error!("Failed to send requested snapshots: {:?}", e);
+                    } else {
+                        info!("Sent {} requested market data snapshots", snapshots.len());
+                    }
+                }
+                Err(e) => {
+                    dead_letter(transport, &message, DEAD_LETTERED_BY, &format!("invalid snapshot request: {}", e)).await;
+                    continue;
+                }
+            }
+
+            if let Err(e) = message.ack().await {
+                error!("Failed to acknowledge message: {:?}", e);
+            }
+        }
+        warn!("Snapshot request consumer stopped, resubscribing");
+    }
+}
+
+async fn process_broker_activities_from_queue(transport: &Transport, order_entry: &OrderEntry) {
+    loop {
+        let mut consumer = consume_messages(transport, &transport.amqp().queues.broker_activities, "stock_consumer").await;
+
+        while let Some(delivery) = consumer.next().await {
+            let message = match delivery {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!("Broker activity consumer failed: {:?}", e);
+                    break;
+                }
+            };
+            match message.decode::<BrokerActivity>() {
+                Ok(broker_activity) => {
+                    let activity_clone = broker_activity.clone();
+                    // A redelivered activity is not applied again, but its
+                    // outcome is published again in case the first was lost
+                    let outcomes = match order_entry.submit(broker_activity).await {
+                        Submission::Applied(outcomes) => {
+                            info!("Processed broker activity: {:?}", activity_clone);
+                            outcomes
+                        }
+                        Submission::Duplicate(outcome) => outcome.into_iter().collect(),
+                    };
+                    for outcome in &outcomes {
+                        let result = match outcome {
+                            ActivityOutcome::Filled(fill) => send_fill(transport, fill).await,
+                            ActivityOutcome::Rejected(rejection) => send_activity_rejection(transport, rejection).await,
+                        };
+                        if let Err(e) = result {
+                            error!("Failed to send activity outcome: {:?}", e);
+                        }
+                    }
+
+                    if let Err(e) = message.ack().await {
+                        error!("Failed to acknowledge message: {:?}", e);
+                    }
+                }
+                Err(e) => dead_letter(transport, &message, DEAD_LETTERED_BY, &format!("invalid broker activity: {}", e)).await,
+            }
+        }
+        warn!("Broker activity consumer stopped, resubscribing");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AmqpConfig;
+    use crate::memory::MemoryBroker;
+    use crate::money::Money;
+    use crate::brokers::Fill;
+    use std::env;
+    use std::process;
+
+    fn activity() -> BrokerActivity {
+        BrokerActivity {
+            activity_id: "test-1".to_string(),
+            broker_id: 7,
+            stock_id: "AAPL".to_string(),
+            action: "Buy".to_string(),
+            quantity: 10,
+            price: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn activity_on_the_queue_is_filled_over_the_in_memory_transport() {
+        let transport = Transport::in_memory(MemoryBroker::new(), AmqpConfig::default());
+        let journal_dir = env::temp_dir().join(format!("stock_side_market_test_{}", process::id()));
+        let order_entry = OrderEntry::for_tests(&journal_dir);
+        let fills = consume_messages(&transport, &transport.amqp().queues.activity_fills, "test_fills").await;
+        let server_transport = Arc::clone(&transport);
+        tokio::spawn(async move {
+            process_broker_activities_from_queue(&server_transport, &order_entry).await;
+        });
+
+        transport.publish(&transport.amqp().queues.broker_activities, &activity()).await.unwrap();
+
+        let delivery = tokio::time::timeout(Duration::from_secs(5), fills.into_future())
+            .await
+            .expect("no fill within 5s")
+            .0
+            .expect("fill consumer ended")
+            .unwrap();
+        let fill: Fill = delivery.decode().unwrap();
+        assert_eq!((fill.broker_id, fill.stock_id.as_str(), fill.quantity), (7, "AAPL", 10));
+        assert_eq!(fill.price, Money::from_f64(150.0));
+
+        let _ = std::fs::remove_dir_all(journal_dir);
+    }
+
+    #[tokio::test]
+    async fn resubmitted_activity_reports_its_recorded_fill_without_applying_it_again() {
+        let journal_dir = env::temp_dir().join(format!("stock_side_duplicate_test_{}", process::id()));
+        let order_entry = OrderEntry::for_tests(&journal_dir);
+
+        let Submission::Applied(outcomes) = order_entry.submit(activity()).await else {
+            panic!("expected the first submission to be applied");
+        };
+        let Some(ActivityOutcome::Filled(first)) = outcomes.into_iter().next() else {
+            panic!("expected a fill");
+        };
+        let Submission::Duplicate(Some(ActivityOutcome::Filled(again))) = order_entry.submit(activity()).await else {
+            panic!("expected the recorded fill");
+        };
+        assert_eq!((again.activity_id, again.quantity, again.price), (first.activity_id, first.quantity, first.price));
+        assert_eq!(again.fees.total, first.fees.total);
+        assert_eq!(order_entry.stocks.lock().await["AAPL"].available_quantity, 990);
+
+        let _ = std::fs::remove_dir_all(journal_dir);
+    }
+}
@@ -0,0 +1,131 @@
+//! Incremental market data.
+//!
+//! Each symbol has its own sequence number, bumped whenever its price or
+//! available quantity changes. An `Update` carries only the fields that
+//! changed under the new sequence; a `Snapshot` carries the full state under
+//! the current sequence. Snapshots go out for every symbol on its first
+//! publish, every `snapshot_interval_cycles` cycles, and on request.
+//! Subscribers apply updates in sequence order and ask for a snapshot when
+//! they see a gap.
+
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use crate::money::Money;
+use crate::stock::Stock;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MarketData {
+    Update {
+        symbol: String,
+        sequence: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        price: Option<Money>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        available_quantity: Option<usize>,
+    },
+    Snapshot {
+        symbol: String,
+        sequence: u64,
+        price: Money,
+        available_quantity: usize,
+    },
+}
+
+impl MarketData {
+    pub fn symbol(&self) -> &str {
+        match self {
+            MarketData::Update { symbol, .. } | MarketData::Snapshot { symbol, .. } => symbol,
+        }
+    }
+}
+
+/// Sent by a subscriber that lost track of some symbols. An empty list asks
+/// for every symbol.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotRequest {
+    pub symbols: Vec<String>,
+}
+
+/// Last published state of one symbol.
+struct Published {
+    sequence: u64,
+    price: Money,
+    available_quantity: usize,
+}
+
+#[derive(Default)]
+pub struct MarketDataPublisher {
+    published: HashMap<String, Published>,
+}
+
+impl MarketDataPublisher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Messages for one market cycle: updates for the symbols that changed
+    /// since the last publish, or a snapshot of every symbol when `full`.
+    pub fn cycle(&mut self, stocks: &HashMap<String, Stock>, full: bool) -> Vec<MarketData> {
+        let mut messages = Vec::new();
+        for stock in stocks.values() {
+            let Some(published) = self.published.get_mut(&stock.id) else {
+                self.published.insert(
+                    stock.id.clone(),
+                    Published {
+                        sequence: 1,
+                        price: stock.price,
+                        available_quantity: stock.available_quantity,
+                    },
+                );
+                messages.push(snapshot(stock, 1));
+                continue;
+            };
+
+            let price = (published.price != stock.price).then_some(stock.price);
+            let available_quantity =
+                (published.available_quantity != stock.available_quantity).then_some(stock.available_quantity);
+            if price.is_some() || available_quantity.is_some() {
+                published.sequence += 1;
+                published.price = stock.price;
+                published.available_quantity = stock.available_quantity;
+                if !full {
+                    messages.push(MarketData::Update {
+                        symbol: stock.id.clone(),
+                        sequence: published.sequence,
+                        price,
+                        available_quantity,
+                    });
+                }
+            }
+            if full {
+                messages.push(snapshot(stock, published.sequence));
+            }
+        }
+        messages
+    }
+
+    /// Snapshots of `symbols` (every published symbol when empty) at their
+    /// current sequence. Symbols never published are skipped; their first
+    /// cycle sends a snapshot anyway.
+    pub fn snapshots(&self, stocks: &HashMap<String, Stock>, symbols: &[String]) -> Vec<MarketData> {
+        let snapshot_of = |symbol: &String| {
+            let published = self.published.get(symbol)?;
+            stocks.get(symbol).map(|stock| snapshot(stock, published.sequence))
+        };
+        if symbols.is_empty() {
+            self.published.keys().filter_map(snapshot_of).collect()
+        } else {
+            symbols.iter().filter_map(snapshot_of).collect()
+        }
+    }
+}
+
+fn snapshot(stock: &Stock, sequence: u64) -> MarketData {
+    MarketData::Snapshot {
+        symbol: stock.id.clone(),
+        sequence,
+        price: stock.price,
+        available_quantity: stock.available_quantity,
+    }
+}
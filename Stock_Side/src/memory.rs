@@ -0,0 +1,170 @@
+//! In-process message broker.
+//!
+//! `MemoryBroker` stands in for RabbitMQ when both sides run in one process
+//! or a test harness. It routes like the broker does for the exchanges we
+//! use: the default exchange delivers to the queue named by the routing key,
+//! direct and topic exchanges follow their bindings (`*` matches one word,
+//! `#` zero or more) and fanout exchanges deliver to every bound queue.
+//! Messages nobody is bound for are dropped. Consumers on the same queue
+//! compete for its messages. Delivery is immediate and never fails, so
+//! there is nothing to acknowledge.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use futures_util::stream::{self, BoxStream, StreamExt};
+use lapin::{BasicProperties, ExchangeKind};
+use log::debug;
+use tokio::sync::mpsc;
+
+#[derive(Debug, Clone)]
+pub struct MemoryMessage {
+    pub exchange: String,
+    pub routing_key: String,
+    pub data: Vec<u8>,
+    pub properties: BasicProperties,
+}
+
+struct MemoryQueue {
+    sender: mpsc::UnboundedSender<MemoryMessage>,
+    /// Shared by every consumer of the queue.
+    receiver: Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<MemoryMessage>>>,
+}
+
+impl MemoryQueue {
+    fn new() -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        MemoryQueue { sender, receiver: Arc::new(tokio::sync::Mutex::new(receiver)) }
+    }
+}
+
+struct Binding {
+    exchange: String,
+    routing_key: String,
+    queue: String,
+}
+
+#[derive(Default)]
+struct State {
+    exchanges: HashMap<String, ExchangeKind>,
+    queues: HashMap<String, MemoryQueue>,
+    bindings: Vec<Binding>,
+    private_queues: u64,
+}
+
+#[derive(Default)]
+pub struct MemoryBroker {
+    state: Mutex<State>,
+}
+
+impl MemoryBroker {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Declaring an existing exchange again keeps its original kind.
+    pub fn declare_exchange(&self, name: &str, kind: ExchangeKind) {
+        self.state.lock().unwrap().exchanges.entry(name.to_string()).or_insert(kind);
+    }
+
+    pub fn declare_queue(&self, name: &str) {
+        self.state.lock().unwrap().queues.entry(name.to_string()).or_insert_with(MemoryQueue::new);
+    }
+
+    /// Declares a queue under a fresh generated name and returns the name.
+    pub fn declare_private_queue(&self) -> String {
+        let mut state = self.state.lock().unwrap();
+        state.private_queues += 1;
+        let name = format!("memory.private.{}", state.private_queues);
+        state.queues.insert(name.clone(), MemoryQueue::new());
+        name
+    }
+
+    pub fn bind(&self, queue: &str, exchange: &str, routing_key: &str) {
+        let mut state = self.state.lock().unwrap();
+        let exists = state
+            .bindings
+            .iter()
+            .any(|binding| binding.queue == queue && binding.exchange == exchange && binding.routing_key == routing_key);
+        if !exists {
+            state.bindings.push(Binding {
+                exchange: exchange.to_string(),
+                routing_key: routing_key.to_string(),
+                queue: queue.to_string(),
+            });
+        }
+    }
+
+    /// Delivers a copy of the message to every queue `exchange` routes
+    /// `routing_key` to.
+    pub fn publish(&self, exchange: &str, routing_key: &str, data: Vec<u8>, properties: BasicProperties) {
+        let state = self.state.lock().unwrap();
+        let queues: Vec<&str> = if exchange.is_empty() {
+            vec![routing_key]
+        } else {
+            let Some(kind) = state.exchanges.get(exchange) else {
+                debug!("Dropped message for {} on undeclared exchange {}", routing_key, exchange);
+                return;
+            };
+            state
+                .bindings
+                .iter()
+                .filter(|binding| binding.exchange == exchange && routes(kind, &binding.routing_key, routing_key))
+                .map(|binding| binding.queue.as_str())
+                .collect()
+        };
+
+        for queue in queues {
+            let Some(queue) = state.queues.get(queue) else {
+                continue;
+            };
+            let message = MemoryMessage {
+                exchange: exchange.to_string(),
+                routing_key: routing_key.to_string(),
+                data: data.clone(),
+                properties: properties.clone(),
+            };
+            // The broker holds the receiver, so the queue cannot be closed
+            let _ = queue.sender.send(message);
+        }
+    }
+
+    /// Messages from `queue`, declaring it first if needed. The stream never
+    /// ends.
+    pub fn consume(&self, queue: &str) -> BoxStream<'static, MemoryMessage> {
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            let queue = state.queues.entry(queue.to_string()).or_insert_with(MemoryQueue::new);
+            Arc::clone(&queue.receiver)
+        };
+        stream::unfold(receiver, |receiver| async move {
+            let message = receiver.lock().await.recv().await?;
+            Some((message, receiver))
+        })
+        .boxed()
+    }
+}
+
+fn routes(kind: &ExchangeKind, binding_key: &str, routing_key: &str) -> bool {
+    match kind {
+        ExchangeKind::Direct => binding_key == routing_key,
+        ExchangeKind::Fanout => true,
+        ExchangeKind::Topic => {
+            let pattern: Vec<&str> = binding_key.split('.').collect();
+            let words: Vec<&str> = routing_key.split('.').collect();
+            topic_matches(&pattern, &words)
+        }
+        // Not used by either side
+        ExchangeKind::Headers | ExchangeKind::Custom(_) => false,
+    }
+}
+
+fn topic_matches(pattern: &[&str], words: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => words.is_empty(),
+        Some((&"#", rest)) => (0..=words.len()).any(|skip| topic_matches(rest, &words[skip..])),
+        Some((&word, rest)) => match words.split_first() {
+            Some((&first, remaining)) => (word == "*" || word == first) && topic_matches(rest, remaining),
+            None => false,
+        },
+    }
+}
@@ -0,0 +1,132 @@
+//! Exact decimal money amounts.
+//!
+//! Rounding rules:
+//! - Prices are rounded to the instrument's tick size, midpoints away from zero.
+//! - Cash amounts produced by a rate (fees, interest, percentages) or a
+//!   division (average costs) are rounded to whole cents, midpoints to even
+//!   (banker's rounding).
+//! - Price × quantity is exact and never rounded.
+//!
+//! Amounts are serialized as decimal strings (`"150.25"`) so no precision is
+//! lost on the wire.
+
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+pub const CENT_DECIMALS: u32 = 2;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(transparent)]
+#[schema(value_type = String, example = "150.25")]
+pub struct Money(Decimal);
+
+impl Money {
+    pub const ZERO: Money = Money(Decimal::ZERO);
+
+    pub fn new(amount: Decimal) -> Self {
+        Money(amount)
+    }
+
+    pub fn from_cents(cents: i64) -> Self {
+        Money(Decimal::new(cents, CENT_DECIMALS))
+    }
+
+    /// Converts a float, keeping at most four decimal places. Only meant for
+    /// literals and random draws, never for accumulated amounts.
+    pub fn from_f64(amount: f64) -> Self {
+        Money(
+            Decimal::from_f64(amount)
+                .unwrap_or_default()
+                .round_dp_with_strategy(4, RoundingStrategy::MidpointAwayFromZero),
+        )
+    }
+
+    pub fn amount(&self) -> Decimal {
+        self.0
+    }
+
+    /// Exact notional of `quantity` units at this price. Negative
+    /// quantities give the (negative) value of a short position.
+    pub fn times(&self, quantity: impl Into<Decimal>) -> Self {
+        Money(self.0 * quantity.into())
+    }
+
+    /// Multiplies by a rate and rounds the result to cents.
+    pub fn apply_rate(&self, rate: Decimal) -> Self {
+        Money(self.0 * rate).round_cents()
+    }
+
+    pub fn round_cents(&self) -> Self {
+        Money(
+            self.0
+                .round_dp_with_strategy(CENT_DECIMALS, RoundingStrategy::MidpointNearestEven),
+        )
+    }
+
+    /// Rounds to the nearest multiple of `tick`.
+    pub fn round_to_tick(&self, tick: Money) -> Self {
+        if tick.0.is_zero() {
+            return *self;
+        }
+        let ticks = (self.0 / tick.0)
+            .round_dp_with_strategy(0, RoundingStrategy::MidpointAwayFromZero);
+        Money(ticks * tick.0)
+    }
+
+    pub fn is_multiple_of(&self, tick: Money) -> bool {
+        !tick.0.is_zero() && (self.0 % tick.0).is_zero()
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+
+    fn add(self, other: Money) -> Money {
+        Money(self.0 + other.0)
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+
+    fn sub(self, other: Money) -> Money {
+        Money(self.0 - other.0)
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+
+    fn neg(self) -> Money {
+        Money(-self.0)
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, other: Money) {
+        self.0 += other.0;
+    }
+}
+
+impl SubAssign for Money {
+    fn sub_assign(&mut self, other: Money) {
+        self.0 -= other.0;
+    }
+}
+
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Money>>(iter: I) -> Money {
+        iter.fold(Money::ZERO, Add::add)
+    }
+}
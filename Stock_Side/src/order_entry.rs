@@ -0,0 +1,132 @@
+//! The one path every broker activity takes, whichever gateway it arrived
+//! through: look up the recorded outcome if its id was already applied,
+//! otherwise record the order, apply it to the stocks, journal the quantity
+//! change, record the outcome with its status and the applied mark, and put
+//! any trade on the feed. Reporting the outcome to the broker, again for a
+//! duplicate, is left to the gateway.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use log::{error, info, warn};
+use tokio::sync::Mutex;
+use crate::bars::BarAggregator;
+use crate::brokers::{process_broker_activities, ActivityOutcome, BrokerActivity};
+use crate::feed::{Feed, FeedEvent};
+use crate::fees::FeeSchedule;
+use crate::instrument::Instrument;
+use crate::journal::{Event, Journal};
+use crate::stock::Stock;
+use crate::store::Store;
+
+pub struct OrderEntry {
+    pub stocks: Arc<Mutex<HashMap<String, Stock>>>,
+    pub instruments: Arc<HashMap<String, Instrument>>,
+    pub fee_schedule: FeeSchedule,
+    pub store: Arc<Store>,
+    pub journal: Arc<Mutex<Journal>>,
+    pub bars: Arc<Mutex<BarAggregator>>,
+    pub feed: Arc<Feed>,
+}
+
+/// What `OrderEntry::submit` did with an activity.
+pub enum Submission {
+    /// Applied now, with these outcomes.
+    Applied(Vec<ActivityOutcome>),
+    /// Applied before under the same id, so left alone. Carries the outcome
+    /// recorded then, `None` if it cannot be found.
+    Duplicate(Option<ActivityOutcome>),
+}
+
+impl OrderEntry {
+    /// Applies `activity`, unless an activity with the same id was applied
+    /// before.
+    pub async fn submit(&self, activity: BrokerActivity) -> Submission {
+        let activity_id = activity.activity_id.clone();
+        let outcomes = {
+            // Checked under the stocks lock so two deliveries of one id
+            // cannot both be applied
+            let mut stocks_guard = self.stocks.lock().await;
+            match self.store.is_activity_processed(&activity_id) {
+                Ok(true) => {
+                    info!("Duplicate broker activity {}, reporting its recorded outcome", activity_id);
+                    return Submission::Duplicate(self.recorded_outcome(&activity_id));
+                }
+                Ok(false) => {}
+                Err(e) => error!("Failed to check for duplicate activity: {:?}", e),
+            }
+            if let Err(e) = self.store.record_order(&activity) {
+                error!("Failed to record order: {:?}", e);
+            }
+
+            let outcomes =
+                process_broker_activities(vec![activity], &mut stocks_guard, &self.instruments, &self.fee_schedule);
+
+            // Journal the new quantity before the fill is published
+            let mut journal_guard = self.journal.lock().await;
+            let mut bars_guard = self.bars.lock().await;
+            for outcome in &outcomes {
+                if let ActivityOutcome::Filled(fill) = outcome {
+                    bars_guard.record_fill(&fill.stock_id, fill.price, fill.quantity);
+                    let available_quantity = stocks_guard[&fill.stock_id].available_quantity;
+                    let event = Event::QuantityChanged { stock_id: fill.stock_id.clone(), available_quantity };
+                    if let Err(e) = journal_guard.append(event) {
+                        error!("Failed to journal quantity change: {:?}", e);
+                    }
+                }
+            }
+            if let Err(e) = self.store.record_outcomes(&activity_id, &outcomes) {
+                error!("Failed to record activity outcome: {:?}", e);
+            }
+            outcomes
+        };
+
+        for outcome in &outcomes {
+            if let ActivityOutcome::Filled(fill) = outcome {
+                self.feed.publish(FeedEvent::Trade(fill.into()));
+            }
+        }
+        Submission::Applied(outcomes)
+    }
+
+    fn recorded_outcome(&self, activity_id: &str) -> Option<ActivityOutcome> {
+        match self.store.recorded_outcome(activity_id) {
+            Ok(Some(outcome)) => Some(outcome),
+            Ok(None) => {
+                warn!("No recorded outcome for applied activity {}", activity_id);
+                None
+            }
+            Err(e) => {
+                error!("Failed to read recorded outcome of {}: {:?}", activity_id, e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl OrderEntry {
+    /// AAPL at $150.00 with 1,000 shares available, an in-memory store and a
+    /// journal in `journal_dir`.
+    pub(crate) fn for_tests(journal_dir: &std::path::Path) -> Arc<OrderEntry> {
+        use crate::bars::DEFAULT_BAR_INTERVALS;
+        use crate::instrument::initialize_instruments;
+        use crate::journal::DEFAULT_SNAPSHOT_INTERVAL;
+        use crate::money::Money;
+
+        let (journal, _) = Journal::open(journal_dir.to_str().unwrap(), DEFAULT_SNAPSHOT_INTERVAL).unwrap();
+        let stocks = HashMap::from([(
+            "AAPL".to_string(),
+            Stock { id: "AAPL".to_string(), price: Money::from_f64(150.0), available_quantity: 1000 },
+        )]);
+        let instruments = Arc::new(initialize_instruments(&stocks, &[]));
+        Arc::new(OrderEntry {
+            stocks: Arc::new(Mutex::new(stocks)),
+            instruments,
+            fee_schedule: FeeSchedule::default(),
+            store: Arc::new(Store::open(":memory:").unwrap()),
+            journal: Arc::new(Mutex::new(journal)),
+            bars: Arc::new(Mutex::new(BarAggregator::new(&DEFAULT_BAR_INTERVALS))),
+            feed: Arc::new(Feed::new()),
+        })
+    }
+}
@@ -0,0 +1,238 @@
+//! Historical price replay.
+//!
+//! Loads OHLCV bars from a CSV file with the header
+//! `timestamp,symbol,open,high,low,close,volume` and turns them into one
+//! price path per symbol, one price per market cycle. Bars longer than a
+//! cycle are interpolated through open, the nearer extreme, the farther
+//! extreme and close, so the path still visits the bar's high and low. Daily
+//! bars are spread across a whole session. Volume is not replayed; bar
+//! volume still comes from fills.
+
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use chrono::{NaiveDate, NaiveDateTime};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use crate::instrument::Instrument;
+use crate::money::Money;
+use crate::stock::Stock;
+
+const MINUTES_PER_DAY: i64 = 24 * 60;
+
+#[derive(Debug, Clone, Deserialize)]
+struct CsvBar {
+    timestamp: String,
+    symbol: String,
+    open: Money,
+    high: Money,
+    low: Money,
+    close: Money,
+}
+
+pub struct PriceReplay {
+    opening_prices: HashMap<String, Money>,
+    paths: HashMap<String, VecDeque<Money>>,
+}
+
+impl PriceReplay {
+    /// Reads `path` and builds price paths for the symbols in `stocks`.
+    /// Rows for other symbols are skipped.
+    pub fn from_csv(
+        path: &str,
+        stocks: &HashMap<String, Stock>,
+        cycle_minutes: i64,
+        cycles_per_session: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut bars: HashMap<String, Vec<(NaiveDateTime, CsvBar)>> = HashMap::new();
+        for row in csv::Reader::from_path(path)?.deserialize() {
+            let bar: CsvBar = row?;
+            if !stocks.contains_key(&bar.symbol) {
+                continue;
+            }
+            let timestamp = parse_timestamp(&bar.timestamp)
+                .ok_or_else(|| format!("invalid timestamp {:?} for {}", bar.timestamp, bar.symbol))?;
+            bars.entry(bar.symbol.clone()).or_default().push((timestamp, bar));
+        }
+
+        let mut opening_prices = HashMap::new();
+        let paths = bars
+            .into_iter()
+            .map(|(symbol, mut rows)| {
+                rows.sort_by_key(|(timestamp, _)| *timestamp);
+                opening_prices.insert(symbol.clone(), rows[0].1.open);
+                let bar_minutes = rows
+                    .windows(2)
+                    .map(|pair| (pair[1].0 - pair[0].0).num_minutes())
+                    .filter(|&minutes| minutes > 0)
+                    .min()
+                    .unwrap_or(cycle_minutes);
+                let ticks_per_bar = if bar_minutes >= MINUTES_PER_DAY {
+                    cycles_per_session
+                } else {
+                    (bar_minutes / cycle_minutes).max(1) as usize
+                };
+                let path = rows
+                    .iter()
+                    .flat_map(|(_, bar)| interpolate(bar, ticks_per_bar))
+                    .collect();
+                (symbol, path)
+            })
+            .collect();
+        Ok(PriceReplay { opening_prices, paths })
+    }
+
+    pub fn symbols(&self) -> impl Iterator<Item = &String> {
+        self.paths.keys()
+    }
+
+    /// Opening price of each replayed symbol's first bar.
+    pub fn opening_prices(&self) -> impl Iterator<Item = (&String, Money)> {
+        self.opening_prices.iter().map(|(symbol, &price)| (symbol, price))
+    }
+
+    /// Drops the first `cycles` prices of every path, e.g. when resuming a
+    /// session that had already replayed them.
+    pub fn skip(&mut self, cycles: usize) {
+        for path in self.paths.values_mut() {
+            path.drain(..cycles.min(path.len()));
+        }
+    }
+
+    /// Moves every replayed stock to its next price. Stocks without history
+    /// keep their price. Returns `false` once every path is exhausted.
+    pub fn apply_next_prices(
+        &mut self,
+        stocks: &mut HashMap<String, Stock>,
+        instruments: &HashMap<String, Instrument>,
+    ) -> bool {
+        let mut advanced = false;
+        for (symbol, path) in self.paths.iter_mut() {
+            let (Some(price), Some(stock)) = (path.pop_front(), stocks.get_mut(symbol)) else {
+                continue;
+            };
+            stock.price = match instruments.get(symbol) {
+                Some(instrument) => instrument.round_to_tick(price),
+                None => price.round_cents(),
+            };
+            advanced = true;
+        }
+        advanced
+    }
+}
+
+fn parse_timestamp(value: &str) -> Option<NaiveDateTime> {
+    let value = value.trim();
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+}
+
+/// `ticks` prices walking open -> nearer extreme -> farther extreme -> close,
+/// ending exactly on the close.
+fn interpolate(bar: &CsvBar, ticks: usize) -> Vec<Money> {
+    if ticks <= 1 {
+        return vec![bar.close];
+    }
+    let (first, second) = if bar.close >= bar.open {
+        (bar.low, bar.high)
+    } else {
+        (bar.high, bar.low)
+    };
+    let waypoints = [bar.open, first, second, bar.close].map(|price| price.amount());
+    let segments = Decimal::from(waypoints.len() - 1);
+
+    (1..=ticks)
+        .map(|tick| {
+            let position = Decimal::from(tick) / Decimal::from(ticks) * segments;
+            let segment = position.floor().min(segments - Decimal::ONE);
+            let index = segment.to_usize().unwrap_or(0);
+            let fraction = position - segment;
+            let (from, to) = (waypoints[index], waypoints[index + 1]);
+            Money::new(from + (to - from) * fraction)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    fn stocks() -> HashMap<String, Stock> {
+        HashMap::from([(
+            "AAPL".to_string(),
+            Stock { id: "AAPL".to_string(), price: Money::from_cents(10_000), available_quantity: 100 },
+        )])
+    }
+
+    /// Loads `csv` through a temporary file, with 30-minute cycles and four
+    /// cycles a session.
+    fn replay(test: &str, csv: &str) -> Result<PriceReplay, Box<dyn Error>> {
+        let path = env::temp_dir().join(format!("stock_side_replay_test_{}_{}.csv", test, process::id()));
+        fs::write(&path, csv).unwrap();
+        let replay = PriceReplay::from_csv(path.to_str().unwrap(), &stocks(), 30, 4);
+        let _ = fs::remove_file(path);
+        replay
+    }
+
+    fn path(replay: &PriceReplay, symbol: &str) -> Vec<Money> {
+        replay.paths[symbol].iter().copied().collect()
+    }
+
+    #[test]
+    fn replays_one_close_per_cycle_for_bars_as_long_as_a_cycle() {
+        let csv = "timestamp,symbol,open,high,low,close,volume\n\
+                   2024-01-02 10:00:00,AAPL,101,103,100,102,500\n\
+                   2024-01-02 09:30:00,AAPL,100,102,99,101,800\n\
+                   2024-01-02 09:30:00,MSFT,300,301,299,300,100\n";
+        let mut replay = replay("closes", csv).unwrap();
+
+        assert_eq!(replay.symbols().collect::<Vec<_>>(), ["AAPL"]);
+        assert_eq!(replay.opening_prices().collect::<Vec<_>>(), [(&"AAPL".to_string(), Money::from_cents(10_000))]);
+        assert_eq!(path(&replay, "AAPL"), [Money::from_cents(10_100), Money::from_cents(10_200)]);
+
+        let mut stocks = stocks();
+        assert!(replay.apply_next_prices(&mut stocks, &HashMap::new()));
+        assert_eq!(stocks["AAPL"].price, Money::from_cents(10_100));
+        replay.skip(1);
+        assert!(!replay.apply_next_prices(&mut stocks, &HashMap::new()));
+        assert_eq!(stocks["AAPL"].price, Money::from_cents(10_100));
+    }
+
+    #[test]
+    fn spreads_a_daily_bar_over_the_session_through_its_high_and_low() {
+        let csv = "timestamp,symbol,open,high,low,close,volume\n\
+                   2024-01-02,AAPL,100,110,96,104,1000\n\
+                   2024-01-03,AAPL,104,106,100,101,1000\n";
+        let replay = replay("daily", csv).unwrap();
+
+        let prices = path(&replay, "AAPL");
+        assert_eq!(prices.len(), 8);
+        assert_eq!((prices[3], prices[7]), (Money::from_cents(10_400), Money::from_cents(10_100)));
+        // Up day: open, low, high, close
+        assert_eq!(prices[0], Money::from_cents(9_700));
+        assert_eq!(prices[1], Money::from_cents(10_300));
+        assert_eq!(prices[2], Money::from_cents(10_850));
+    }
+
+    #[test]
+    fn rejects_malformed_rows() {
+        let header = "timestamp,symbol,open,high,low,close,volume\n";
+        let bad_price = format!("{}2024-01-02 09:30:00,AAPL,abc,102,99,101,800\n", header);
+        let bad_timestamp = format!("{}yesterday,AAPL,100,102,99,101,800\n", header);
+        let missing_column = format!("{}2024-01-02 09:30:00,AAPL,100,102\n", header);
+
+        assert!(replay("price", &bad_price).is_err());
+        let error = replay("timestamp", &bad_timestamp).err().unwrap();
+        assert!(error.to_string().contains("invalid timestamp"), "{}", error);
+        assert!(replay("columns", &missing_column).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+//! Request/reply queries on the `rpc_requests` queue: the current quote for
+//! a symbol, the status of an activity by id, and instrument reference data.
+//!
+//! Callers set `reply_to` and `correlation_id` on the request; the reply goes
+//! to `reply_to` through the default exchange, with the same correlation id
+//! and in the request's encoding. A request that cannot be decoded still gets
+//! an `Error` reply, so the caller fails fast instead of timing out. Only
+//! requests without a `reply_to` are dead-lettered, as nobody is waiting.
+
+use std::sync::Arc;
+use futures_util::StreamExt;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use crate::brokers::OrderStatus;
+use crate::codec::Encoding;
+use crate::instrument::Instrument;
+use crate::messaging::{consume_messages, dead_letter, DEAD_LETTERED_BY};
+use crate::money::Money;
+use crate::order_entry::OrderEntry;
+use crate::transport::{Delivery, Transport};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RpcRequest {
+    Quote { symbol: String },
+    OrderStatus { activity_id: String },
+    Instrument { symbol: String },
+}
+
+/// Machine-readable reason attached to every `Error` reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RpcErrorCode {
+    UnknownSymbol,
+    InvalidRequest,
+    Internal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Quote {
+    pub symbol: String,
+    pub price: Money,
+    pub available_quantity: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RpcReply {
+    Quote(Quote),
+    OrderStatus { activity_id: String, status: OrderStatus },
+    Instrument(Instrument),
+    Error { code: RpcErrorCode, message: String },
+}
+
+impl RpcReply {
+    fn error(code: RpcErrorCode, message: String) -> Self {
+        RpcReply::Error { code, message }
+    }
+}
+
+/// Answers queries until the process exits, resubscribing whenever the
+/// consumer stops.
+pub(crate) async fn serve(transport: Arc<Transport>, order_entry: Arc<OrderEntry>) {
+    loop {
+        let mut consumer = consume_messages(&transport, &transport.amqp().queues.rpc_requests, "rpc_consumer").await;
+
+        while let Some(delivery) = consumer.next().await {
+            let message = match delivery {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!("RPC request consumer failed: {:?}", e);
+                    break;
+                }
+            };
+            let Some(reply_to) = message.properties.reply_to().as_ref().map(|reply_to| reply_to.to_string()) else {
+                dead_letter(&transport, &message, DEAD_LETTERED_BY, "RPC request without reply_to").await;
+                continue;
+            };
+
+            let reply = match message.decode::<RpcRequest>() {
+                Ok(request) => answer(&order_entry, request).await,
+                Err(e) => RpcReply::error(RpcErrorCode::InvalidRequest, format!("invalid RPC request: {}", e)),
+            };
+            if let Err(e) = send_reply(&transport, &message, &reply_to, &reply).await {
+                error!("Failed to send RPC reply to {}: {:?}", reply_to, e);
+            }
+
+            if let Err(e) = message.ack().await {
+                error!("Failed to acknowledge message: {:?}", e);
+            }
+        }
+        warn!("RPC request consumer stopped, resubscribing");
+    }
+}
+
+async fn answer(order_entry: &OrderEntry, request: RpcRequest) -> RpcReply {
+    let unknown_symbol = |symbol: &str| RpcReply::error(RpcErrorCode::UnknownSymbol, format!("unknown symbol {}", symbol));
+    match request {
+        RpcRequest::Quote { symbol } => match order_entry.stocks.lock().await.get(&symbol) {
+            Some(stock) => RpcReply::Quote(Quote {
+                symbol,
+                price: stock.price,
+                available_quantity: stock.available_quantity,
+            }),
+            None => unknown_symbol(&symbol),
+        },
+        RpcRequest::OrderStatus { activity_id } => match order_entry.store.order_status(&activity_id) {
+            Ok(status) => RpcReply::OrderStatus { activity_id, status },
+            Err(e) => {
+                error!("Failed to read order status: {:?}", e);
+                RpcReply::error(RpcErrorCode::Internal, "failed to read order status".to_string())
+            }
+        },
+        RpcRequest::Instrument { symbol } => match order_entry.instruments.get(&symbol) {
+            Some(instrument) => RpcReply::Instrument(instrument.clone()),
+            None => unknown_symbol(&symbol),
+        },
+    }
+}
+
+/// Replies in the request's encoding, or JSON if that is not one we know.
+async fn send_reply(
+    transport: &Transport,
+    request: &Delivery,
+    reply_to: &str,
+    reply: &RpcReply,
+) -> Result<(), lapin::Error> {
+    let encoding = Encoding::of(&request.properties).unwrap_or_default();
+    let mut properties = encoding.properties();
+    if let Some(correlation_id) = request.properties.correlation_id() {
+        properties = properties.with_correlation_id(correlation_id.clone());
+    }
+    transport.publish_with("", reply_to, encoding.encode(reply), properties).await?;
+    if let RpcReply::Error { code, message } = reply {
+        info!("Answered RPC request with {:?}: {}", code, message);
+    }
+    Ok(())
+}
@@ -4,21 +4,33 @@ use serde::{Deserialize, Serialize};
 // use tokio::sync::Mutex;
 // use std::sync::Arc;
 use rand::Rng;
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+use utoipa::ToSchema;
+use crate::instrument::Instrument;
+use crate::money::Money;
 
-pub fn apply_price_fluctuations(stocks: &mut HashMap<String, Stock>) {
+pub fn apply_price_fluctuations(
+    stocks: &mut HashMap<String, Stock>,
+    instruments: &HashMap<String, Instrument>,
+) {
     let mut rng = rand::thread_rng();
     for stock in stocks.values_mut() {
         // Random price change between -5% and +5%
-        let change_percent = rng.gen_range(-5.0..=5.0);
-        let price_change = stock.price * (change_percent / 100.0);
-        stock.price = (stock.price + price_change).max(1.0);
+        let change_percent: f64 = rng.gen_range(-5.0..=5.0);
+        let factor = Decimal::ONE + Decimal::from_f64(change_percent / 100.0).unwrap_or_default();
+        let new_price = Money::new(stock.price.amount() * factor).max(Money::from_cents(100));
+        stock.price = match instruments.get(&stock.id) {
+            Some(instrument) => instrument.round_to_tick(new_price),
+            None => new_price.round_cents(),
+        };
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Stock {
     pub id: String,
-    pub price: f64,
+    pub price: Money,
     pub available_quantity: usize,
 }
 
@@ -44,7 +56,7 @@ pub fn initialize_stocks() -> HashMap<String, Stock> {
                 id.to_string(),
                 Stock {
                     id: id.to_string(),
-                    price,
+                    price: Money::from_f64(price),
                     available_quantity: 100,
                 },
             )
@@ -0,0 +1,599 @@
+//! SQLite record of a Stock_Side session.
+//!
+//! Every run inserts a row into `sessions`; all other rows carry that
+//! `session_id`. Money columns are decimal strings so they round-trip exactly,
+//! timestamps are RFC 3339 UTC and `market_time` is the simulated clock
+//! (`HH:MM`).
+
+use std::str::FromStr;
+use std::sync::Mutex;
+use chrono::{NaiveTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use rusqlite::types::Type;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use utoipa::ToSchema;
+use crate::bars::Bar;
+use crate::brokers::{ActivityOutcome, ActivityRejection, BrokerActivity, Fill, OrderStatus, RejectReason};
+use crate::fees::{FeeBreakdown, Liquidity};
+use crate::export::{self, Column, Value};
+use crate::money::Money;
+use crate::stock::Stock;
+
+pub const DEFAULT_DATABASE_PATH: &str = "stock_side.db";
+
+pub const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS sessions (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    started_at  TEXT NOT NULL
+);
+
+-- Broker activities as received, before validation
+CREATE TABLE IF NOT EXISTS orders (
+    id           INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_id   INTEGER NOT NULL REFERENCES sessions(id),
+    activity_id  TEXT,
+    received_at  TEXT NOT NULL,
+    broker_id    INTEGER NOT NULL,
+    stock_id     TEXT NOT NULL,
+    action       TEXT NOT NULL,
+    quantity     INTEGER NOT NULL,
+    price        TEXT
+);
+
+CREATE TABLE IF NOT EXISTS fills (
+    id            INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_id    INTEGER NOT NULL REFERENCES sessions(id),
+    activity_id   TEXT,
+    filled_at     TEXT NOT NULL,
+    broker_id     INTEGER NOT NULL,
+    stock_id      TEXT NOT NULL,
+    action        TEXT NOT NULL,
+    quantity      INTEGER NOT NULL,
+    price         TEXT NOT NULL,
+    liquidity     TEXT NOT NULL,
+    commission    TEXT NOT NULL,
+    exchange_fee  TEXT NOT NULL,
+    total_fee     TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS rejections (
+    id           INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_id   INTEGER NOT NULL REFERENCES sessions(id),
+    activity_id  TEXT,
+    rejected_at  TEXT NOT NULL,
+    broker_id    INTEGER NOT NULL,
+    stock_id     TEXT NOT NULL,
+    action       TEXT NOT NULL,
+    quantity     INTEGER NOT NULL,
+    price        TEXT,
+    reason       TEXT NOT NULL
+);
+
+-- One row per stock per market cycle
+CREATE TABLE IF NOT EXISTS price_ticks (
+    id                  INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_id          INTEGER NOT NULL REFERENCES sessions(id),
+    market_time         TEXT NOT NULL,
+    stock_id            TEXT NOT NULL,
+    price               TEXT NOT NULL,
+    available_quantity  INTEGER NOT NULL
+);
+
+-- Completed OHLCV bars; `interval` is 1m, 5m, 30m or 1d and `start_time`
+-- the market time the bar's bucket opens
+CREATE TABLE IF NOT EXISTS bars (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_id  INTEGER NOT NULL REFERENCES sessions(id),
+    stock_id    TEXT NOT NULL,
+    interval    TEXT NOT NULL,
+    start_time  TEXT NOT NULL,
+    open        TEXT NOT NULL,
+    high        TEXT NOT NULL,
+    low         TEXT NOT NULL,
+    close       TEXT NOT NULL,
+    volume      INTEGER NOT NULL
+);
+
+-- Activity ids already applied, so redeliveries are skipped across restarts
+CREATE TABLE IF NOT EXISTS processed_activities (
+    activity_id   TEXT PRIMARY KEY,
+    session_id    INTEGER NOT NULL REFERENCES sessions(id),
+    processed_at  TEXT NOT NULL
+);
+
+-- Final state of each applied activity, for order status queries
+CREATE TABLE IF NOT EXISTS order_statuses (
+    activity_id  TEXT PRIMARY KEY,
+    session_id   INTEGER NOT NULL REFERENCES sessions(id),
+    state        TEXT NOT NULL,
+    quantity     INTEGER,
+    price        TEXT,
+    reason       TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_fills_session_broker ON fills(session_id, broker_id);
+CREATE INDEX IF NOT EXISTS idx_price_ticks_session_stock ON price_ticks(session_id, stock_id);
+CREATE INDEX IF NOT EXISTS idx_bars_session_stock ON bars(session_id, stock_id, interval);
+";
+
+/// Built after `activity_id` is added to the tables of older databases.
+const ACTIVITY_ID_INDEXES: &str = "
+CREATE INDEX IF NOT EXISTS idx_orders_activity ON orders(activity_id);
+CREATE INDEX IF NOT EXISTS idx_fills_activity ON fills(activity_id);
+CREATE INDEX IF NOT EXISTS idx_rejections_activity ON rejections(activity_id);
+";
+
+const ACTIVITY_ID_TABLES: [&str; 3] = ["orders", "fills", "rejections"];
+
+/// A fill as the tape shows it, without the broker or fees.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TradeRecord {
+    /// RFC 3339 UTC.
+    pub filled_at: String,
+    pub symbol: String,
+    pub action: String,
+    pub quantity: usize,
+    pub price: Money,
+}
+
+pub struct Store {
+    conn: Mutex<Connection>,
+    session_id: i64,
+}
+
+impl Store {
+    /// Opens (or creates) the database and starts a new session.
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        // Older databases lack `activity_id`, and the indexes on it with it
+        for table in ACTIVITY_ID_TABLES {
+            add_missing_column(&conn, table, "activity_id", "TEXT")?;
+        }
+        conn.execute_batch(ACTIVITY_ID_INDEXES)?;
+        conn.execute(
+            "INSERT INTO sessions (started_at) VALUES (?1)",
+            params![Utc::now().to_rfc3339()],
+        )?;
+        let session_id = conn.last_insert_rowid();
+        Ok(Store {
+            conn: Mutex::new(conn),
+            session_id,
+        })
+    }
+
+    pub fn session_id(&self) -> i64 {
+        self.session_id
+    }
+
+    pub fn record_order(&self, activity: &BrokerActivity) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO orders (session_id, activity_id, received_at, broker_id, stock_id, action, quantity, price)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                self.session_id,
+                activity.activity_id,
+                Utc::now().to_rfc3339(),
+                activity.broker_id,
+                activity.stock_id,
+                activity.action,
+                activity.quantity as i64,
+                activity.price.map(|price| price.to_string()),
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn record_price_ticks<'a>(
+        &self,
+        market_time: NaiveTime,
+        stocks: impl Iterator<Item = &'a Stock>,
+    ) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        {
+            let mut statement = tx.prepare(
+                "INSERT INTO price_ticks (session_id, market_time, stock_id, price, available_quantity)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            let market_time = market_time.format("%H:%M").to_string();
+            for stock in stocks {
+                statement.execute(params![
+                    self.session_id,
+                    market_time,
+                    stock.id,
+                    stock.price.to_string(),
+                    stock.available_quantity as i64,
+                ])?;
+            }
+        }
+        tx.commit()
+    }
+
+    pub fn record_bars(&self, bars: &[Bar]) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        {
+            let mut statement = tx.prepare(
+                "INSERT INTO bars (session_id, stock_id, interval, start_time, open, high, low, close, volume)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )?;
+            for bar in bars {
+                statement.execute(params![
+                    self.session_id,
+                    bar.symbol,
+                    bar.interval.to_string(),
+                    bar.start.format("%H:%M").to_string(),
+                    bar.open.to_string(),
+                    bar.high.to_string(),
+                    bar.low.to_string(),
+                    bar.close.to_string(),
+                    bar.volume as i64,
+                ])?;
+            }
+        }
+        tx.commit()
+    }
+
+    pub fn is_activity_processed(&self, activity_id: &str) -> rusqlite::Result<bool> {
+        self.conn.lock().unwrap().query_row(
+            "SELECT EXISTS (SELECT 1 FROM processed_activities WHERE activity_id = ?1)",
+            params![activity_id],
+            |row| row.get(0),
+        )
+    }
+
+    /// Records what became of `activity_id` in one transaction: its fill or
+    /// rejection, its status, and the mark that it was applied. A restart
+    /// therefore never finds an activity marked applied without the outcome
+    /// to report for it.
+    pub fn record_outcomes(&self, activity_id: &str, outcomes: &[ActivityOutcome]) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let now = Utc::now().to_rfc3339();
+        for outcome in outcomes {
+            match outcome {
+                ActivityOutcome::Filled(fill) => tx.execute(
+                    "INSERT INTO fills (session_id, activity_id, filled_at, broker_id, stock_id, action, quantity,
+                                        price, liquidity, commission, exchange_fee, total_fee)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                    params![
+                        self.session_id,
+                        fill.activity_id,
+                        now,
+                        fill.broker_id,
+                        fill.stock_id,
+                        fill.action,
+                        fill.quantity as i64,
+                        fill.price.to_string(),
+                        fill.liquidity.to_string(),
+                        fill.fees.commission.to_string(),
+                        fill.fees.exchange_fee.to_string(),
+                        fill.fees.total.to_string(),
+                    ],
+                )?,
+                ActivityOutcome::Rejected(rejection) => tx.execute(
+                    "INSERT INTO rejections (session_id, activity_id, rejected_at, broker_id, stock_id, action,
+                                             quantity, price, reason)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                    params![
+                        self.session_id,
+                        rejection.activity_id,
+                        now,
+                        rejection.broker_id,
+                        rejection.stock_id,
+                        rejection.action,
+                        rejection.quantity as i64,
+                        rejection.price.map(|price| price.to_string()),
+                        rejection.reason.code(),
+                    ],
+                )?,
+            };
+            let (state, quantity, price, reason) = match OrderStatus::from(outcome) {
+                OrderStatus::Filled { quantity, price } => ("filled", Some(quantity as i64), Some(price.to_string()), None),
+                OrderStatus::Rejected { reason } => ("rejected", None, None, Some(reason.code())),
+                OrderStatus::Unknown => continue,
+            };
+            tx.execute(
+                "INSERT OR REPLACE INTO order_statuses (activity_id, session_id, state, quantity, price, reason)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![activity_id, self.session_id, state, quantity, price, reason],
+            )?;
+        }
+        tx.execute(
+            "INSERT OR IGNORE INTO processed_activities (activity_id, session_id, processed_at)
+             VALUES (?1, ?2, ?3)",
+            params![activity_id, self.session_id, now],
+        )?;
+        tx.commit()
+    }
+
+    /// The fill or rejection recorded for `activity_id` in any session, to
+    /// report again when the activity is sent twice. `None` if it was never
+    /// applied, or was applied before outcomes carried their activity id.
+    pub fn recorded_outcome(&self, activity_id: &str) -> rusqlite::Result<Option<ActivityOutcome>> {
+        let conn = self.conn.lock().unwrap();
+        let fill = conn
+            .query_row(
+                "SELECT broker_id, stock_id, action, quantity, price, liquidity, commission, exchange_fee, total_fee
+                 FROM fills WHERE activity_id = ?1 ORDER BY id DESC LIMIT 1",
+                params![activity_id],
+                |row| {
+                    let liquidity: String = row.get(5)?;
+                    let liquidity = Liquidity::from_str(&liquidity)
+                        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(5, Type::Text, e.into()))?;
+                    Ok(Fill {
+                        activity_id: activity_id.to_string(),
+                        broker_id: row.get(0)?,
+                        stock_id: row.get(1)?,
+                        action: row.get(2)?,
+                        quantity: row.get::<_, i64>(3)? as usize,
+                        price: money_column(row, 4)?,
+                        liquidity,
+                        fees: FeeBreakdown {
+                            commission: money_column(row, 6)?,
+                            exchange_fee: money_column(row, 7)?,
+                            total: money_column(row, 8)?,
+                        },
+                    })
+                },
+            )
+            .optional()?;
+        if let Some(fill) = fill {
+            return Ok(Some(ActivityOutcome::Filled(fill)));
+        }
+        let rejection = conn
+            .query_row(
+                "SELECT broker_id, stock_id, action, quantity, price, reason
+                 FROM rejections WHERE activity_id = ?1 ORDER BY id DESC LIMIT 1",
+                params![activity_id],
+                |row| {
+                    let price = match row.get::<_, Option<String>>(4)? {
+                        Some(_) => Some(money_column(row, 4)?),
+                        None => None,
+                    };
+                    let code: String = row.get(5)?;
+                    let reason = RejectReason::from_code(&code).ok_or_else(|| {
+                        rusqlite::Error::FromSqlConversionFailure(5, Type::Text, format!("unknown reason {}", code).into())
+                    })?;
+                    Ok(ActivityRejection {
+                        activity_id: activity_id.to_string(),
+                        broker_id: row.get(0)?,
+                        stock_id: row.get(1)?,
+                        action: row.get(2)?,
+                        quantity: row.get::<_, i64>(3)? as usize,
+                        price,
+                        reason,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(rejection.map(ActivityOutcome::Rejected))
+    }
+
+    /// The recorded status of `activity_id` from any session, `Unknown` if
+    /// it was never applied.
+    pub fn order_status(&self, activity_id: &str) -> rusqlite::Result<OrderStatus> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement =
+            conn.prepare("SELECT state, quantity, price, reason FROM order_statuses WHERE activity_id = ?1")?;
+        let mut rows = statement.query(params![activity_id])?;
+        let Some(row) = rows.next()? else {
+            return Ok(OrderStatus::Unknown);
+        };
+        let state: String = row.get(0)?;
+        match state.as_str() {
+            "filled" => Ok(OrderStatus::Filled {
+                quantity: row.get::<_, i64>(1)? as usize,
+                price: money_column(row, 2)?,
+            }),
+            "rejected" => {
+                let code: String = row.get(3)?;
+                let reason = RejectReason::from_code(&code).ok_or_else(|| {
+                    rusqlite::Error::FromSqlConversionFailure(3, Type::Text, format!("unknown reason {}", code).into())
+                })?;
+                Ok(OrderStatus::Rejected { reason })
+            }
+            other => Err(rusqlite::Error::FromSqlConversionFailure(
+                0,
+                Type::Text,
+                format!("unknown order state {}", other).into(),
+            )),
+        }
+    }
+
+    /// This session's fills of `stock_id`, newest first, at most `limit`.
+    pub fn trades(&self, stock_id: &str, limit: usize) -> rusqlite::Result<Vec<TradeRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(
+            "SELECT filled_at, stock_id, action, quantity, price FROM fills
+             WHERE session_id = ?1 AND stock_id = ?2 ORDER BY id DESC LIMIT ?3",
+        )?;
+        let rows = statement.query_map(params![self.session_id, stock_id, limit as i64], |row| {
+            Ok(TradeRecord {
+                filled_at: row.get(0)?,
+                symbol: row.get(1)?,
+                action: row.get(2)?,
+                quantity: row.get::<_, i64>(3)? as usize,
+                price: money_column(row, 4)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Runs `query` with this session's id bound as `?1`; see
+    /// `export::session_rows`.
+    pub fn session_rows(&self, query: &str, columns: &[Column]) -> rusqlite::Result<Vec<Vec<Value>>> {
+        export::session_rows(&self.conn.lock().unwrap(), self.session_id, query, columns)
+    }
+}
+
+/// Adds `column` to `table` in a database created before the table had it;
+/// existing rows get `definition`'s default. Does nothing if it is there.
+pub fn add_missing_column(conn: &Connection, table: &str, column: &str, definition: &str) -> rusqlite::Result<()> {
+    let mut statement = conn.prepare(&format!("SELECT 1 FROM pragma_table_info('{}') WHERE name = ?1", table))?;
+    if !statement.exists(params![column])? {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), [])?;
+    }
+    Ok(())
+}
+
+/// A decimal string column as `Money`.
+fn money_column(row: &Row, index: usize) -> rusqlite::Result<Money> {
+    let amount: String = row.get(index)?;
+    let amount = Decimal::from_str(&amount)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(e)))?;
+    Ok(Money::new(amount))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{column, nullable, ColumnType};
+    use std::env;
+    use std::process;
+
+    fn fill(activity_id: &str) -> Fill {
+        Fill {
+            activity_id: activity_id.to_string(),
+            broker_id: 7,
+            stock_id: "AAPL".to_string(),
+            action: "Buy".to_string(),
+            quantity: 10,
+            price: Money::from_f64(150.25),
+            liquidity: Liquidity::Maker,
+            fees: FeeBreakdown {
+                commission: Money::from_f64(1.0),
+                exchange_fee: Money::from_f64(-0.02),
+                total: Money::from_f64(0.98),
+            },
+        }
+    }
+
+    #[test]
+    fn recorded_outcomes_are_found_by_activity_id() {
+        let store = Store::open(":memory:").unwrap();
+        let rejection = ActivityRejection {
+            activity_id: "b".to_string(),
+            broker_id: 7,
+            stock_id: "AAPL".to_string(),
+            action: "Sell".to_string(),
+            quantity: 5,
+            price: None,
+            reason: RejectReason::InvalidLotSize,
+        };
+        store.record_outcomes("a", &[ActivityOutcome::Filled(fill("a"))]).unwrap();
+        store.record_outcomes("b", &[ActivityOutcome::Rejected(rejection)]).unwrap();
+
+        assert!(store.is_activity_processed("a").unwrap());
+        let Some(ActivityOutcome::Filled(recorded)) = store.recorded_outcome("a").unwrap() else {
+            panic!("expected the fill");
+        };
+        assert_eq!((recorded.quantity, recorded.price, recorded.liquidity), (10, Money::from_f64(150.25), Liquidity::Maker));
+        assert_eq!(recorded.fees.exchange_fee, Money::from_f64(-0.02));
+        assert_eq!(store.order_status("a").unwrap(), OrderStatus::Filled { quantity: 10, price: Money::from_f64(150.25) });
+
+        let Some(ActivityOutcome::Rejected(recorded)) = store.recorded_outcome("b").unwrap() else {
+            panic!("expected the rejection");
+        };
+        assert_eq!((recorded.reason, recorded.price), (RejectReason::InvalidLotSize, None));
+        assert!(store.recorded_outcome("c").unwrap().is_none());
+        assert!(!store.is_activity_processed("c").unwrap());
+    }
+
+    #[test]
+    fn orders_trades_and_prices_read_back_for_the_session() {
+        let store = Store::open(":memory:").unwrap();
+        let activity = BrokerActivity {
+            activity_id: "a".to_string(),
+            broker_id: 7,
+            stock_id: "AAPL".to_string(),
+            action: "Buy".to_string(),
+            quantity: 10,
+            price: Some(Money::from_f64(150.25)),
+        };
+        store.record_order(&activity).unwrap();
+        store.record_outcomes("a", &[ActivityOutcome::Filled(fill("a"))]).unwrap();
+        let stock = Stock { id: "AAPL".to_string(), price: Money::from_f64(150.5), available_quantity: 90 };
+        let open = NaiveTime::from_hms_opt(9, 30, 0).unwrap();
+        store.record_price_ticks(open, [&stock].into_iter()).unwrap();
+
+        let orders = store
+            .session_rows(
+                "SELECT activity_id, quantity, price FROM orders WHERE session_id = ?1",
+                &[
+                    column("activity_id", ColumnType::Text),
+                    column("quantity", ColumnType::Integer),
+                    nullable("price", ColumnType::Decimal),
+                ],
+            )
+            .unwrap();
+        assert_eq!(orders.len(), 1);
+        assert!(matches!(
+            orders[0].as_slice(),
+            [Value::Text(id), Value::Integer(10), Value::Decimal(price)] if id == "a" && *price == Decimal::new(15025, 2)
+        ));
+
+        let trades = store.trades("AAPL", 10).unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!((trades[0].action.as_str(), trades[0].quantity, trades[0].price), ("Buy", 10, Money::from_f64(150.25)));
+        assert!(store.trades("MSFT", 10).unwrap().is_empty());
+
+        let prices = store
+            .session_rows(
+                "SELECT market_time, price, available_quantity FROM price_ticks WHERE session_id = ?1",
+                &[
+                    column("market_time", ColumnType::Text),
+                    column("price", ColumnType::Decimal),
+                    column("available_quantity", ColumnType::Integer),
+                ],
+            )
+            .unwrap();
+        assert_eq!(prices.len(), 1);
+        assert!(matches!(
+            prices[0].as_slice(),
+            [Value::Text(time), Value::Decimal(price), Value::Integer(90)] if time == "09:30" && *price == Decimal::new(1505, 1)
+        ));
+    }
+
+    #[test]
+    fn each_open_starts_a_new_session() {
+        let path = env::temp_dir().join(format!("stock_side_store_sessions_test_{}.db", process::id()));
+        let _ = std::fs::remove_file(&path);
+        let first = Store::open(path.to_str().unwrap()).unwrap();
+        first.record_outcomes("a", &[ActivityOutcome::Filled(fill("a"))]).unwrap();
+        let second = Store::open(path.to_str().unwrap()).unwrap();
+
+        assert_eq!(second.session_id(), first.session_id() + 1);
+        assert!(second.trades("AAPL", 10).unwrap().is_empty());
+        // Outcomes are kept across sessions so redeliveries stay duplicates
+        assert!(second.is_activity_processed("a").unwrap());
+
+        drop((first, second));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn opening_an_older_database_adds_activity_ids() {
+        let path = env::temp_dir().join(format!("stock_side_store_test_{}.db", process::id()));
+        let _ = std::fs::remove_file(&path);
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE fills (id INTEGER PRIMARY KEY AUTOINCREMENT, session_id INTEGER NOT NULL,
+                                     filled_at TEXT NOT NULL, broker_id INTEGER NOT NULL, stock_id TEXT NOT NULL,
+                                     action TEXT NOT NULL, quantity INTEGER NOT NULL, price TEXT NOT NULL,
+                                     liquidity TEXT NOT NULL, commission TEXT NOT NULL,
+                                     exchange_fee TEXT NOT NULL, total_fee TEXT NOT NULL);",
+            )
+            .unwrap();
+
+        let store = Store::open(path.to_str().unwrap()).unwrap();
+        store.record_outcomes("a", &[ActivityOutcome::Filled(fill("a"))]).unwrap();
+        assert!(matches!(store.recorded_outcome("a").unwrap(), Some(ActivityOutcome::Filled(_))));
+
+        drop(store);
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,236 @@
+//! Where messages go: a supervised RabbitMQ link, or an in-process
+//! `MemoryBroker` shared with Trading_Side so both sides can run together
+//! without a broker. Both declare the same topology and expose the same
+//! publish and consume calls; `messaging` only talks to a `Transport`.
+
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use futures_util::stream::{BoxStream, StreamExt};
+use lapin::acker::Acker;
+use lapin::options::BasicAckOptions;
+use lapin::{BasicProperties, ExchangeKind};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::codec::{self, DecodeError};
+use crate::config::AmqpConfig;
+use crate::link::{AmqpLink, HealthStatus, LinkState, Published};
+use crate::memory::{MemoryBroker, MemoryMessage};
+
+/// A received message from either transport.
+pub struct Delivery {
+    pub exchange: String,
+    pub routing_key: String,
+    pub data: Vec<u8>,
+    pub properties: BasicProperties,
+    /// `None` for in-memory messages, which need no ack.
+    acker: Option<Acker>,
+}
+
+impl Delivery {
+    /// Decodes the payload according to its content type.
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, DecodeError> {
+        codec::decode(&self.properties, &self.data)
+    }
+
+    pub async fn ack(&self) -> Result<(), lapin::Error> {
+        match &self.acker {
+            Some(acker) => acker.ack(BasicAckOptions::default()).await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl From<lapin::message::Delivery> for Delivery {
+    fn from(delivery: lapin::message::Delivery) -> Self {
+        Delivery {
+            exchange: delivery.exchange.to_string(),
+            routing_key: delivery.routing_key.to_string(),
+            data: delivery.data,
+            properties: delivery.properties,
+            acker: Some(delivery.acker),
+        }
+    }
+}
+
+impl From<MemoryMessage> for Delivery {
+    fn from(message: MemoryMessage) -> Self {
+        Delivery {
+            exchange: message.exchange,
+            routing_key: message.routing_key,
+            data: message.data,
+            properties: message.properties,
+            acker: None,
+        }
+    }
+}
+
+/// Deliveries from one consumer. The stream ends, or yields an error, when
+/// the underlying channel is lost.
+pub type Deliveries = BoxStream<'static, Result<Delivery, lapin::Error>>;
+
+pub enum Transport {
+    Amqp(Arc<AmqpLink>),
+    Memory(Box<MemoryTransport>),
+}
+
+pub struct MemoryTransport {
+    broker: Arc<MemoryBroker>,
+    amqp: AmqpConfig,
+    since: DateTime<Utc>,
+}
+
+impl Transport {
+    /// Starts a supervised RabbitMQ link; see `AmqpLink::start`.
+    pub fn start_amqp(amqp: AmqpConfig) -> Arc<Self> {
+        Arc::new(Transport::Amqp(AmqpLink::start(amqp)))
+    }
+
+    /// Uses `broker` in place of RabbitMQ, declaring the same topology on it.
+    pub fn in_memory(broker: Arc<MemoryBroker>, amqp: AmqpConfig) -> Arc<Self> {
+        declare_topology(&broker, &amqp);
+        Arc::new(Transport::Memory(Box::new(MemoryTransport { broker, amqp, since: Utc::now() })))
+    }
+
+    pub fn amqp(&self) -> &AmqpConfig {
+        match self {
+            Transport::Amqp(link) => link.amqp(),
+            Transport::Memory(memory) => &memory.amqp,
+        }
+    }
+
+    /// The in-memory transport is always connected.
+    pub fn health(&self) -> HealthStatus {
+        match self {
+            Transport::Amqp(link) => link.health(),
+            Transport::Memory(memory) => HealthStatus {
+                state: LinkState::Connected,
+                since: memory.since,
+                reconnects: 0,
+                buffered: 0,
+                dropped: 0,
+                last_error: None,
+            },
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        match self {
+            Transport::Amqp(link) => link.is_connected(),
+            Transport::Memory(_) => true,
+        }
+    }
+
+    /// Publishes `message` to `routing_key` on the configured exchange.
+    pub async fn publish(&self, routing_key: &str, message: &impl Serialize) -> Result<Published, lapin::Error> {
+        self.publish_to(&self.amqp().exchange, routing_key, message).await
+    }
+
+    /// Publishes `message` in the configured encoding, labelled with its
+    /// content type.
+    pub async fn publish_to(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        message: &impl Serialize,
+    ) -> Result<Published, lapin::Error> {
+        let encoding = self.amqp().encoding;
+        self.publish_with(exchange, routing_key, encoding.encode(message), encoding.properties()).await
+    }
+
+    /// See `AmqpLink::publish_with`. The in-memory broker delivers at once,
+    /// so its messages are always `Confirmed`.
+    pub async fn publish_with(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        payload: Vec<u8>,
+        properties: BasicProperties,
+    ) -> Result<Published, lapin::Error> {
+        match self {
+            Transport::Amqp(link) => link.publish_with(exchange, routing_key, payload, properties).await,
+            Transport::Memory(memory) => {
+                memory.broker.publish(exchange, routing_key, payload, properties);
+                Ok(Published::Confirmed)
+            }
+        }
+    }
+
+    /// Starts consuming `queue`, waiting for the link if it is down. Call
+    /// again after the returned stream ends.
+    pub async fn consumer(&self, queue: &str, consumer_tag: &str) -> Deliveries {
+        match self {
+            Transport::Amqp(link) => link
+                .consumer(queue, consumer_tag)
+                .await
+                .map(|delivery| delivery.map(Delivery::from))
+                .boxed(),
+            Transport::Memory(memory) => memory.broker.consume(queue).map(|message| Ok(message.into())).boxed(),
+        }
+    }
+
+    /// Consumes a private queue bound to `exchange` under `binding_keys`;
+    /// see `AmqpLink::subscriber`.
+    pub async fn subscriber(&self, exchange: &str, binding_keys: &[String], consumer_tag: &str) -> Deliveries {
+        self.private_queue(exchange, binding_keys, consumer_tag).await.1
+    }
+
+    /// Consumes a private queue with no bindings, reachable only by name
+    /// through the default exchange, for replies. Returns the queue's name,
+    /// which changes each time the link reconnects.
+    pub async fn reply_queue(&self, consumer_tag: &str) -> (String, Deliveries) {
+        self.private_queue("", &[], consumer_tag).await
+    }
+
+    async fn private_queue(&self, exchange: &str, binding_keys: &[String], consumer_tag: &str) -> (String, Deliveries) {
+        match self {
+            Transport::Amqp(link) => {
+                let (queue, consumer) = link.subscriber(exchange, binding_keys, consumer_tag).await;
+                (queue, consumer.map(|delivery| delivery.map(Delivery::from)).boxed())
+            }
+            Transport::Memory(memory) => {
+                let queue = memory.broker.declare_private_queue();
+                for key in binding_keys {
+                    memory.broker.bind(&queue, exchange, key);
+                }
+                let deliveries = memory.broker.consume(&queue).map(|message| Ok(message.into())).boxed();
+                (queue, deliveries)
+            }
+        }
+    }
+}
+
+/// The in-memory counterpart of `link::declare_topology`.
+fn declare_topology(broker: &MemoryBroker, amqp: &AmqpConfig) {
+    broker.declare_exchange(&amqp.market_data_exchange, ExchangeKind::Topic);
+    broker.declare_exchange(&amqp.dead_letter_exchange, ExchangeKind::Fanout);
+    broker.declare_queue(&amqp.dead_letter_queue);
+    broker.bind(&amqp.dead_letter_queue, &amqp.dead_letter_exchange, "");
+    if !amqp.exchange.is_empty() {
+        broker.declare_exchange(&amqp.exchange, ExchangeKind::Direct);
+    }
+
+    for queue in amqp.queues.all() {
+        broker.declare_queue(queue);
+        if !amqp.exchange.is_empty() {
+            broker.bind(queue, &amqp.exchange, queue);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+
+    #[tokio::test]
+    async fn in_memory_publishes_are_confirmed_and_delivered() {
+        let transport = Transport::in_memory(MemoryBroker::new(), AmqpConfig::default());
+        let queue = transport.amqp().queues.activity_fills.clone();
+        let mut deliveries = transport.consumer(&queue, "test").await;
+
+        let published = transport.publish(&queue, &"fill").await.unwrap();
+        assert_eq!(published, Published::Confirmed);
+        let delivery = deliveries.next().await.unwrap().unwrap();
+        assert_eq!(delivery.decode::<String>().unwrap(), "fill");
+    }
+}
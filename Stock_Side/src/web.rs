@@ -0,0 +1,264 @@
+//! WebSocket server for dashboards and clients without AMQP.
+//!
+//! Clients connect to `/ws` and receive JSON text messages tagged by `type`:
+//! every stock on connect and then each changed `stock` per market cycle,
+//! every `trade` (from any gateway, without the broker) and every completed
+//! `bar`. To trade, a client first sends `{"type": "auth", "token": ...}`
+//! with a token from `[[web.clients]]`, which fixes its broker ID. Then:
+//!
+//! - `{"type": "order", "client_order_id", "stock_id", "action": "Buy" |
+//!   "Sell", "quantity", "price"?}` goes through the same `OrderEntry` as
+//!   RabbitMQ activities, with the id `ws:<broker_id>:<client_order_id>`,
+//!   and is answered with `filled` (the fill with its fees) or `rejected`
+//!   (the reject code). A reused id is not applied again and gets the
+//!   original answer, or `DUPLICATE_ORDER` if that cannot be found.
+//! - `{"type": "cancel", "client_order_id"}` is answered with
+//!   `cancel_rejected`, since orders are filled or rejected on arrival.
+//!
+//! Anything else gets an `error` message and the connection stays open.
+//!
+//! The same server answers the REST routes in `api`.
+
+use std::sync::Arc;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::response::Response;
+use axum::routing::get;
+use axum::Router;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+use crate::api;
+use crate::brokers::{ActivityOutcome, BrokerActivity, Fill};
+use crate::config::WebConfig;
+use crate::feed::FeedEvent;
+use crate::money::Money;
+use crate::order_entry::{OrderEntry, Submission};
+use crate::stock::Stock;
+
+struct WebState {
+    config: WebConfig,
+    order_entry: Arc<OrderEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    Auth {
+        token: String,
+    },
+    Order {
+        client_order_id: String,
+        stock_id: String,
+        action: String,
+        quantity: usize,
+        #[serde(default)]
+        price: Option<Money>,
+    },
+    Cancel {
+        client_order_id: String,
+    },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Reply {
+    Authenticated { broker_id: u32 },
+    Filled { client_order_id: String, fill: Fill },
+    Rejected { client_order_id: String, reason: String },
+    CancelRejected { client_order_id: String, reason: String },
+    Error { message: String },
+}
+
+/// Serves `/ws` and the REST API until the process exits.
+pub async fn serve(listener: TcpListener, config: WebConfig, order_entry: Arc<OrderEntry>) {
+    let api = api::router(Arc::clone(&order_entry));
+    let state = Arc::new(WebState { config, order_entry });
+    let router = Router::new().route("/ws", get(upgrade)).with_state(state).merge(api);
+    if let Err(e) = axum::serve(listener, router).await {
+        error!("Web server failed: {:?}", e);
+    }
+}
+
+async fn upgrade(ws: WebSocketUpgrade, State(state): State<Arc<WebState>>) -> Response {
+    ws.on_upgrade(move |socket| client(socket, state))
+}
+
+async fn client(mut socket: WebSocket, state: Arc<WebState>) {
+    let mut feed = state.order_entry.feed.subscribe();
+
+    // Current prices first, so a new client does not wait for the next cycle
+    let mut stocks: Vec<Stock> = state.order_entry.stocks.lock().await.values().cloned().collect();
+    stocks.sort_by(|a, b| a.id.cmp(&b.id));
+    for stock in stocks {
+        if send(&mut socket, &FeedEvent::Stock(stock)).await.is_err() {
+            return;
+        }
+    }
+
+    let mut broker_id = None;
+    loop {
+        let sent = tokio::select! {
+            event = feed.recv() => match event {
+                Ok(event) => send(&mut socket, &event).await,
+                Err(RecvError::Lagged(missed)) => {
+                    let message = format!("too slow, missed {} events", missed);
+                    send(&mut socket, &Reply::Error { message }).await
+                }
+                Err(RecvError::Closed) => return,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let reply = handle(&state, &mut broker_id, &text).await;
+                    send(&mut socket, &reply).await
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                // Pings are answered for us
+                Some(Ok(_)) => Ok(()),
+            },
+        };
+        if sent.is_err() {
+            return;
+        }
+    }
+}
+
+async fn handle(state: &WebState, broker_id: &mut Option<u32>, text: &str) -> Reply {
+    let request = match serde_json::from_str::<Request>(text) {
+        Ok(request) => request,
+        Err(e) => return Reply::Error { message: format!("invalid request: {}", e) },
+    };
+    match (request, *broker_id) {
+        (Request::Auth { token }, _) => match state.config.broker_id(&token) {
+            Some(id) => {
+                info!("WebSocket client authenticated for broker {}", id);
+                *broker_id = Some(id);
+                Reply::Authenticated { broker_id: id }
+            }
+            None => Reply::Error { message: "unknown token".to_string() },
+        },
+        (_, None) => Reply::Error { message: "authenticate before trading".to_string() },
+        (Request::Order { client_order_id, stock_id, action, quantity, price }, Some(broker_id)) => {
+            if client_order_id.is_empty() {
+                return Reply::Error { message: "client_order_id must not be empty".to_string() };
+            }
+            let activity = BrokerActivity {
+                activity_id: activity_id(broker_id, &client_order_id),
+                broker_id,
+                stock_id,
+                action,
+                quantity,
+                price,
+            };
+            // Resending an order gets the reply it got the first time
+            let outcome = match state.order_entry.submit(activity).await {
+                Submission::Applied(outcomes) => outcomes.into_iter().next(),
+                Submission::Duplicate(outcome) => outcome,
+            };
+            match outcome {
+                Some(ActivityOutcome::Filled(fill)) => Reply::Filled { client_order_id, fill },
+                Some(ActivityOutcome::Rejected(rejection)) => Reply::Rejected {
+                    client_order_id,
+                    reason: rejection.reason.code().to_string(),
+                },
+                None => Reply::Rejected { client_order_id, reason: "DUPLICATE_ORDER".to_string() },
+            }
+        }
+        (Request::Cancel { client_order_id }, Some(broker_id)) => {
+            let known = state
+                .order_entry
+                .store
+                .is_activity_processed(&activity_id(broker_id, &client_order_id))
+                .unwrap_or(false);
+            let reason = if known { "ORDER_NOT_OPEN" } else { "UNKNOWN_ORDER" };
+            Reply::CancelRejected { client_order_id, reason: reason.to_string() }
+        }
+    }
+}
+
+fn activity_id(broker_id: u32, client_order_id: &str) -> String {
+    format!("ws:{}:{}", broker_id, client_order_id)
+}
+
+async fn send(socket: &mut WebSocket, message: &impl Serialize) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(message).unwrap();
+    socket.send(Message::Text(text.into())).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::PathBuf;
+    use std::process;
+    use crate::config::WebClientConfig;
+
+    fn state(test: &str) -> (WebState, PathBuf) {
+        let journal_dir = env::temp_dir().join(format!("stock_side_web_test_{}_{}", test, process::id()));
+        let config = WebConfig {
+            listen: String::new(),
+            clients: vec![WebClientConfig { token: "secret".to_string(), broker_id: 7 }],
+        };
+        (WebState { config, order_entry: OrderEntry::for_tests(&journal_dir) }, journal_dir)
+    }
+
+    fn order(client_order_id: &str, quantity: usize) -> String {
+        format!(
+            r#"{{"type": "order", "client_order_id": "{}", "stock_id": "AAPL", "action": "Buy", "quantity": {}}}"#,
+            client_order_id, quantity
+        )
+    }
+
+    #[tokio::test]
+    async fn refuses_orders_without_a_known_token() {
+        let (state, journal_dir) = state("auth");
+        let mut broker_id = None;
+
+        let reply = handle(&state, &mut broker_id, r#"{"type": "auth", "token": "guess"}"#).await;
+        assert!(matches!(reply, Reply::Error { message } if message == "unknown token"));
+        let reply = handle(&state, &mut broker_id, &order("1", 10)).await;
+        assert!(matches!(reply, Reply::Error { message } if message == "authenticate before trading"));
+        assert_eq!(broker_id, None);
+        assert_eq!(state.order_entry.stocks.lock().await["AAPL"].available_quantity, 1000);
+
+        let _ = std::fs::remove_dir_all(journal_dir);
+    }
+
+    #[tokio::test]
+    async fn fills_an_order_once_and_answers_a_resend_the_same_way() {
+        let (state, journal_dir) = state("fill");
+        let mut broker_id = None;
+
+        let reply = handle(&state, &mut broker_id, r#"{"type": "auth", "token": "secret"}"#).await;
+        assert!(matches!(reply, Reply::Authenticated { broker_id: 7 }));
+        for _ in 0..2 {
+            let reply = handle(&state, &mut broker_id, &order("1", 10)).await;
+            let Reply::Filled { client_order_id, fill } = reply else {
+                panic!("expected a fill, got {:?}", reply);
+            };
+            assert_eq!((client_order_id.as_str(), fill.activity_id.as_str()), ("1", "ws:7:1"));
+            assert_eq!((fill.broker_id, fill.quantity), (7, 10));
+        }
+        assert_eq!(state.order_entry.stocks.lock().await["AAPL"].available_quantity, 990);
+
+        let _ = std::fs::remove_dir_all(journal_dir);
+    }
+
+    #[tokio::test]
+    async fn rejects_an_order_outside_the_instrument_limits() {
+        let (state, journal_dir) = state("reject");
+        let mut broker_id = Some(7);
+
+        let reply = handle(&state, &mut broker_id, &order("big", 500)).await;
+        assert!(matches!(
+            reply,
+            Reply::Rejected { client_order_id, reason } if client_order_id == "big" && reason == "QUANTITY_ABOVE_MAXIMUM"
+        ));
+        let reply = handle(&state, &mut broker_id, r#"{"type": "order", "stock_id": "AAPL"}"#).await;
+        assert!(matches!(reply, Reply::Error { message } if message.starts_with("invalid request")));
+        assert_eq!(state.order_entry.stocks.lock().await["AAPL"].available_quantity, 1000);
+
+        let _ = std::fs::remove_dir_all(journal_dir);
+    }
+}
@@ -0,0 +1,321 @@
+//! REST API over the live broker accounts: cash, holdings, open orders and
+//! P&L, all as JSON. The OpenAPI document at `/api/openapi.json` is
+//! generated from the handlers and types below. Requests wait for the
+//! brokers lock, so they may be held up while a trading round runs.
+
+use std::sync::Arc;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use log::error;
+use serde::Serialize;
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+use utoipa::{OpenApi, ToSchema};
+use stock_side::money::Money;
+use crate::broker::{Broker, OpenOrder};
+use crate::simulation::Stock;
+use crate::trading_strategy::Strategy;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Trading_Side", description = "Broker accounts, positions and P&L."),
+    paths(list_brokers, broker, cash, holdings, orders, pnl)
+)]
+struct ApiDoc;
+
+struct ApiState {
+    brokers: Arc<Mutex<Vec<Broker>>>,
+    stocks: Arc<Mutex<Vec<Stock>>>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ApiError {
+    error: String,
+}
+
+type ApiResult<T> = Result<Json<T>, (StatusCode, Json<ApiError>)>;
+
+#[derive(Debug, Serialize, ToSchema)]
+struct BrokerSummary {
+    id: u32,
+    strategy: Strategy,
+    cash: Money,
+    /// Cash plus positions at the latest prices.
+    equity: Money,
+    short_selling: bool,
+    margin: bool,
+    open_orders: usize,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct Cash {
+    cash: Money,
+    /// Cash less short sale proceeds held as collateral for cash accounts;
+    /// margin accounts may borrow up to this much.
+    buying_power: Money,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct Holding {
+    stock_id: String,
+    /// Negative for short positions.
+    quantity: i64,
+    average_cost: Money,
+    /// Latest price seen; `null` until the stock has been marked.
+    mark: Option<Money>,
+    unrealized_pnl: Option<Money>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct Pnl {
+    realized: Money,
+    unrealized: Money,
+    trading_fees: Money,
+    borrow_fees: Money,
+    margin_interest: Money,
+    /// Realized plus unrealized, less fees and interest.
+    net: Money,
+}
+
+/// Serves the REST API until the process exits.
+pub async fn serve(listener: TcpListener, brokers: Arc<Mutex<Vec<Broker>>>, stocks: Arc<Mutex<Vec<Stock>>>) {
+    if let Err(e) = axum::serve(listener, router(brokers, stocks)).await {
+        error!("[Trading_Side] REST server failed: {:?}", e);
+    }
+}
+
+fn router(brokers: Arc<Mutex<Vec<Broker>>>, stocks: Arc<Mutex<Vec<Stock>>>) -> Router {
+    let state = Arc::new(ApiState { brokers, stocks });
+    Router::new()
+        .route("/api/openapi.json", get(openapi))
+        .route("/api/brokers", get(list_brokers))
+        .route("/api/brokers/{id}", get(broker))
+        .route("/api/brokers/{id}/cash", get(cash))
+        .route("/api/brokers/{id}/holdings", get(holdings))
+        .route("/api/brokers/{id}/orders", get(orders))
+        .route("/api/brokers/{id}/pnl", get(pnl))
+        .with_state(state)
+}
+
+async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// Runs `f` on broker `id`, or answers 404.
+async fn with_broker<T>(state: &ApiState, id: u32, f: impl FnOnce(&Broker, &[Stock]) -> T) -> ApiResult<T> {
+    let brokers = state.brokers.lock().await;
+    let stocks = state.stocks.lock().await;
+    match brokers.iter().find(|broker| broker.id == id) {
+        Some(broker) => Ok(Json(f(broker, &stocks))),
+        None => Err((StatusCode::NOT_FOUND, Json(ApiError { error: format!("unknown broker {}", id) }))),
+    }
+}
+
+fn summary(broker: &Broker, stocks: &[Stock]) -> BrokerSummary {
+    BrokerSummary {
+        id: broker.id,
+        strategy: broker.strategy.clone(),
+        cash: broker.get_cash(),
+        equity: broker.get_total_value(stocks),
+        short_selling: broker.short_selling,
+        margin: broker.margin.is_some(),
+        open_orders: broker.open_orders.len(),
+    }
+}
+
+/// Every broker, by id.
+#[utoipa::path(get, path = "/api/brokers", responses((status = 200, body = Vec<BrokerSummary>)))]
+async fn list_brokers(State(state): State<Arc<ApiState>>) -> Json<Vec<BrokerSummary>> {
+    let brokers = state.brokers.lock().await;
+    let stocks = state.stocks.lock().await;
+    let mut summaries: Vec<BrokerSummary> = brokers.iter().map(|broker| summary(broker, &stocks)).collect();
+    summaries.sort_by_key(|summary| summary.id);
+    Json(summaries)
+}
+
+/// One broker's account summary.
+#[utoipa::path(
+    get,
+    path = "/api/brokers/{id}",
+    params(("id" = u32, Path)),
+    responses((status = 200, body = BrokerSummary), (status = 404, body = ApiError))
+)]
+async fn broker(State(state): State<Arc<ApiState>>, Path(id): Path<u32>) -> ApiResult<BrokerSummary> {
+    with_broker(&state, id, summary).await
+}
+
+/// Cash and buying power.
+#[utoipa::path(
+    get,
+    path = "/api/brokers/{id}/cash",
+    params(("id" = u32, Path)),
+    responses((status = 200, body = Cash), (status = 404, body = ApiError))
+)]
+async fn cash(State(state): State<Arc<ApiState>>, Path(id): Path<u32>) -> ApiResult<Cash> {
+    with_broker(&state, id, |broker, stocks| Cash {
+        cash: broker.get_cash(),
+        buying_power: broker.buying_power(stocks),
+    })
+    .await
+}
+
+/// Open positions, by symbol.
+#[utoipa::path(
+    get,
+    path = "/api/brokers/{id}/holdings",
+    params(("id" = u32, Path)),
+    responses((status = 200, body = Vec<Holding>), (status = 404, body = ApiError))
+)]
+async fn holdings(State(state): State<Arc<ApiState>>, Path(id): Path<u32>) -> ApiResult<Vec<Holding>> {
+    with_broker(&state, id, |broker, _| {
+        let mut holdings: Vec<Holding> = broker
+            .holdings
+            .iter()
+            .map(|(stock_id, position)| {
+                let mark = broker.marks.get(stock_id).copied();
+                Holding {
+                    stock_id: stock_id.clone(),
+                    quantity: position.quantity,
+                    average_cost: position.average_cost(),
+                    mark,
+                    unrealized_pnl: mark.map(|mark| position.unrealized_pnl(mark)),
+                }
+            })
+            .collect();
+        holdings.sort_by(|a, b| a.stock_id.cmp(&b.stock_id));
+        holdings
+    })
+    .await
+}
+
+/// Orders sent to Stock_Side that have not been filled or rejected yet.
+#[utoipa::path(
+    get,
+    path = "/api/brokers/{id}/orders",
+    params(("id" = u32, Path)),
+    responses((status = 200, body = Vec<OpenOrder>), (status = 404, body = ApiError))
+)]
+async fn orders(State(state): State<Arc<ApiState>>, Path(id): Path<u32>) -> ApiResult<Vec<OpenOrder>> {
+    with_broker(&state, id, |broker, _| broker.open_orders.clone()).await
+}
+
+/// Realized and unrealized P&L and what was paid for it.
+#[utoipa::path(
+    get,
+    path = "/api/brokers/{id}/pnl",
+    params(("id" = u32, Path)),
+    responses((status = 200, body = Pnl), (status = 404, body = ApiError))
+)]
+async fn pnl(State(state): State<Arc<ApiState>>, Path(id): Path<u32>) -> ApiResult<Pnl> {
+    with_broker(&state, id, |broker, _| {
+        let realized = broker.realized_pnl;
+        let unrealized = broker.unrealized_pnl();
+        let margin_interest = broker.margin.as_ref().map_or(Money::ZERO, |margin| margin.interest_charged);
+        Pnl {
+            realized,
+            unrealized,
+            trading_fees: broker.trading_fees,
+            borrow_fees: broker.borrow_fees,
+            margin_interest,
+            net: realized + unrealized - broker.trading_fees - broker.borrow_fees - margin_interest,
+        }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    fn dollars(amount: f64) -> Money {
+        Money::from_f64(amount)
+    }
+
+    /// Broker 1 holds 10 AAPL bought at $100 and has a sell of 5 open, with
+    /// AAPL now at $110; broker 2 has only cash.
+    fn router_with_brokers() -> Router {
+        let mut broker = Broker::new(1, dollars(10_000.0), Strategy::RiskAverse);
+        let order = |activity_id: &str, action: &str, quantity: usize, price: f64| OpenOrder {
+            activity_id: activity_id.to_string(),
+            stock_id: "AAPL".to_string(),
+            action: action.to_string(),
+            quantity,
+            price: dollars(price),
+        };
+        broker.open_orders.push(order("a", "Buy", 10, 100.0));
+        assert!(broker.fill("a", 10, dollars(100.0)));
+        broker.open_orders.push(order("b", "Sell", 5, 110.0));
+        let stocks = vec![Stock { id: "AAPL".to_string(), price: dollars(110.0), available_quantity: 100 }];
+        broker.mark_to_market(&stocks);
+
+        let brokers = vec![Broker::new(2, dollars(500.0), Strategy::Random), broker];
+        router(Arc::new(Mutex::new(brokers)), Arc::new(Mutex::new(stocks)))
+    }
+
+    async fn get_json(router: &Router, uri: &str) -> (StatusCode, Value) {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn money(value: &Value) -> Money {
+        serde_json::from_value(value.clone()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn lists_brokers_by_id_with_their_equity() {
+        let router = router_with_brokers();
+        let (status, brokers) = get_json(&router, "/api/brokers").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!((&brokers[0]["id"], &brokers[1]["id"]), (&Value::from(1), &Value::from(2)));
+        assert_eq!(money(&brokers[0]["cash"]), dollars(9_000.0));
+        assert_eq!(money(&brokers[0]["equity"]), dollars(10_100.0));
+        assert_eq!(brokers[0]["open_orders"], 1);
+
+        let (status, broker) = get_json(&router, "/api/brokers/2").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(money(&broker["equity"]), dollars(500.0));
+    }
+
+    #[tokio::test]
+    async fn serves_cash_holdings_orders_and_pnl() {
+        let router = router_with_brokers();
+
+        let (_, cash) = get_json(&router, "/api/brokers/1/cash").await;
+        assert_eq!(money(&cash["buying_power"]), dollars(9_000.0));
+
+        let (_, holdings) = get_json(&router, "/api/brokers/1/holdings").await;
+        assert_eq!(holdings[0]["stock_id"], "AAPL");
+        assert_eq!(holdings[0]["quantity"], 10);
+        assert_eq!(money(&holdings[0]["average_cost"]), dollars(100.0));
+        assert_eq!(money(&holdings[0]["unrealized_pnl"]), dollars(100.0));
+
+        let (_, orders) = get_json(&router, "/api/brokers/1/orders").await;
+        assert_eq!(orders.as_array().unwrap().len(), 1);
+        assert_eq!((&orders[0]["activity_id"], &orders[0]["action"]), (&Value::from("b"), &Value::from("Sell")));
+
+        let (_, pnl) = get_json(&router, "/api/brokers/1/pnl").await;
+        assert_eq!((money(&pnl["realized"]), money(&pnl["net"])), (Money::ZERO, dollars(100.0)));
+    }
+
+    #[tokio::test]
+    async fn answers_404_for_an_unknown_broker() {
+        let router = router_with_brokers();
+        for uri in ["/api/brokers/9", "/api/brokers/9/cash", "/api/brokers/9/pnl"] {
+            let (status, error) = get_json(&router, uri).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{}", uri);
+            assert_eq!(error["error"], "unknown broker 9");
+        }
+        let (status, openapi) = get_json(&router, "/api/openapi.json").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(openapi["paths"]["/api/brokers/{id}/pnl"].is_object());
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::{HashMap, HashSet};
+use rand::Rng;
+use rust_decimal::Decimal;
+use crate::broker::Broker;
+
+/// Shares lendable per symbol when the desk first sees it.
+const DEFAULT_LENDABLE_QUANTITY: usize = 50;
+
+/// Stock loan desk backing short sales. A broker's borrow in a symbol is
+/// always equal to its short position, so `on_loan` is rebuilt from the
+/// brokers at the start of every round.
+#[derive(Debug, Clone)]
+pub struct BorrowDesk {
+    lendable: HashMap<String, usize>,
+    on_loan: HashMap<String, usize>,
+    /// Symbols recalled this session. Shorts in them are bought in, a round
+    /// at a time, until they are flat.
+    recalled: HashSet<String>,
+    /// Fee charged per simulated day on the market value of borrowed shares.
+    pub fee_rate_per_day: Decimal,
+    /// Chance per round that the lender recalls an outstanding borrow.
+    pub recall_probability: f64,
+}
+
+impl BorrowDesk {
+    pub fn new(fee_rate_per_day: Decimal, recall_probability: f64) -> Self {
+        BorrowDesk {
+            lendable: HashMap::new(),
+            on_loan: HashMap::new(),
+            recalled: HashSet::new(),
+            fee_rate_per_day,
+            recall_probability,
+        }
+    }
+
+    pub fn available(&self, stock_id: &str) -> usize {
+        let lendable = self.lendable.get(stock_id).copied().unwrap_or(DEFAULT_LENDABLE_QUANTITY);
+        let on_loan = self.on_loan.get(stock_id).copied().unwrap_or(0);
+        lendable.saturating_sub(on_loan)
+    }
+
+    /// Locate check: reserves `quantity` shares if the desk can lend them.
+    pub fn locate(&mut self, stock_id: &str, quantity: usize) -> Result<(), &'static str> {
+        if self.available(stock_id) < quantity {
+            return Err("No borrow available to locate");
+        }
+        self.lendable.entry(stock_id.to_string()).or_insert(DEFAULT_LENDABLE_QUANTITY);
+        *self.on_loan.entry(stock_id.to_string()).or_insert(0) += quantity;
+        Ok(())
+    }
+
+    pub fn reconcile<'a>(&mut self, brokers: impl Iterator<Item = &'a Broker>) {
+        self.on_loan.clear();
+        for broker in brokers {
+            for (stock_id, quantity) in broker.short_positions() {
+                *self.on_loan.entry(stock_id.clone()).or_insert(0) += quantity;
+            }
+        }
+    }
+
+    /// Randomly recalls outstanding borrows and returns the newly recalled
+    /// symbols. A recalled symbol can no longer be borrowed for the rest of
+    /// the session and every short in it must be bought in.
+    pub fn recalls(&mut self) -> Vec<String> {
+        let mut rng = rand::thread_rng();
+        let recalled: Vec<String> = self
+            .on_loan
+            .iter()
+            .filter(|(stock_id, &quantity)| quantity > 0 && !self.recalled.contains(*stock_id))
+            .filter(|_| rng.gen_bool(self.recall_probability))
+            .map(|(stock_id, _)| stock_id.clone())
+            .collect();
+
+        for stock_id in &recalled {
+            self.lendable.insert(stock_id.clone(), 0);
+            self.recalled.insert(stock_id.clone());
+        }
+        recalled
+    }
+
+    /// Every symbol recalled so far this session.
+    pub fn recalled(&self) -> impl Iterator<Item = &String> {
+        self.recalled.iter()
+    }
+}
@@ -1,86 +1,617 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::fmt;
+use stock_side::money::Money;
 use crate::trading_strategy::Strategy;
 use crate::simulation::Stock;
+use crate::borrow::BorrowDesk;
+use crate::margin::{MarginAccount, MarginStatus};
+use crate::position::{CostBasisMethod, Position, TaxLot};
+use crate::ledger::{Account, EntryKind, Ledger, LedgerError};
+use stock_side::instrument::Instrument;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// An activity published to Stock_Side that has not been filled or
+/// rejected yet. Nothing is booked until the fill arrives.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct OpenOrder {
+    /// Empty for orders saved before ids were kept.
+    #[serde(default)]
+    pub activity_id: String,
+    pub stock_id: String,
+    pub action: String,
+    pub quantity: usize,
+    /// The last price seen when it was sent; it fills at the market price.
+    pub price: Money,
+}
+
+/// Everything needed to rebuild a broker in a later session. The ledger is
+/// not carried over; a restored broker opens a new one from `cash` and the
+/// lot quantities.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BrokerState {
+    pub id: u32,
+    pub strategy: Strategy,
+    pub cost_basis: CostBasisMethod,
+    pub short_selling: bool,
+    pub margin: Option<MarginAccount>,
+    pub cash: Money,
+    pub lots: BTreeMap<String, Vec<TaxLot>>,
+    pub marks: BTreeMap<String, Money>,
+    pub realized_pnl: Money,
+    pub trading_fees: Money,
+    pub borrow_fees: Money,
+    pub open_orders: Vec<OpenOrder>,
+}
 
 pub struct Broker {
     pub id: u32,
-    pub cash: f64,
+    /// Source of truth for cash; every cash and share movement is posted here.
+    pub ledger: Ledger,
     pub strategy: Strategy,
-    pub holdings: HashMap<String, usize>,
+    /// Open positions; negative quantities are short and fully borrowed.
+    pub holdings: HashMap<String, Position>,
+    pub cost_basis: CostBasisMethod,
+    pub realized_pnl: Money,
+    /// Latest price seen for each held stock, used for unrealized P&L.
+    pub marks: HashMap<String, Money>,
+    pub short_selling: bool,
+    pub borrow_fees: Money,
+    /// Market value borrowed in each round of this session, summed; borrow
+    /// fees are charged on it at the close.
+    pub borrowed_value: Money,
+    /// Commissions and exchange fees net of rebates, as reported on fills.
+    pub trading_fees: Money,
+    /// `None` for cash accounts.
+    pub margin: Option<MarginAccount>,
+    pub open_orders: Vec<OpenOrder>,
 }
 
 impl Broker {
-    pub fn new(id: u32, initial_cash: f64, strategy: Strategy) -> Self {
+    pub fn new(id: u32, initial_cash: Money, strategy: Strategy) -> Self {
+        let mut ledger = Ledger::default();
+        ledger.record_deposit(initial_cash);
         Broker {
             id,
-            cash: initial_cash,
+            ledger,
             strategy,
             holdings: HashMap::new(),
+            cost_basis: CostBasisMethod::default(),
+            realized_pnl: Money::ZERO,
+            marks: HashMap::new(),
+            short_selling: false,
+            borrow_fees: Money::ZERO,
+            borrowed_value: Money::ZERO,
+            trading_fees: Money::ZERO,
+            margin: None,
+            open_orders: Vec::new(),
+        }
+    }
+
+    /// Rebuilds a broker saved by `state`, opening its ledger with the
+    /// carried cash and positions.
+    pub fn restore(state: BrokerState) -> Self {
+        let positions: Vec<(String, i64)> = state
+            .lots
+            .iter()
+            .map(|(stock_id, lots)| (stock_id.clone(), lots.iter().map(|lot| lot.quantity).sum()))
+            .collect();
+        let mut ledger = Ledger::default();
+        ledger.record_opening_balance(state.cash, &positions);
+
+        let holdings = state
+            .lots
+            .into_iter()
+            .map(|(stock_id, lots)| {
+                let quantity = lots.iter().map(|lot| lot.quantity).sum();
+                (stock_id, Position { quantity, lots: VecDeque::from(lots) })
+            })
+            .collect();
+        Broker {
+            id: state.id,
+            ledger,
+            strategy: state.strategy,
+            holdings,
+            cost_basis: state.cost_basis,
+            realized_pnl: state.realized_pnl,
+            marks: state.marks.into_iter().collect(),
+            short_selling: state.short_selling,
+            borrow_fees: state.borrow_fees,
+            borrowed_value: Money::ZERO,
+            trading_fees: state.trading_fees,
+            margin: state.margin,
+            open_orders: state.open_orders,
+        }
+    }
+
+    pub fn state(&self) -> BrokerState {
+        BrokerState {
+            id: self.id,
+            strategy: self.strategy.clone(),
+            cost_basis: self.cost_basis,
+            short_selling: self.short_selling,
+            margin: self.margin.clone(),
+            cash: self.get_cash(),
+            lots: self
+                .holdings
+                .iter()
+                .map(|(stock_id, position)| (stock_id.clone(), position.lots.iter().cloned().collect()))
+                .collect(),
+            marks: self.marks.iter().map(|(stock_id, &mark)| (stock_id.clone(), mark)).collect(),
+            realized_pnl: self.realized_pnl,
+            trading_fees: self.trading_fees,
+            borrow_fees: self.borrow_fees,
+            open_orders: self.open_orders.clone(),
         }
     }
 
+    /// Closes open order `activity_id` after Stock_Side rejected it,
+    /// releasing the buying power a buy took.
+    pub fn reject_order(&mut self, activity_id: &str) {
+        let Some(index) = self.open_orders.iter().position(|order| order.activity_id == activity_id) else {
+            return;
+        };
+        let order = self.open_orders.remove(index);
+        if let (Some(margin), "Buy") = (self.margin.as_mut(), order.action.as_str()) {
+            margin.buying_power += order.price.times(order.quantity);
+        }
+    }
+
+    /// Books the fill of open order `activity_id` at the price it traded at
+    /// and closes the order. Returns `false`, booking nothing, if no such
+    /// order is open, e.g. because it was already settled on startup.
+    pub fn fill(&mut self, activity_id: &str, quantity: usize, price: Money) -> bool {
+        let index = self
+            .open_orders
+            .iter()
+            .position(|order| !activity_id.is_empty() && order.activity_id == activity_id);
+        let Some(index) = index else {
+            return false;
+        };
+        let order = self.open_orders.remove(index);
+        let delta = match order.action.as_str() {
+            "Buy" => quantity as i64,
+            _ => -(quantity as i64),
+        };
+        self.adjust_position(&order.stock_id, delta, price);
+        true
+    }
+
+    /// Position in `stock_id` once every open order for it fills.
+    pub fn committed_quantity(&self, stock_id: &str) -> i64 {
+        let pending: i64 = self
+            .open_orders
+            .iter()
+            .filter(|order| order.stock_id == stock_id)
+            .map(|order| match order.action.as_str() {
+                "Buy" => order.quantity as i64,
+                _ => -(order.quantity as i64),
+            })
+            .sum();
+        self.position_quantity(stock_id) + pending
+    }
+
+    /// Cash set aside for open buy orders, at the prices they were sent at.
+    pub fn pending_buy_cost(&self) -> Money {
+        self.open_orders
+            .iter()
+            .filter(|order| order.action == "Buy")
+            .map(|order| order.price.times(order.quantity))
+            .sum()
+    }
+
+    /// Opts the broker into short selling.
+    pub fn with_short_selling(mut self) -> Self {
+        self.short_selling = true;
+        self
+    }
+
+    pub fn with_cost_basis(mut self, method: CostBasisMethod) -> Self {
+        self.cost_basis = method;
+        self
+    }
+
+    /// Turns the broker into a margin account on the given terms.
+    pub fn with_margin(mut self, account: MarginAccount) -> Self {
+        self.margin = Some(account);
+        self
+    }
+
+    /// Checks a buy can be paid for and reserves its cost; the shares are
+    /// booked when it fills.
     pub fn buy(&mut self, stock: &Stock, quantity: usize) -> Result<(), &'static str> {
-        let cost = stock.price * quantity as f64;
-        if self.cash >= cost && stock.available_quantity >= quantity {
-            self.cash -= cost;
-            *self.holdings.entry(stock.id.clone()).or_insert(0) += quantity;
+        let cost = stock.price.times(quantity);
+        let affordable = match &self.margin {
+            Some(margin) => margin.buying_power >= cost,
+            None => {
+                let spendable = self.spendable_cash() + self.collateral_released_by(&stock.id, quantity);
+                spendable - self.pending_buy_cost() >= cost
+            }
+        };
+        if affordable && stock.available_quantity >= quantity {
+            if let Some(margin) = self.margin.as_mut() {
+                margin.buying_power -= cost;
+            }
             Ok(())
         } else {
             Err("Insufficient funds or stock quantity")
         }
     }
 
+    /// Checks the shares are held and not already being sold; the sale is
+    /// booked when it fills.
     pub fn sell(&mut self, stock: &Stock, quantity: usize) -> Result<(), &'static str> {
-        if let Some(&current_quantity) = self.holdings.get(&stock.id) {
-            if current_quantity >= quantity {
-                let revenue = stock.price * quantity as f64;
-                self.cash += revenue;
-                
-                let new_quantity = current_quantity - quantity;
-                if new_quantity == 0 {
-                    self.holdings.remove(&stock.id);
-                } else {
-                    self.holdings.insert(stock.id.clone(), new_quantity);
-                }
-                Ok(())
+        if !self.holdings.contains_key(&stock.id) {
+            return Err("Stock not found in holdings");
+        }
+        if self.committed_quantity(&stock.id) >= quantity as i64 {
+            Ok(())
+        } else {
+            Err("Insufficient stock quantity in holdings")
+        }
+    }
+
+    /// Checks a sale of `quantity` shares, closing any long position first
+    /// and locating the borrow for the remainder. Booked when it fills.
+    pub fn sell_short(
+        &mut self,
+        stock: &Stock,
+        quantity: usize,
+        desk: &mut BorrowDesk,
+    ) -> Result<(), &'static str> {
+        if !self.short_selling {
+            return Err("Short selling not enabled for broker");
+        }
+        let long_quantity = self.committed_quantity(&stock.id).max(0) as usize;
+        let short_quantity = quantity.saturating_sub(long_quantity);
+        if short_quantity > 0 {
+            desk.locate(&stock.id, short_quantity)?;
+        }
+        Ok(())
+    }
+
+    /// Size of the next forced purchase covering the short position in
+    /// `stock`, including short sales still open: as much as the market has
+    /// available, up to the instrument's maximum. The rest is bought in on
+    /// later rounds. Paid for even if it takes cash negative.
+    pub fn buy_in(&self, stock: &Stock, instrument: &Instrument) -> usize {
+        let short_quantity = self.committed_quantity(&stock.id).min(0).unsigned_abs() as usize;
+        short_quantity.min(stock.available_quantity).min(instrument.max_quantity)
+    }
+
+    /// Applies the fees reported on a fill. Rebates arrive as negative fees.
+    pub fn pay_fee(&mut self, fee: Money) {
+        self.ledger.record_charge(EntryKind::Fee, Account::TradingFees, fee, "Trading fees".to_string());
+        self.trading_fees += fee;
+        if let Some(margin) = self.margin.as_mut() {
+            margin.buying_power -= fee;
+        }
+    }
+
+    /// Adds the short positions held this round, at their latest marks, to
+    /// the value borrow fees are charged on.
+    pub fn record_borrowed_value(&mut self) {
+        let value: Money = self
+            .holdings
+            .iter()
+            .filter(|(_, position)| position.quantity < 0)
+            .filter_map(|(stock_id, position)| {
+                self.marks.get(stock_id).map(|mark| mark.times(position.quantity.unsigned_abs()))
+            })
+            .sum();
+        self.borrowed_value += value;
+    }
+
+    /// Charges the session's borrow fees: a day's fee spread over
+    /// `rounds_per_day` rounds for every round a short was held, whether or
+    /// not it is still open at the close.
+    pub fn accrue_borrow_fees(&mut self, fee_rate_per_day: Decimal, rounds_per_day: usize) -> Money {
+        let per_round = self.borrowed_value.amount() / Decimal::from(rounds_per_day.max(1));
+        let fee = Money::new(per_round).apply_rate(fee_rate_per_day);
+        self.borrowed_value = Money::ZERO;
+        self.ledger.record_charge(EntryKind::BorrowFee, Account::BorrowFees, fee, "Daily borrow fee".to_string());
+        self.borrow_fees += fee;
+        fee
+    }
+
+    /// Gross (long plus short) market value of all positions.
+    pub fn gross_position_value(&self, stocks: &[Stock]) -> Money {
+        stocks
+            .iter()
+            .map(|stock| stock.price.times(self.position_quantity(&stock.id).unsigned_abs()))
+            .sum()
+    }
+
+    /// What the broker can still spend, net of open buy orders.
+    pub fn buying_power(&self, stocks: &[Stock]) -> Money {
+        let buying_power = match &self.margin {
+            Some(margin) => {
+                margin.buying_power_for(self.get_total_value(stocks), self.gross_position_value(stocks))
+            }
+            None => self.spendable_cash(),
+        };
+        buying_power - self.pending_buy_cost()
+    }
+
+    /// Refreshes buying power and the margin call state at current prices.
+    /// Cash accounts are always `Ok`.
+    pub fn check_margin(&mut self, stocks: &[Stock]) -> MarginStatus {
+        let buying_power = self.buying_power(stocks);
+        let equity = self.get_total_value(stocks);
+        let gross = self.gross_position_value(stocks);
+        match self.margin.as_mut() {
+            Some(margin) => {
+                margin.buying_power = buying_power;
+                margin.assess(equity, gross)
+            }
+            None => MarginStatus::Ok,
+        }
+    }
+
+    /// Picks positions to close, largest first, until equity covers the
+    /// maintenance requirement again. Closing at the market leaves equity
+    /// unchanged and only shrinks the requirement. A position in a stock
+    /// with no current quote is valued and closed at its last mark. Orders
+    /// are split to fit the instrument's maximum quantity, or sent whole
+    /// without instrument data, and covering buys are capped at what the
+    /// market has available. Returns the orders to send as
+    /// `(stock, action, quantity)`; they are booked as they fill.
+    pub fn liquidate(&self, stocks: &[Stock], instruments: &HashMap<String, Instrument>) -> Vec<(Stock, &'static str, usize)> {
+        let Some(margin) = &self.margin else {
+            return Vec::new();
+        };
+        let mut positions: Vec<(Stock, i64)> = self.priced_positions(stocks);
+        positions.sort_by_key(|(stock, quantity)| std::cmp::Reverse(stock.price.times(quantity.unsigned_abs())));
+
+        let priced: Vec<Stock> = positions.iter().map(|(stock, _)| stock.clone()).collect();
+        let equity = self.get_total_value(&priced);
+        let mut gross = self.gross_position_value(&priced);
+        let mut trades = Vec::new();
+        for (stock, quantity) in positions {
+            if equity >= margin.maintenance_for(gross) {
+                break;
+            }
+            let (action, mut remaining) = if quantity > 0 {
+                ("Sell", quantity as usize)
             } else {
-                Err("Insufficient stock quantity in holdings")
+                ("Buy", (quantity.unsigned_abs() as usize).min(stock.available_quantity))
+            };
+            gross -= stock.price.times(remaining);
+            let max_quantity = instruments.get(&stock.id).map_or(remaining, |instrument| instrument.max_quantity);
+            while remaining > 0 {
+                let order_quantity = remaining.min(max_quantity.max(1));
+                trades.push((stock.clone(), action, order_quantity));
+                remaining -= order_quantity;
             }
-        } else {
-            Err("Stock not found in holdings")
         }
+        trades
     }
 
-    pub fn get_total_value(&self, stocks: &[Stock]) -> f64 {
-        let holdings_value: f64 = stocks.iter()
-            .filter_map(|stock| {
-                self.holdings.get(&stock.id)
-                    .map(|&quantity| stock.price * quantity as f64)
+    /// Every position open or pending, with the stock it is in as quoted in
+    /// `stocks`. A stock missing there is priced at its last mark, or at the
+    /// price its open orders were sent at, with the position's size as the
+    /// quantity available.
+    fn priced_positions(&self, stocks: &[Stock]) -> Vec<(Stock, i64)> {
+        let stock_ids: BTreeSet<&String> = self
+            .holdings
+            .keys()
+            .chain(self.open_orders.iter().map(|order| &order.stock_id))
+            .collect();
+        stock_ids
+            .into_iter()
+            .filter_map(|stock_id| {
+                let quantity = self.committed_quantity(stock_id);
+                if quantity == 0 {
+                    return None;
+                }
+                let stock = match stocks.iter().find(|stock| stock.id == *stock_id) {
+                    Some(stock) => stock.clone(),
+                    None => Stock {
+                        id: stock_id.clone(),
+                        price: self.marks.get(stock_id).copied().or_else(|| {
+                            self.open_orders.iter().find(|order| order.stock_id == *stock_id).map(|order| order.price)
+                        })?,
+                        available_quantity: quantity.unsigned_abs() as usize,
+                    },
+                };
+                Some((stock, quantity))
             })
+            .collect()
+    }
+
+    /// Charges one day of interest on a negative cash balance.
+    pub fn accrue_margin_interest(&mut self) -> Money {
+        let cash = self.get_cash();
+        let Some(margin) = self.margin.as_mut() else {
+            return Money::ZERO;
+        };
+        let interest = margin.daily_interest(cash);
+        margin.interest_charged += interest;
+        self.ledger.record_charge(EntryKind::Interest, Account::MarginInterest, interest, "Daily margin interest".to_string());
+        interest
+    }
+
+    pub fn position_quantity(&self, stock_id: &str) -> i64 {
+        self.holdings.get(stock_id).map_or(0, |position| position.quantity)
+    }
+
+    pub fn short_quantity(&self, stock_id: &str) -> usize {
+        self.position_quantity(stock_id).min(0).unsigned_abs() as usize
+    }
+
+    /// Proceeds of the short positions still open. A cash account holds them
+    /// as collateral for buying the shares back instead of spending them.
+    pub fn short_collateral(&self) -> Money {
+        self.holdings
+            .values()
+            .flat_map(|position| position.lots.iter())
+            .filter(|lot| lot.quantity < 0)
+            .map(|lot| lot.price.times(lot.quantity.unsigned_abs()))
+            .sum()
+    }
+
+    /// Collateral freed by buying `quantity` shares of `stock_id` back, at
+    /// the short position's average price.
+    fn collateral_released_by(&self, stock_id: &str, quantity: usize) -> Money {
+        let covered = self.short_quantity(stock_id).min(quantity);
+        self.holdings
+            .get(stock_id)
+            .map_or(Money::ZERO, |position| position.average_cost().times(covered))
+    }
+
+    /// Cash a cash account can spend or withdraw: its balance less the
+    /// collateral held against short positions.
+    pub fn spendable_cash(&self) -> Money {
+        self.get_cash() - self.short_collateral()
+    }
+
+    /// Short positions as they will be once open orders fill, which is what
+    /// the broker has borrowed.
+    pub fn short_positions(&self) -> Vec<(&String, usize)> {
+        let mut stock_ids: Vec<&String> = self
+            .holdings
+            .keys()
+            .chain(self.open_orders.iter().map(|order| &order.stock_id))
+            .collect();
+        stock_ids.sort();
+        stock_ids.dedup();
+        stock_ids
+            .into_iter()
+            .map(|stock_id| (stock_id, self.committed_quantity(stock_id)))
+            .filter(|(_, quantity)| *quantity < 0)
+            .map(|(stock_id, quantity)| (stock_id, quantity.unsigned_abs() as usize))
+            .collect()
+    }
+
+    /// Posts a signed trade to the ledger, books it against the position's
+    /// lots and records any realized P&L. Flat positions are dropped from
+    /// `holdings`.
+    fn adjust_position(&mut self, stock_id: &str, delta: i64, price: Money) {
+        self.ledger.record_trade(stock_id, delta, price);
+        let position = self.holdings.entry(stock_id.to_string()).or_default();
+        self.realized_pnl += position.apply_trade(delta, price, self.cost_basis);
+        if position.quantity == 0 {
+            self.holdings.remove(stock_id);
+        }
+        self.marks.insert(stock_id.to_string(), price);
+    }
+
+    /// Records the latest prices for unrealized P&L.
+    pub fn mark_to_market(&mut self, stocks: &[Stock]) {
+        for stock in stocks {
+            if self.holdings.contains_key(&stock.id) {
+                self.marks.insert(stock.id.clone(), stock.price);
+            }
+        }
+    }
+
+    pub fn unrealized_pnl(&self) -> Money {
+        self.holdings
+            .iter()
+            .filter_map(|(stock_id, position)| {
+                self.marks.get(stock_id).map(|&mark| position.unrealized_pnl(mark))
+            })
+            .sum()
+    }
+
+    pub fn get_total_value(&self, stocks: &[Stock]) -> Money {
+        let holdings_value: Money = stocks.iter()
+            .map(|stock| stock.price.times(self.position_quantity(&stock.id)))
             .sum();
-        self.cash + holdings_value
+        self.get_cash() + holdings_value
     }
 
-    pub fn get_holdings(&self) -> &HashMap<String, usize> {
+    #[allow(dead_code)]
+    pub fn get_holdings(&self) -> &HashMap<String, Position> {
         &self.holdings
     }
 
-    pub fn get_cash(&self) -> f64 {
-        self.cash
+    pub fn get_cash(&self) -> Money {
+        self.ledger.cash()
+    }
+
+    #[allow(dead_code)]
+    pub fn deposit(&mut self, amount: Money) {
+        self.ledger.record_deposit(amount);
+    }
+
+    #[allow(dead_code)]
+    pub fn withdraw(&mut self, amount: Money) -> Result<(), &'static str> {
+        if amount > self.spendable_cash() {
+            return Err("Insufficient cash to withdraw");
+        }
+        self.ledger.record_withdrawal(amount);
+        Ok(())
+    }
+
+    #[allow(dead_code)]
+    pub fn receive_dividend(&mut self, stock_id: &str, amount_per_share: Money) {
+        let quantity = self.position_quantity(stock_id);
+        if quantity != 0 {
+            // Short positions owe the dividend to the lender
+            self.ledger.record_dividend(stock_id, amount_per_share.times(quantity));
+        }
+    }
+
+    /// Checks the ledger is internally consistent and that the positions
+    /// derived from it match `holdings`.
+    pub fn verify_ledger(&self) -> Result<(), LedgerError> {
+        self.ledger.audit()?;
+        let mut stock_ids: Vec<&String> = self.holdings.keys().collect();
+        for entry in self.ledger.entries() {
+            for posting in &entry.postings {
+                if let Account::Holdings(stock_id) = &posting.account {
+                    stock_ids.push(stock_id);
+                }
+            }
+        }
+        for stock_id in stock_ids {
+            if self.ledger.shares(stock_id) != self.position_quantity(stock_id) {
+                return Err(LedgerError::BalanceMismatch(Account::Holdings(stock_id.clone())));
+            }
+        }
+        Ok(())
     }
 }
 
 impl fmt::Display for Broker {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Broker {}: Cash: ${:.2}, Holdings: {:?}", 
-            self.id, self.cash, self.holdings)
+        let mut stock_ids: Vec<&String> = self.holdings.keys().collect();
+        stock_ids.sort();
+        let holdings: Vec<String> = stock_ids
+            .into_iter()
+            .map(|stock_id| {
+                let position = &self.holdings[stock_id];
+                match self.marks.get(stock_id) {
+                    Some(&mark) => format!("{} {} (uP&L ${:.2})", stock_id, position, position.unrealized_pnl(mark)),
+                    None => format!("{} {}", stock_id, position),
+                }
+            })
+            .collect();
+
+        write!(f, "Broker {}: Cash: ${:.2}, Holdings: [{}], Realized P&L: ${:.2}, Unrealized P&L: ${:.2}, Fees: ${:.2}",
+            self.id, self.get_cash(), holdings.join(", "), self.realized_pnl, self.unrealized_pnl(), self.trading_fees)?;
+        if self.short_selling {
+            write!(f, ", Borrow Fees: ${:.2}", self.borrow_fees)?;
+        }
+        if let Some(margin) = &self.margin {
+            write!(f, ", Buying Power: ${:.2}, Margin Interest: ${:.2}",
+                margin.buying_power, margin.interest_charged)?;
+            if let Some(call) = &margin.call {
+                write!(f, ", MARGIN CALL: ${:.2} ({} rounds left)", call.amount, call.rounds_remaining)?;
+            }
+        }
+        if !self.open_orders.is_empty() {
+            write!(f, ", Open Orders: {}", self.open_orders.len())?;
+        }
+        Ok(())
     }
 }
 
 impl fmt::Debug for Broker {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Broker {{ id: {}, cash: ${:.2}, strategy: {:?}, holdings: {:?} }}", 
-            self.id, self.cash, self.strategy, self.holdings)
+        write!(f, "Broker {{ id: {}, cash: ${:.2}, strategy: {:?}, holdings: {:?}, cost_basis: {:?}, short_selling: {}, margin: {:?}, open_orders: {:?} }}",
+            self.id, self.get_cash(), self.strategy, self.holdings, self.cost_basis, self.short_selling, self.margin, self.open_orders)
     }
-}
\ No newline at end of file
+}
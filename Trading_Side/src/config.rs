@@ -0,0 +1,307 @@
+//! Startup configuration.
+//!
+//! Same scheme as Stock_Side: `trading_side.toml` (or `TRADING_SIDE_CONFIG`),
+//! then `TRADING_SIDE_*` environment overrides, then validation. The AMQP
+//! and market sections are Stock_Side's own types and must agree with its
+//! settings. See `trading_side.example.toml`.
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use serde::Deserialize;
+use rust_decimal::Decimal;
+use stock_side::config::{override_from_env, AmqpConfig, ConfigError, MarketConfig};
+use stock_side::money::Money;
+use crate::borrow::BorrowDesk;
+use log::{info, warn};
+use crate::broker::{Broker, BrokerState};
+use crate::margin::MarginAccount;
+use crate::position::CostBasisMethod;
+use crate::trading_strategy::Strategy;
+
+pub const DEFAULT_CONFIG_PATH: &str = "trading_side.toml";
+pub const CONFIG_PATH_ENV: &str = "TRADING_SIDE_CONFIG";
+
+const ENV_PREFIX: &str = "TRADING_SIDE_";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub amqp: AmqpConfig,
+    pub market: MarketConfig,
+    /// Starting roster, used only when no saved broker accounts exist.
+    pub brokers: Vec<BrokerConfig>,
+    /// Symbols to subscribe to for market data; empty for every symbol.
+    pub symbols: Vec<String>,
+    pub borrow: BorrowConfig,
+    pub web: WebConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BorrowConfig {
+    /// Fee charged per simulated day on the market value of borrowed shares.
+    pub fee_rate_per_day: Decimal,
+    /// Chance per round that the lender recalls an outstanding borrow.
+    pub recall_probability: f64,
+}
+
+impl Default for BorrowConfig {
+    fn default() -> Self {
+        // 3 bps of borrowed market value per day, 2% chance of a recall per round
+        BorrowConfig {
+            fee_rate_per_day: Decimal::new(3, 4),
+            recall_probability: 0.02,
+        }
+    }
+}
+
+impl BorrowConfig {
+    pub fn build(&self) -> BorrowDesk {
+        BorrowDesk::new(self.fee_rate_per_day, self.recall_probability)
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebConfig {
+    /// Address the REST server listens on, e.g. `0.0.0.0:8081`; empty
+    /// disables it.
+    pub listen: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BrokerConfig {
+    pub id: u32,
+    pub cash: Money,
+    pub strategy: Strategy,
+    #[serde(default)]
+    pub cost_basis: CostBasisMethod,
+    #[serde(default)]
+    pub short_selling: bool,
+    /// Present for margin accounts.
+    pub margin: Option<MarginConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MarginConfig {
+    pub initial_requirement: Decimal,
+    pub maintenance_requirement: Decimal,
+    pub annual_interest_rate: Decimal,
+}
+
+impl BrokerConfig {
+    pub fn build(&self) -> Broker {
+        let mut broker = Broker::new(self.id, self.cash, self.strategy.clone()).with_cost_basis(self.cost_basis);
+        if self.short_selling {
+            broker = broker.with_short_selling();
+        }
+        if let Some(margin) = &self.margin {
+            broker = broker.with_margin(MarginAccount::new(
+                margin.initial_requirement,
+                margin.maintenance_requirement,
+                margin.annual_interest_rate,
+            ));
+        }
+        broker
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            amqp: AmqpConfig::default(),
+            market: MarketConfig::default(),
+            brokers: vec![
+                BrokerConfig {
+                    id: 1,
+                    cash: Money::from_f64(10_000.0),
+                    strategy: Strategy::RiskAverse,
+                    cost_basis: CostBasisMethod::Fifo,
+                    short_selling: false,
+                    margin: None,
+                },
+                BrokerConfig {
+                    id: 2,
+                    cash: Money::from_f64(20_000.0),
+                    strategy: Strategy::Aggressive,
+                    cost_basis: CostBasisMethod::Lifo,
+                    short_selling: false,
+                    // 50% initial, 25% maintenance, 8% annual interest on debit balances
+                    margin: Some(MarginConfig {
+                        initial_requirement: Decimal::new(50, 2),
+                        maintenance_requirement: Decimal::new(25, 2),
+                        annual_interest_rate: Decimal::new(8, 2),
+                    }),
+                },
+                BrokerConfig {
+                    id: 3,
+                    cash: Money::from_f64(15_000.0),
+                    strategy: Strategy::Random,
+                    cost_basis: CostBasisMethod::AverageCost,
+                    short_selling: true,
+                    margin: None,
+                },
+            ],
+            symbols: Vec::new(),
+            borrow: BorrowConfig::default(),
+            web: WebConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The configured roster, each broker restored from `saved` if it has
+    /// an account there and built fresh otherwise. Saved accounts of brokers
+    /// no longer configured are left out (and left in the database, so
+    /// configuring the id again brings the account back).
+    pub fn build_brokers(&self, saved: Vec<BrokerState>) -> Vec<Broker> {
+        let mut saved: HashMap<u32, BrokerState> = saved.into_iter().map(|state| (state.id, state)).collect();
+        let brokers = self
+            .brokers
+            .iter()
+            .map(|config| match saved.remove(&config.id) {
+                Some(state) => Broker::restore(state),
+                None => {
+                    info!("[Trading_Side] Broker {} has no saved account, opening one", config.id);
+                    config.build()
+                }
+            })
+            .collect();
+        for id in saved.keys() {
+            warn!("[Trading_Side] Broker {} is no longer configured, not restoring its account", id);
+        }
+        brokers
+    }
+
+    /// Loads the config file (if any), applies environment overrides and
+    /// validates the result.
+    pub fn load() -> Result<Config, ConfigError> {
+        let (path, required) = match env::var(CONFIG_PATH_ENV) {
+            Ok(path) => (path, true),
+            Err(_) => (DEFAULT_CONFIG_PATH.to_string(), false),
+        };
+        let mut config = match fs::read_to_string(&path) {
+            Ok(text) => toml::from_str(&text).map_err(|e| ConfigError::Parse(path.clone(), e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => Config::default(),
+            Err(e) => return Err(ConfigError::Read(path, e)),
+        };
+        config.apply_env_overrides()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply_env_overrides(&mut self) -> Result<(), ConfigError> {
+        self.amqp.apply_env_overrides(ENV_PREFIX)?;
+        self.market.apply_env_overrides(ENV_PREFIX)?;
+        override_from_env(ENV_PREFIX, "BORROW_FEE_RATE_PER_DAY", &mut self.borrow.fee_rate_per_day)?;
+        override_from_env(ENV_PREFIX, "BORROW_RECALL_PROBABILITY", &mut self.borrow.recall_probability)?;
+        override_from_env(ENV_PREFIX, "WEB_LISTEN", &mut self.web.listen)?;
+
+        // Comma-separated subset of the configured roster
+        let mut broker_ids = String::new();
+        override_from_env(ENV_PREFIX, "BROKERS", &mut broker_ids)?;
+        if !broker_ids.is_empty() {
+            let name = format!("{}BROKERS", ENV_PREFIX);
+            let mut wanted = HashSet::new();
+            for id in broker_ids.split(',').map(str::trim) {
+                let id: u32 = id.parse().map_err(|_| ConfigError::Env(name.clone(), id.to_string()))?;
+                if !self.brokers.iter().any(|broker| broker.id == id) {
+                    return Err(ConfigError::Env(name, id.to_string()));
+                }
+                wanted.insert(id);
+            }
+            self.brokers.retain(|broker| wanted.contains(&broker.id));
+        }
+
+        // Comma-separated market data subscription
+        let mut symbols = String::new();
+        override_from_env(ENV_PREFIX, "SYMBOLS", &mut symbols)?;
+        if !symbols.is_empty() {
+            self.symbols = symbols.split(',').map(|symbol| symbol.trim().to_string()).collect();
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: String| Err(ConfigError::Invalid(message));
+
+        self.amqp.validate()?;
+        self.market.validate()?;
+
+        if self.brokers.is_empty() {
+            return invalid("the broker roster is empty".to_string());
+        }
+        let mut ids = HashSet::new();
+        for broker in &self.brokers {
+            if !ids.insert(broker.id) {
+                return invalid(format!("broker {} is listed more than once", broker.id));
+            }
+            if broker.cash < Money::ZERO {
+                return invalid(format!("broker {} must not start with negative cash", broker.id));
+            }
+            if let Some(margin) = &broker.margin {
+                let unit = Decimal::ZERO..=Decimal::ONE;
+                if !unit.contains(&margin.initial_requirement)
+                    || !unit.contains(&margin.maintenance_requirement)
+                    || margin.initial_requirement.is_zero()
+                {
+                    return invalid(format!("broker {} margin requirements must be between 0 and 1", broker.id));
+                }
+                if margin.maintenance_requirement > margin.initial_requirement {
+                    return invalid(format!("broker {} maintenance requirement exceeds the initial requirement", broker.id));
+                }
+                if margin.annual_interest_rate < Decimal::ZERO {
+                    return invalid(format!("broker {} margin interest rate must not be negative", broker.id));
+                }
+            }
+        }
+
+        let mut symbols = HashSet::new();
+        for symbol in &self.symbols {
+            if symbol.is_empty() {
+                return invalid("subscribed symbols must not be empty".to_string());
+            }
+            if !symbols.insert(symbol) {
+                return invalid(format!("symbol {} is subscribed more than once", symbol));
+            }
+        }
+
+        if self.borrow.fee_rate_per_day < Decimal::ZERO {
+            return invalid("borrow.fee_rate_per_day must not be negative".to_string());
+        }
+        // Also rejects NaN, which gen_bool would panic on
+        if !(0.0..=1.0).contains(&self.borrow.recall_probability) {
+            return invalid("borrow.recall_probability must be between 0 and 1".to_string());
+        }
+
+        if !self.web.listen.is_empty() && self.web.listen.parse::<SocketAddr>().is_err() {
+            return invalid(format!("web.listen {:?} must be an address like 0.0.0.0:8081", self.web.listen));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_accounts_are_merged_into_the_configured_roster_by_id() {
+        let config = Config::default();
+        let saved = vec![
+            Broker::new(1, Money::from_f64(12_345.0), Strategy::RiskAverse).state(),
+            Broker::new(9, Money::from_f64(500.0), Strategy::Random).state(),
+        ];
+
+        let brokers = config.build_brokers(saved);
+        let ids: Vec<u32> = brokers.iter().map(|broker| broker.id).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert_eq!(brokers[0].get_cash(), Money::from_f64(12_345.0));
+        assert_eq!(brokers[1].get_cash(), Money::from_f64(20_000.0));
+    }
+}
@@ -0,0 +1,80 @@
+//! End-of-session export of orders, trades and per-broker equity curves as
+//! CSV and Parquet.
+//!
+//! Written by Stock_Side's export code, so the layout and column types are
+//! the same, under `<dir>/trading_side/session_<id>/`. The equity curve is
+//! one row per broker per round plus one at close, taken from the broker
+//! snapshots.
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use stock_side::export::{column, write_table, ColumnType, Table};
+use crate::store::Store;
+
+const TABLES: &[Table] = &[
+    Table {
+        name: "orders",
+        columns: &[
+            column("session_id", ColumnType::Integer),
+            column("sent_at", ColumnType::Text),
+            column("market_time", ColumnType::Text),
+            column("broker_id", ColumnType::Integer),
+            column("stock_id", ColumnType::Text),
+            column("action", ColumnType::Text),
+            column("quantity", ColumnType::Integer),
+            column("price", ColumnType::Decimal),
+        ],
+        query: "SELECT session_id, sent_at, market_time, broker_id, stock_id, action, quantity, price
+                FROM orders WHERE session_id = ?1 ORDER BY id",
+    },
+    Table {
+        name: "trades",
+        columns: &[
+            column("session_id", ColumnType::Integer),
+            column("received_at", ColumnType::Text),
+            column("broker_id", ColumnType::Integer),
+            column("stock_id", ColumnType::Text),
+            column("action", ColumnType::Text),
+            column("quantity", ColumnType::Integer),
+            column("price", ColumnType::Decimal),
+            column("liquidity", ColumnType::Text),
+            column("commission", ColumnType::Decimal),
+            column("exchange_fee", ColumnType::Decimal),
+            column("total_fee", ColumnType::Decimal),
+        ],
+        query: "SELECT session_id, received_at, broker_id, stock_id, action, quantity, price,
+                       liquidity, commission, exchange_fee, total_fee
+                FROM fills WHERE session_id = ?1 ORDER BY id",
+    },
+    Table {
+        name: "equity",
+        columns: &[
+            column("session_id", ColumnType::Integer),
+            column("market_time", ColumnType::Text),
+            column("broker_id", ColumnType::Integer),
+            column("cash", ColumnType::Decimal),
+            column("equity", ColumnType::Decimal),
+            column("realized_pnl", ColumnType::Decimal),
+            column("unrealized_pnl", ColumnType::Decimal),
+            column("trading_fees", ColumnType::Decimal),
+        ],
+        query: "SELECT session_id, market_time, broker_id, cash, equity, realized_pnl, unrealized_pnl, trading_fees
+                FROM broker_snapshots WHERE session_id = ?1 ORDER BY id",
+    },
+];
+
+/// Writes every table for the store's session and returns the directory
+/// the files went to.
+pub fn export_session(store: &Store, dir: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let session_dir = Path::new(dir)
+        .join("trading_side")
+        .join(format!("session_{}", store.session_id()));
+    fs::create_dir_all(&session_dir)?;
+
+    for table in TABLES {
+        let rows = store.session_rows(table.query, table.columns)?;
+        write_table(&session_dir, table, &rows)?;
+    }
+    Ok(session_dir)
+}
@@ -0,0 +1,411 @@
+use std::collections::HashMap;
+use std::fmt;
+use chrono::{DateTime, Utc};
+use stock_side::money::Money;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Account {
+    Cash,
+    /// Shares held in a stock.
+    Holdings(String),
+    /// Counterparty side of every trade.
+    Market,
+    /// Deposits and withdrawals from outside the simulation.
+    Capital,
+    TradingFees,
+    BorrowFees,
+    MarginInterest,
+    Dividends,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    Trade,
+    Fee,
+    BorrowFee,
+    Interest,
+    Dividend,
+    Deposit,
+    Withdrawal,
+    /// Balance carried over from a previous session.
+    Opening,
+}
+
+/// A posting moves either cash or shares of one stock into an account.
+/// Negative amounts move them out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Amount {
+    Cash(Money),
+    Shares(String, i64),
+}
+
+#[derive(Debug, Clone)]
+pub struct Posting {
+    pub account: Account,
+    pub amount: Amount,
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub sequence: u64,
+    pub timestamp: DateTime<Utc>,
+    pub kind: EntryKind,
+    pub description: String,
+    pub postings: Vec<Posting>,
+}
+
+impl Entry {
+    /// Cash postings sum to zero, and so do share postings per stock.
+    pub fn is_balanced(&self) -> bool {
+        let mut cash = Money::ZERO;
+        let mut shares: HashMap<&str, i64> = HashMap::new();
+        for posting in &self.postings {
+            match &posting.amount {
+                Amount::Cash(amount) => cash += *amount,
+                Amount::Shares(stock_id, quantity) => *shares.entry(stock_id).or_insert(0) += quantity,
+            }
+        }
+        cash == Money::ZERO && shares.values().all(|&quantity| quantity == 0)
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} {} {:?}: {}", self.sequence, self.timestamp.format("%H:%M:%S"), self.kind, self.description)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LedgerError {
+    Unbalanced(String),
+    /// An entry whose sequence number is not one more than the last.
+    OutOfSequence(u64),
+    BalanceMismatch(Account),
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerError::Unbalanced(description) => write!(f, "unbalanced entry: {}", description),
+            LedgerError::OutOfSequence(sequence) => write!(f, "entry #{} is out of sequence", sequence),
+            LedgerError::BalanceMismatch(account) => write!(f, "balance mismatch in {:?}", account),
+        }
+    }
+}
+
+/// Append-only double-entry ledger. Running balances are kept alongside the
+/// entries so lookups are cheap; `audit` checks them against the entries.
+#[derive(Debug, Clone, Default)]
+pub struct Ledger {
+    entries: Vec<Entry>,
+    cash_balances: HashMap<Account, Money>,
+    share_balances: HashMap<(Account, String), i64>,
+}
+
+impl Ledger {
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn post(
+        &mut self,
+        kind: EntryKind,
+        description: String,
+        postings: Vec<Posting>,
+    ) -> Result<(), LedgerError> {
+        let entry = Entry {
+            sequence: self.entries.len() as u64 + 1,
+            timestamp: Utc::now(),
+            kind,
+            description,
+            postings,
+        };
+        if !entry.is_balanced() {
+            return Err(LedgerError::Unbalanced(entry.description));
+        }
+
+        for posting in &entry.postings {
+            match &posting.amount {
+                Amount::Cash(amount) => {
+                    *self.cash_balances.entry(posting.account.clone()).or_default() += *amount
+                }
+                Amount::Shares(stock_id, quantity) => {
+                    *self
+                        .share_balances
+                        .entry((posting.account.clone(), stock_id.clone()))
+                        .or_default() += quantity
+                }
+            }
+        }
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    pub fn cash_balance(&self, account: &Account) -> Money {
+        self.cash_balances.get(account).copied().unwrap_or_default()
+    }
+
+    pub fn share_balance(&self, account: &Account, stock_id: &str) -> i64 {
+        self.share_balances
+            .get(&(account.clone(), stock_id.to_string()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn cash(&self) -> Money {
+        self.cash_balance(&Account::Cash)
+    }
+
+    pub fn shares(&self, stock_id: &str) -> i64 {
+        self.share_balance(&Account::Holdings(stock_id.to_string()), stock_id)
+    }
+
+    /// Checks that entries are numbered without gaps and each balances on
+    /// its own, that the running balances are what the postings add up to,
+    /// and that the balances themselves net to zero (the trial balance).
+    pub fn audit(&self) -> Result<(), LedgerError> {
+        let mut cash: HashMap<&Account, Money> = HashMap::new();
+        let mut shares: HashMap<(&Account, &str), i64> = HashMap::new();
+        for (index, entry) in self.entries.iter().enumerate() {
+            if entry.sequence != index as u64 + 1 {
+                return Err(LedgerError::OutOfSequence(entry.sequence));
+            }
+            if !entry.is_balanced() {
+                return Err(LedgerError::Unbalanced(entry.description.clone()));
+            }
+            for posting in &entry.postings {
+                match &posting.amount {
+                    Amount::Cash(amount) => *cash.entry(&posting.account).or_default() += *amount,
+                    Amount::Shares(stock_id, quantity) => {
+                        *shares.entry((&posting.account, stock_id.as_str())).or_default() += quantity
+                    }
+                }
+            }
+        }
+
+        let cash_accounts = cash.keys().copied().chain(self.cash_balances.keys());
+        for account in cash_accounts {
+            if cash.get(account).copied().unwrap_or_default() != self.cash_balance(account) {
+                return Err(LedgerError::BalanceMismatch(account.clone()));
+            }
+        }
+        let share_accounts = shares
+            .keys()
+            .copied()
+            .chain(self.share_balances.keys().map(|(account, stock_id)| (account, stock_id.as_str())));
+        for (account, stock_id) in share_accounts {
+            if shares.get(&(account, stock_id)).copied().unwrap_or_default() != self.share_balance(account, stock_id) {
+                return Err(LedgerError::BalanceMismatch(account.clone()));
+            }
+        }
+
+        if self.cash_balances.values().copied().sum::<Money>() != Money::ZERO {
+            return Err(LedgerError::Unbalanced("cash trial balance".to_string()));
+        }
+        let mut share_totals: HashMap<&str, i64> = HashMap::new();
+        for ((_, stock_id), quantity) in &self.share_balances {
+            *share_totals.entry(stock_id).or_default() += quantity;
+        }
+        if let Some((stock_id, _)) = share_totals.iter().find(|(_, &total)| total != 0) {
+            return Err(LedgerError::Unbalanced(format!("{} share trial balance", stock_id)));
+        }
+        Ok(())
+    }
+
+    pub fn record_trade(&mut self, stock_id: &str, quantity: i64, price: Money) {
+        let cost = price.times(quantity);
+        let action = if quantity > 0 { "Buy" } else { "Sell" };
+        self.post(
+            EntryKind::Trade,
+            format!("{} {} {} @ {}", action, quantity.unsigned_abs(), stock_id, price),
+            vec![
+                Posting { account: Account::Cash, amount: Amount::Cash(-cost) },
+                Posting { account: Account::Market, amount: Amount::Cash(cost) },
+                Posting {
+                    account: Account::Holdings(stock_id.to_string()),
+                    amount: Amount::Shares(stock_id.to_string(), quantity),
+                },
+                Posting {
+                    account: Account::Market,
+                    amount: Amount::Shares(stock_id.to_string(), -quantity),
+                },
+            ],
+        )
+        .expect("ledger entry is balanced by construction");
+    }
+
+    /// Moves `amount` out of cash into an expense account (or back in, when
+    /// negative, e.g. a maker rebate).
+    pub fn record_charge(
+        &mut self,
+        kind: EntryKind,
+        account: Account,
+        amount: Money,
+        description: String,
+    ) {
+        self.post(
+            kind,
+            description,
+            vec![
+                Posting { account: Account::Cash, amount: Amount::Cash(-amount) },
+                Posting { account, amount: Amount::Cash(amount) },
+            ],
+        )
+        .expect("ledger entry is balanced by construction");
+    }
+
+    pub fn record_deposit(&mut self, amount: Money) {
+        self.post(
+            EntryKind::Deposit,
+            format!("Deposit {}", amount),
+            vec![
+                Posting { account: Account::Capital, amount: Amount::Cash(-amount) },
+                Posting { account: Account::Cash, amount: Amount::Cash(amount) },
+            ],
+        )
+        .expect("ledger entry is balanced by construction");
+    }
+
+    /// Opens the ledger of a restored broker with the cash and shares it
+    /// held when its previous session was saved.
+    pub fn record_opening_balance(&mut self, cash: Money, positions: &[(String, i64)]) {
+        let mut postings = vec![
+            Posting { account: Account::Capital, amount: Amount::Cash(-cash) },
+            Posting { account: Account::Cash, amount: Amount::Cash(cash) },
+        ];
+        for (stock_id, quantity) in positions {
+            postings.push(Posting {
+                account: Account::Capital,
+                amount: Amount::Shares(stock_id.clone(), -quantity),
+            });
+            postings.push(Posting {
+                account: Account::Holdings(stock_id.clone()),
+                amount: Amount::Shares(stock_id.clone(), *quantity),
+            });
+        }
+        self.post(EntryKind::Opening, format!("Opening balance {}", cash), postings)
+            .expect("ledger entry is balanced by construction");
+    }
+
+    pub fn record_withdrawal(&mut self, amount: Money) {
+        self.post(
+            EntryKind::Withdrawal,
+            format!("Withdrawal {}", amount),
+            vec![
+                Posting { account: Account::Cash, amount: Amount::Cash(-amount) },
+                Posting { account: Account::Capital, amount: Amount::Cash(amount) },
+            ],
+        )
+        .expect("ledger entry is balanced by construction");
+    }
+
+    pub fn record_dividend(&mut self, stock_id: &str, amount: Money) {
+        self.post(
+            EntryKind::Dividend,
+            format!("Dividend on {} {}", stock_id, amount),
+            vec![
+                Posting { account: Account::Dividends, amount: Amount::Cash(-amount) },
+                Posting { account: Account::Cash, amount: Amount::Cash(amount) },
+            ],
+        )
+        .expect("ledger entry is balanced by construction");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+
+    fn dollars(amount: i64) -> Money {
+        Money::new(Decimal::from(amount))
+    }
+
+    #[test]
+    fn posting_updates_balances_and_numbers_entries() {
+        let mut ledger = Ledger::default();
+        ledger.record_deposit(dollars(1_000));
+        ledger.record_trade("AAPL", 5, dollars(100));
+        ledger.record_charge(EntryKind::Fee, Account::TradingFees, dollars(2), "Fees".to_string());
+        ledger.record_trade("AAPL", -2, dollars(110));
+
+        assert_eq!(ledger.cash(), dollars(1_000 - 500 - 2 + 220));
+        assert_eq!(ledger.shares("AAPL"), 3);
+        assert_eq!(ledger.share_balance(&Account::Market, "AAPL"), -3);
+        assert_eq!(ledger.cash_balance(&Account::TradingFees), dollars(2));
+        assert_eq!(ledger.cash_balance(&Account::Capital), dollars(-1_000));
+        let sequences: Vec<u64> = ledger.entries().iter().map(|entry| entry.sequence).collect();
+        assert_eq!(sequences, [1, 2, 3, 4]);
+        assert_eq!(ledger.audit(), Ok(()));
+    }
+
+    #[test]
+    fn opening_balance_carries_cash_and_shares() {
+        let mut ledger = Ledger::default();
+        ledger.record_opening_balance(dollars(500), &[("AAPL".to_string(), 10), ("MSFT".to_string(), -4)]);
+
+        assert_eq!(ledger.cash(), dollars(500));
+        assert_eq!(ledger.shares("AAPL"), 10);
+        assert_eq!(ledger.shares("MSFT"), -4);
+        assert_eq!(ledger.audit(), Ok(()));
+    }
+
+    #[test]
+    fn dividends_and_withdrawals_post_balanced_entries() {
+        let mut ledger = Ledger::default();
+        ledger.record_deposit(dollars(1_000));
+        ledger.record_dividend("AAPL", dollars(12));
+        // A short position pays the dividend to the lender
+        ledger.record_dividend("MSFT", dollars(-5));
+        ledger.record_withdrawal(dollars(300));
+
+        assert!(ledger.entries().iter().all(Entry::is_balanced));
+        let kinds: Vec<EntryKind> = ledger.entries().iter().map(|entry| entry.kind).collect();
+        assert_eq!(kinds, [EntryKind::Deposit, EntryKind::Dividend, EntryKind::Dividend, EntryKind::Withdrawal]);
+        assert_eq!(ledger.cash(), dollars(1_000 + 12 - 5 - 300));
+        assert_eq!(ledger.cash_balance(&Account::Dividends), dollars(-7));
+        assert_eq!(ledger.cash_balance(&Account::Capital), dollars(-700));
+        assert_eq!(ledger.audit(), Ok(()));
+    }
+
+    #[test]
+    fn refuses_an_unbalanced_entry() {
+        let mut ledger = Ledger::default();
+        let postings = vec![
+            Posting { account: Account::Cash, amount: Amount::Cash(dollars(10)) },
+            Posting { account: Account::Capital, amount: Amount::Cash(dollars(-9)) },
+        ];
+        assert_eq!(
+            ledger.post(EntryKind::Deposit, "Short deposit".to_string(), postings),
+            Err(LedgerError::Unbalanced("Short deposit".to_string()))
+        );
+
+        let postings = vec![
+            Posting { account: Account::Holdings("AAPL".to_string()), amount: Amount::Shares("AAPL".to_string(), 1) },
+            Posting { account: Account::Market, amount: Amount::Shares("MSFT".to_string(), -1) },
+        ];
+        assert!(ledger.post(EntryKind::Trade, "Swap".to_string(), postings).is_err());
+
+        assert!(ledger.entries().is_empty());
+        assert_eq!(ledger.cash(), Money::ZERO);
+    }
+
+    #[test]
+    fn audit_catches_a_tampered_entry() {
+        let mut ledger = Ledger::default();
+        ledger.record_deposit(dollars(100));
+        ledger.record_trade("AAPL", 1, dollars(50));
+
+        let mut renumbered = ledger.clone();
+        renumbered.entries[1].sequence = 5;
+        assert_eq!(renumbered.audit(), Err(LedgerError::OutOfSequence(5)));
+
+        let mut rewritten = ledger.clone();
+        rewritten.entries[0].postings[1].amount = Amount::Cash(dollars(90));
+        assert!(rewritten.audit().is_err());
+
+        let mut drifted = ledger;
+        drifted.cash_balances.insert(Account::Cash, dollars(1));
+        assert_eq!(drifted.audit(), Err(LedgerError::BalanceMismatch(Account::Cash)));
+    }
+}
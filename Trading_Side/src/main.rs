@@ -3,17 +3,43 @@ mod trading_strategy;
 mod broker;
 mod messaging;
 mod utils;
+mod borrow;
+mod margin;
+mod position;
+mod ledger;
+mod store;
+mod export;
+mod config;
+mod market_data;
+mod api;
+mod rpc;
 
-use simulation::{run_trading_side, Stock};
-use broker::Broker;
-use trading_strategy::Strategy;
-use messaging::{connect_to_rabbitmq, receive_stock_updates};
+use simulation::{reconcile_open_orders, run_trading_side, Stock};
+use store::{Store, DEFAULT_DATABASE_PATH};
+use export::export_session;
+use stock_side::export::DEFAULT_EXPORT_DIR;
+use config::Config;
+use stock_side::transport::Transport;
+use rpc::RpcClient;
+use stock_side::memory::MemoryBroker;
+use messaging::{receive_stock_updates, receive_activity_rejections, receive_fills, receive_bars};
+use std::error::Error;
+use std::io;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use log::info;
+use tokio::sync::{watch, Mutex};
+use log::{info, warn, error};
 use env_logger::Env;
 use tokio::time::sleep;
 use std::time::Duration;
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+
+/// `Trading_Side in-process` runs a Stock_Side market in this process.
+const IN_PROCESS_COMMAND: &str = "in-process";
+/// `Trading_Side query quote <SYMBOL> | instrument <SYMBOL> | order <ACTIVITY_ID>`
+/// asks a running Stock_Side and prints the answer as JSON.
+const QUERY_COMMAND: &str = "query";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -21,35 +47,182 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
     info!("[Trading_Side] Starting...");
 
-    // Connect to RabbitMQ
-    let channel = connect_to_rabbitmq().await?;
-    info!("[Trading_Side] Connected to RabbitMQ");
+    // Configuration file and environment overrides
+    let config = Arc::new(Config::load().inspect_err(|e| error!("[Trading_Side] {}", e))?);
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some(IN_PROCESS_COMMAND) => return run_in_process(config).await,
+        Some(QUERY_COMMAND) => return run_query(&config, &args[1..]).await,
+        _ => {}
+    }
+
+    // Supervised RabbitMQ connection; reconnects on its own
+    let transport = Transport::start_amqp(config.amqp.clone());
+    run(config, transport, shutdown_on_signal()).await
+}
+
+/// Turns true on Ctrl+C or, on Unix, SIGTERM.
+fn shutdown_on_signal() -> watch::Receiver<bool> {
+    let (sender, receiver) = watch::channel(false);
+    tokio::spawn(async move {
+        if shutdown_requested().await.is_ok() {
+            info!("[Trading_Side] Shutdown requested, finishing the current round");
+            sender.send_replace(true);
+            // Keep the receivers from seeing the channel close
+            sender.closed().await;
+        }
+    });
+    receiver
+}
+
+#[cfg(unix)]
+async fn shutdown_requested() -> io::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        _ = terminate.recv() => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+async fn shutdown_requested() -> io::Result<()> {
+    tokio::signal::ctrl_c().await
+}
+
+/// Sends one query to Stock_Side over RabbitMQ and prints the reply.
+async fn run_query(config: &Config, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (kind, key) = match args {
+        [kind, key] => (kind.as_str(), key.as_str()),
+        _ => return Err("usage: Trading_Side query quote <SYMBOL> | instrument <SYMBOL> | order <ACTIVITY_ID>".into()),
+    };
+    let transport = Transport::start_amqp(config.amqp.clone());
+    let rpc = RpcClient::start(transport, Duration::from_millis(config.amqp.rpc_timeout_ms));
+    let reply = match kind {
+        "quote" => serde_json::to_string_pretty(&rpc.quote(key).await?)?,
+        "instrument" => serde_json::to_string_pretty(&rpc.instrument(key).await?)?,
+        "order" => serde_json::to_string_pretty(&rpc.order_status(key).await?)?,
+        _ => return Err(format!("unknown query {}; expected quote, instrument or order", kind).into()),
+    };
+    println!("{}", reply);
+    Ok(())
+}
+
+/// Runs Stock_Side and Trading_Side together on one in-memory broker, with
+/// no RabbitMQ. Stock_Side reads its own configuration as usual, but keeps
+/// Trading_Side's AMQP settings, market hours and pace so both sides share
+/// one transport and their clocks agree.
+async fn run_in_process(config: Arc<Config>) -> Result<(), Box<dyn Error>> {
+    let mut stock_config = stock_side::config::Config::load().inspect_err(|e| error!("[Stock_Side] {}", e))?;
+    stock_config.amqp = config.amqp.clone();
+    stock_config.market.open = config.market.open;
+    stock_config.market.close = config.market.close;
+    stock_config.market.cycle_minutes = config.market.cycle_minutes;
+    stock_config.market.tick_interval_secs = config.market.tick_interval_secs;
+    stock_config.market.validate().inspect_err(|e| error!("[Stock_Side] {}", e))?;
+
+    let transport = Transport::in_memory(MemoryBroker::new(), config.amqp.clone());
+    info!("[Trading_Side] Running Stock_Side in process on an in-memory broker");
+
+    // The market keeps filling until trading is done, then stops with it;
+    // its journal resumes it next time
+    let market = stock_side::market::run_market(stock_config, Arc::clone(&transport));
+    let trading = run(config, transport, shutdown_on_signal());
+    tokio::pin!(trading);
+    tokio::select! {
+        result = &mut trading => result,
+        result = market => {
+            if let Err(e) = result {
+                error!("[Stock_Side] {}", e);
+            }
+            trading.await
+        }
+    }
+}
+
+/// Runs the trading session on `transport` until the market closes or
+/// `shutdown` turns true, then saves broker accounts and exports the
+/// session.
+async fn run(config: Arc<Config>, transport: Arc<Transport>, shutdown: watch::Receiver<bool>) -> Result<(), Box<dyn Error>> {
+    // Session database
+    let store = Arc::new(Store::open(DEFAULT_DATABASE_PATH)?);
+    info!("[Trading_Side] Recording session {} to {}", store.session_id(), DEFAULT_DATABASE_PATH);
 
-    // Initialize shared state
+    // Initialize shared state, carrying brokers over from the last session
     let stocks = Arc::new(Mutex::new(Vec::<Stock>::new()));
-    let brokers = Arc::new(Mutex::new(vec![
-        Broker::new(1, 10_000.0, Strategy::RiskAverse),
-        Broker::new(2, 20_000.0, Strategy::Aggressive),
-        Broker::new(3, 15_000.0, Strategy::Random),
-    ]));
+    let saved_accounts = store.load_broker_accounts()?;
+    if !saved_accounts.is_empty() {
+        info!("[Trading_Side] Found {} broker accounts from the last session", saved_accounts.len());
+    }
+    let brokers = Arc::new(Mutex::new(config.build_brokers(saved_accounts)));
 
     // Start stock updates consumer first
     let stocks_clone = Arc::clone(&stocks);
-    let channel_clone = channel.clone();
+    let transport_clone = Arc::clone(&transport);
+    let config_clone = Arc::clone(&config);
     tokio::spawn(async move {
-        receive_stock_updates(&channel_clone, stocks_clone).await;
+        receive_stock_updates(&transport_clone, &config_clone.symbols, stocks_clone).await;
     });
 
+    let rejections_transport = Arc::clone(&transport);
+    let rejections_brokers = Arc::clone(&brokers);
+    let rejections_store = Arc::clone(&store);
+    tokio::spawn(async move {
+        receive_activity_rejections(&rejections_transport, rejections_brokers, rejections_store).await;
+    });
+
+    let fills_transport = Arc::clone(&transport);
+    let brokers_clone = Arc::clone(&brokers);
+    let fills_store = Arc::clone(&store);
+    tokio::spawn(async move {
+        receive_fills(&fills_transport, brokers_clone, fills_store).await;
+    });
+
+    let bars_transport = Arc::clone(&transport);
+    tokio::spawn(async move {
+        receive_bars(&bars_transport).await;
+    });
+
+    if !config.web.listen.is_empty() {
+        let listener = TcpListener::bind(&config.web.listen).await?;
+        info!("[Trading_Side] REST server listening on {}", config.web.listen);
+        tokio::spawn(api::serve(listener, Arc::clone(&brokers), Arc::clone(&stocks)));
+    }
+
+    // Settle or resend orders left open by the last session
+    let rpc = RpcClient::start(Arc::clone(&transport), Duration::from_millis(config.amqp.rpc_timeout_ms));
+    reconcile_open_orders(&brokers, &transport, &rpc).await;
+
     // Small delay to ensure consumer is ready
     sleep(Duration::from_millis(100)).await;
     info!("[Trading_Side] System Initialized");
 
-    // Start main trading simulation
+    // Start main trading simulation; a shutdown ends it after the current round
     run_trading_side(
+        shutdown,
         Arc::clone(&brokers),
         Arc::clone(&stocks),
-        channel,
-    ).await;
+        Arc::clone(&transport),
+        &config,
+        Arc::clone(&store),
+        Arc::clone(&rpc),
+    )
+    .await;
+
+    let health = transport.health();
+    if health.buffered > 0 {
+        warn!("[Trading_Side] {} messages were never delivered: RabbitMQ link {}", health.buffered, health);
+    }
+
+    // Save broker accounts for the next session
+    let brokers_guard = brokers.lock().await;
+    store.save_broker_accounts(&brokers_guard)?;
+    info!("[Trading_Side] Saved {} broker accounts", brokers_guard.len());
+
+    match export_session(&store, DEFAULT_EXPORT_DIR) {
+        Ok(dir) => info!("[Trading_Side] Exported session data to {}", dir.display()),
+        Err(e) => error!("[Trading_Side] Failed to export session data: {:?}", e),
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,166 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use stock_side::money::Money;
+
+/// Rounds a broker gets to meet a margin call before it is liquidated: with
+/// 2, a call issued in one round is liquidated in the second round after it
+/// if equity is still short.
+pub const MARGIN_CALL_GRACE_ROUNDS: u32 = 2;
+
+/// Days per year used to turn the annual debit rate into a daily charge.
+const INTEREST_DAYS_PER_YEAR: i64 = 360;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarginCall {
+    /// Equity shortfall against the maintenance requirement at the latest mark.
+    pub amount: Money,
+    pub rounds_remaining: u32,
+}
+
+#[derive(Debug, Clone)]
+pub enum MarginStatus {
+    Ok,
+    Call(MarginCall),
+    Liquidate,
+}
+
+/// Margin terms and running state for a broker trading on credit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarginAccount {
+    /// Fraction of gross position value that must be funded by equity to open positions.
+    pub initial_requirement: Decimal,
+    /// Fraction of gross position value equity must stay above to avoid a call.
+    pub maintenance_requirement: Decimal,
+    /// Annual rate charged on a negative cash (debit) balance.
+    pub annual_interest_rate: Decimal,
+    pub buying_power: Money,
+    pub call: Option<MarginCall>,
+    pub interest_charged: Money,
+}
+
+impl MarginAccount {
+    pub fn new(
+        initial_requirement: Decimal,
+        maintenance_requirement: Decimal,
+        annual_interest_rate: Decimal,
+    ) -> Self {
+        MarginAccount {
+            initial_requirement,
+            maintenance_requirement,
+            annual_interest_rate,
+            buying_power: Money::ZERO,
+            call: None,
+            interest_charged: Money::ZERO,
+        }
+    }
+
+    /// Purchases the excess equity over the initial requirement can support.
+    pub fn buying_power_for(&self, equity: Money, gross_position_value: Money) -> Money {
+        let excess = equity - gross_position_value.apply_rate(self.initial_requirement);
+        if excess <= Money::ZERO || self.initial_requirement.is_zero() {
+            return Money::ZERO;
+        }
+        excess.apply_rate(Decimal::ONE / self.initial_requirement)
+    }
+
+    pub fn maintenance_for(&self, gross_position_value: Money) -> Money {
+        gross_position_value.apply_rate(self.maintenance_requirement)
+    }
+
+    /// Updates the call state after marking the account and reports what the
+    /// broker has to do about it.
+    pub fn assess(&mut self, equity: Money, gross_position_value: Money) -> MarginStatus {
+        let maintenance = self.maintenance_for(gross_position_value);
+        if equity >= maintenance {
+            self.call = None;
+            return MarginStatus::Ok;
+        }
+
+        let rounds_remaining = match self.call.take() {
+            Some(call) => call.rounds_remaining.saturating_sub(1),
+            None => MARGIN_CALL_GRACE_ROUNDS,
+        };
+        if rounds_remaining == 0 {
+            return MarginStatus::Liquidate;
+        }
+        let call = MarginCall {
+            amount: maintenance - equity,
+            rounds_remaining,
+        };
+        self.call = Some(call.clone());
+        MarginStatus::Call(call)
+    }
+
+    /// One day of interest on a debit balance; zero if cash is positive.
+    pub fn daily_interest(&self, cash: Money) -> Money {
+        if cash >= Money::ZERO {
+            return Money::ZERO;
+        }
+        (-cash).apply_rate(self.annual_interest_rate / Decimal::from(INTEREST_DAYS_PER_YEAR))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dollars(amount: i64) -> Money {
+        Money::from_f64(amount as f64)
+    }
+
+    /// 50% initial, 25% maintenance, 8% a year.
+    fn account() -> MarginAccount {
+        MarginAccount::new(Decimal::new(50, 2), Decimal::new(25, 2), Decimal::new(8, 2))
+    }
+
+    fn call(status: MarginStatus) -> MarginCall {
+        match status {
+            MarginStatus::Call(call) => call,
+            other => panic!("expected a call, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn no_call_while_equity_covers_maintenance() {
+        let mut account = account();
+        assert!(matches!(account.assess(dollars(250), dollars(1_000)), MarginStatus::Ok));
+        assert!(account.call.is_none());
+    }
+
+    #[test]
+    fn liquidates_on_the_last_grace_round() {
+        let mut account = account();
+
+        let first = call(account.assess(dollars(200), dollars(1_000)));
+        assert_eq!(first.amount, dollars(50));
+        assert_eq!(first.rounds_remaining, MARGIN_CALL_GRACE_ROUNDS);
+
+        // The amount follows the latest mark
+        let second = call(account.assess(dollars(150), dollars(1_000)));
+        assert_eq!(second.amount, dollars(100));
+        assert_eq!(second.rounds_remaining, MARGIN_CALL_GRACE_ROUNDS - 1);
+
+        assert!(matches!(account.assess(dollars(240), dollars(1_000)), MarginStatus::Liquidate));
+        assert!(account.call.is_none());
+    }
+
+    #[test]
+    fn meeting_the_call_clears_it() {
+        let mut account = account();
+        call(account.assess(dollars(200), dollars(1_000)));
+        assert!(matches!(account.assess(dollars(300), dollars(1_000)), MarginStatus::Ok));
+        assert!(account.call.is_none());
+
+        let fresh = call(account.assess(dollars(200), dollars(1_000)));
+        assert_eq!(fresh.rounds_remaining, MARGIN_CALL_GRACE_ROUNDS);
+    }
+
+    #[test]
+    fn buying_power_and_interest() {
+        let account = account();
+        assert_eq!(account.buying_power_for(dollars(1_000), dollars(1_000)), dollars(1_000));
+        assert_eq!(account.buying_power_for(dollars(400), dollars(1_000)), Money::ZERO);
+        assert_eq!(account.daily_interest(dollars(-3_600)), Money::from_f64(0.80));
+        assert_eq!(account.daily_interest(dollars(3_600)), Money::ZERO);
+    }
+}
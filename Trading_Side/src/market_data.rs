@@ -0,0 +1,73 @@
+//! Incremental market data from Stock_Side.
+//!
+//! Stock_Side publishes `stock_side::market_data::MarketData`: per-symbol
+//! `Update`s carry only changed fields under the next sequence number and
+//! `Snapshot`s carry the full state. `MarketDataBook` applies them in order and reports when a symbol
+//! needs a snapshot: on a sequence gap, or on an update for a symbol it has
+//! no snapshot of yet. Until that snapshot arrives the symbol's updates are
+//! ignored.
+
+use std::collections::{HashMap, HashSet};
+use log::warn;
+use stock_side::market_data::MarketData;
+use crate::simulation::Stock;
+
+#[derive(Default)]
+pub struct MarketDataBook {
+    /// Last applied sequence per symbol.
+    sequences: HashMap<String, u64>,
+    /// Symbols with a snapshot request outstanding.
+    awaiting_snapshot: HashSet<String>,
+}
+
+impl MarketDataBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies `message` to `stocks`. Returns the symbol to request a
+    /// snapshot for, if this message revealed a gap.
+    pub fn apply(&mut self, message: MarketData, stocks: &mut Vec<Stock>) -> Option<String> {
+        match message {
+            MarketData::Snapshot { symbol, sequence, price, available_quantity } => {
+                let stock = Stock { id: symbol.clone(), price, available_quantity };
+                match stocks.iter_mut().find(|stock| stock.id == symbol) {
+                    Some(existing) => *existing = stock,
+                    None => stocks.push(stock),
+                }
+                self.awaiting_snapshot.remove(&symbol);
+                self.sequences.insert(symbol, sequence);
+                None
+            }
+            MarketData::Update { symbol, sequence, price, available_quantity } => {
+                if self.awaiting_snapshot.contains(&symbol) {
+                    return None;
+                }
+                let Some(&last) = self.sequences.get(&symbol) else {
+                    self.awaiting_snapshot.insert(symbol.clone());
+                    return Some(symbol);
+                };
+                if sequence <= last {
+                    // Already applied, or superseded by a snapshot
+                    return None;
+                }
+                if sequence != last + 1 {
+                    warn!("Market data gap for {}: expected sequence {}, got {}", symbol, last + 1, sequence);
+                    self.awaiting_snapshot.insert(symbol.clone());
+                    return Some(symbol);
+                }
+
+                if let Some(stock) = stocks.iter_mut().find(|stock| stock.id == symbol) {
+                    if let Some(price) = price {
+                        stock.price = price;
+                    }
+                    if let Some(available_quantity) = available_quantity {
+                        stock.available_quantity = available_quantity;
+                    }
+                }
+                self.sequences.insert(symbol, sequence);
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::VecDeque;
+use std::fmt;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use stock_side::money::Money;
+
+/// Which lots a closing trade is matched against when realizing P&L.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum CostBasisMethod {
+    #[default]
+    Fifo,
+    Lifo,
+    AverageCost,
+}
+
+/// Shares opened in a single trade. Quantity is signed like the position:
+/// short lots are negative.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaxLot {
+    pub quantity: i64,
+    pub price: Money,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Position {
+    pub quantity: i64,
+    pub lots: VecDeque<TaxLot>,
+}
+
+impl Position {
+    /// Volume-weighted cost of the open lots, rounded to cents.
+    pub fn average_cost(&self) -> Money {
+        if self.quantity == 0 {
+            return Money::ZERO;
+        }
+        let cost: Money = self.lots.iter().map(|lot| lot.price.times(lot.quantity)).sum();
+        Money::new(cost.amount() / Decimal::from(self.quantity)).round_cents()
+    }
+
+    pub fn unrealized_pnl(&self, mark: Money) -> Money {
+        self.lots.iter().map(|lot| (mark - lot.price).times(lot.quantity)).sum()
+    }
+
+    /// Applies a signed trade of `delta` shares at `price` and returns the
+    /// P&L realized by any lots it closed.
+    pub fn apply_trade(&mut self, delta: i64, price: Money, method: CostBasisMethod) -> Money {
+        let mut realized = Money::ZERO;
+        let mut remaining = delta;
+
+        while remaining != 0 && self.quantity != 0 && remaining.signum() != self.quantity.signum() {
+            let lot = match method {
+                CostBasisMethod::Lifo => self.lots.back_mut(),
+                CostBasisMethod::Fifo | CostBasisMethod::AverageCost => self.lots.front_mut(),
+            };
+            let Some(lot) = lot else { break };
+
+            let closed = remaining.abs().min(lot.quantity.abs()) * lot.quantity.signum();
+            realized += (price - lot.price).times(closed);
+            lot.quantity -= closed;
+            self.quantity -= closed;
+            remaining += closed;
+
+            if lot.quantity == 0 {
+                match method {
+                    CostBasisMethod::Lifo => self.lots.pop_back(),
+                    CostBasisMethod::Fifo | CostBasisMethod::AverageCost => self.lots.pop_front(),
+                };
+            }
+        }
+
+        if remaining != 0 {
+            self.quantity += remaining;
+            self.lots.push_back(TaxLot { quantity: remaining, price });
+            if method == CostBasisMethod::AverageCost {
+                let average = self.average_cost();
+                self.lots = VecDeque::from([TaxLot { quantity: self.quantity, price: average }]);
+            }
+        }
+
+        realized
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} @ ${:.2}", self.quantity, self.average_cost())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dollars(amount: i64) -> Money {
+        Money::new(Decimal::from(amount))
+    }
+
+    /// Buys 10 @ $10 and 10 @ $20, then sells 15 @ $30.
+    fn sell_out_of_two_lots(method: CostBasisMethod) -> (Position, Money) {
+        let mut position = Position::default();
+        assert_eq!(position.apply_trade(10, dollars(10), method), Money::ZERO);
+        assert_eq!(position.apply_trade(10, dollars(20), method), Money::ZERO);
+        let realized = position.apply_trade(-15, dollars(30), method);
+        (position, realized)
+    }
+
+    fn lots(position: &Position) -> Vec<(i64, Money)> {
+        position.lots.iter().map(|lot| (lot.quantity, lot.price)).collect()
+    }
+
+    #[test]
+    fn fifo_closes_the_oldest_lot_first() {
+        let (position, realized) = sell_out_of_two_lots(CostBasisMethod::Fifo);
+        assert_eq!(realized, dollars(20 * 10 + 10 * 5));
+        assert_eq!(position.quantity, 5);
+        assert_eq!(lots(&position), [(5, dollars(20))]);
+    }
+
+    #[test]
+    fn lifo_closes_the_newest_lot_first() {
+        let (position, realized) = sell_out_of_two_lots(CostBasisMethod::Lifo);
+        assert_eq!(realized, dollars(10 * 10 + 20 * 5));
+        assert_eq!(position.quantity, 5);
+        assert_eq!(lots(&position), [(5, dollars(10))]);
+    }
+
+    #[test]
+    fn average_cost_keeps_one_lot_at_the_blended_price() {
+        let (position, realized) = sell_out_of_two_lots(CostBasisMethod::AverageCost);
+        assert_eq!(realized, dollars(15 * 15));
+        assert_eq!(position.quantity, 5);
+        assert_eq!(lots(&position), [(5, dollars(15))]);
+        assert_eq!(position.average_cost(), dollars(15));
+    }
+
+    #[test]
+    fn average_cost_is_rounded_to_cents() {
+        let mut position = Position::default();
+        position.apply_trade(1, dollars(10), CostBasisMethod::AverageCost);
+        position.apply_trade(2, dollars(11), CostBasisMethod::AverageCost);
+
+        // $32 over 3 shares
+        let average = Money::new(Decimal::new(1067, 2));
+        assert_eq!(lots(&position), [(3, average)]);
+        let realized = position.apply_trade(-3, dollars(12), CostBasisMethod::AverageCost);
+        assert_eq!(realized, Money::new(Decimal::new(399, 2)));
+        assert!(position.lots.is_empty());
+    }
+
+    #[test]
+    fn covering_a_short_realizes_the_drop_in_price() {
+        let mut position = Position::default();
+        position.apply_trade(-10, dollars(50), CostBasisMethod::Fifo);
+        assert_eq!(position.unrealized_pnl(dollars(45)), dollars(50));
+
+        let realized = position.apply_trade(4, dollars(40), CostBasisMethod::Fifo);
+        assert_eq!(realized, dollars(40));
+        assert_eq!(lots(&position), [(-6, dollars(50))]);
+    }
+
+    #[test]
+    fn a_trade_through_zero_opens_the_other_side() {
+        let mut position = Position::default();
+        position.apply_trade(5, dollars(10), CostBasisMethod::Fifo);
+
+        let realized = position.apply_trade(-8, dollars(12), CostBasisMethod::Fifo);
+        assert_eq!(realized, dollars(10));
+        assert_eq!(position.quantity, -3);
+        assert_eq!(lots(&position), [(-3, dollars(12))]);
+    }
+}
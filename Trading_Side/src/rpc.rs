@@ -0,0 +1,250 @@
+//! Queries to Stock_Side over request/reply: the current quote for a symbol,
+//! the status of an activity by id, and instrument reference data.
+//!
+//! Requests go to the `rpc_requests` queue with `reply_to` set to a private
+//! reply queue and a fresh `correlation_id`; one listener task hands each
+//! reply to the call waiting on that id. Every call gives up after the
+//! configured timeout, and the request expires in RabbitMQ at the same time
+//! so Stock_Side does not answer a caller that has moved on.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use futures_util::StreamExt;
+use log::{debug, error, warn};
+use stock_side::brokers::OrderStatus;
+use stock_side::codec::DecodeError;
+use stock_side::instrument::Instrument;
+use stock_side::link::Published;
+use stock_side::rpc::{Quote, RpcErrorCode, RpcReply, RpcRequest};
+use tokio::sync::{oneshot, watch};
+use uuid::Uuid;
+use stock_side::transport::{Delivery, Transport};
+
+#[derive(Debug)]
+pub enum RpcError {
+    /// No reply queue within the timeout, or it was lost while waiting.
+    NotConnected,
+    Timeout(Duration),
+    Publish(lapin::Error),
+    Decode(DecodeError),
+    UnknownSymbol(String),
+    InvalidRequest(String),
+    /// Stock_Side failed to answer.
+    Server(String),
+    /// A well-formed reply of the wrong type.
+    UnexpectedReply(String),
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::NotConnected => write!(f, "not connected to Stock_Side"),
+            RpcError::Timeout(timeout) => write!(f, "no reply from Stock_Side within {:?}", timeout),
+            RpcError::Publish(e) => write!(f, "failed to send request: {}", e),
+            RpcError::Decode(e) => write!(f, "invalid reply: {}", e),
+            RpcError::UnknownSymbol(message) => write!(f, "{}", message),
+            RpcError::InvalidRequest(message) => write!(f, "{}", message),
+            RpcError::Server(message) => write!(f, "Stock_Side error: {}", message),
+            RpcError::UnexpectedReply(reply) => write!(f, "unexpected reply {}", reply),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+type Pending = Arc<Mutex<HashMap<String, oneshot::Sender<Delivery>>>>;
+
+pub struct RpcClient {
+    transport: Arc<Transport>,
+    timeout: Duration,
+    /// The current reply queue, `None` while there is none.
+    reply_queue: watch::Receiver<Option<String>>,
+    pending: Pending,
+}
+
+impl RpcClient {
+    /// Starts listening for replies on `transport`. Calls wait up to
+    /// `timeout` for a reply.
+    pub fn start(transport: Arc<Transport>, timeout: Duration) -> Arc<Self> {
+        let (reply_queue_sender, reply_queue) = watch::channel(None);
+        let pending = Pending::default();
+        tokio::spawn(receive_replies(Arc::clone(&transport), reply_queue_sender, Arc::clone(&pending)));
+        Arc::new(RpcClient { transport, timeout, reply_queue, pending })
+    }
+
+    pub async fn quote(&self, symbol: &str) -> Result<Quote, RpcError> {
+        match self.call(&RpcRequest::Quote { symbol: symbol.to_string() }).await? {
+            RpcReply::Quote(quote) => Ok(quote),
+            other => Err(RpcError::UnexpectedReply(format!("{:?}", other))),
+        }
+    }
+
+    pub async fn order_status(&self, activity_id: &str) -> Result<OrderStatus, RpcError> {
+        match self.call(&RpcRequest::OrderStatus { activity_id: activity_id.to_string() }).await? {
+            RpcReply::OrderStatus { activity_id: id, status } if id == activity_id => Ok(status),
+            other => Err(RpcError::UnexpectedReply(format!("{:?}", other))),
+        }
+    }
+
+    pub async fn instrument(&self, symbol: &str) -> Result<Instrument, RpcError> {
+        match self.call(&RpcRequest::Instrument { symbol: symbol.to_string() }).await? {
+            RpcReply::Instrument(instrument) => Ok(instrument),
+            other => Err(RpcError::UnexpectedReply(format!("{:?}", other))),
+        }
+    }
+
+    /// Sends `request` and waits for its reply, turning `Error` replies
+    /// into the matching `RpcError`.
+    async fn call(&self, request: &RpcRequest) -> Result<RpcReply, RpcError> {
+        let correlation_id = Uuid::new_v4().to_string();
+        let reply = tokio::time::timeout(self.timeout, self.send(request, &correlation_id)).await;
+        self.pending.lock().unwrap().remove(&correlation_id);
+        let delivery = match reply {
+            Ok(result) => result?,
+            Err(_) if self.reply_queue.borrow().is_none() => return Err(RpcError::NotConnected),
+            Err(_) => return Err(RpcError::Timeout(self.timeout)),
+        };
+
+        match delivery.decode::<RpcReply>().map_err(RpcError::Decode)? {
+            RpcReply::Error { code, message } => Err(match code {
+                RpcErrorCode::UnknownSymbol => RpcError::UnknownSymbol(message),
+                RpcErrorCode::InvalidRequest => RpcError::InvalidRequest(message),
+                RpcErrorCode::Internal => RpcError::Server(message),
+            }),
+            reply => Ok(reply),
+        }
+    }
+
+    async fn send(&self, request: &RpcRequest, correlation_id: &str) -> Result<Delivery, RpcError> {
+        let mut reply_queue = self.reply_queue.clone();
+        let reply_to = reply_queue
+            .wait_for(Option::is_some)
+            .await
+            .map_err(|_| RpcError::NotConnected)?
+            .clone()
+            .unwrap_or_default();
+        // The link would buffer the request until it reconnects, by which
+        // time the reply queue is gone
+        if !self.transport.is_connected() {
+            return Err(RpcError::NotConnected);
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        self.pending.lock().unwrap().insert(correlation_id.to_string(), sender);
+
+        let amqp = self.transport.amqp();
+        let properties = amqp
+            .encoding
+            .properties()
+            .with_reply_to(reply_to.into())
+            .with_correlation_id(correlation_id.into())
+            .with_expiration(self.timeout.as_millis().to_string().into());
+        let published = self
+            .transport
+            .publish_with(&amqp.exchange, &amqp.queues.rpc_requests, amqp.encoding.encode(request), properties)
+            .await
+            .map_err(RpcError::Publish)?;
+        // A buffered request would only go out once the reply queue is gone
+        if published != Published::Confirmed {
+            return Err(RpcError::NotConnected);
+        }
+
+        // The sender is dropped if the reply queue is lost
+        receiver.await.map_err(|_| RpcError::NotConnected)
+    }
+}
+
+/// Consumes the reply queue, handing each reply to the call waiting on its
+/// correlation id. Calls still waiting when the queue is lost fail with
+/// `NotConnected`, as their replies can no longer arrive.
+async fn receive_replies(transport: Arc<Transport>, reply_queue: watch::Sender<Option<String>>, pending: Pending) {
+    loop {
+        let (queue, mut consumer) = transport.reply_queue("rpc_reply_consumer").await;
+        debug!("Receiving RPC replies on {}", queue);
+        reply_queue.send_replace(Some(queue));
+
+        while let Some(delivery) = consumer.next().await {
+            let delivery = match delivery {
+                Ok(delivery) => delivery,
+                Err(err) => {
+                    warn!("RPC reply consumer failed: {:?}", err);
+                    break;
+                }
+            };
+            if let Err(err) = delivery.ack().await {
+                error!("Failed to acknowledge message: {:?}", err);
+            }
+            let caller = delivery
+                .properties
+                .correlation_id()
+                .as_ref()
+                .and_then(|correlation_id| pending.lock().unwrap().remove(correlation_id.as_str()));
+            match caller {
+                Some(caller) => {
+                    let _ = caller.send(delivery);
+                }
+                None => debug!("Dropped RPC reply for a call that is no longer waiting"),
+            }
+        }
+
+        reply_queue.send_replace(None);
+        pending.lock().unwrap().clear();
+        warn!("RPC reply consumer stopped, resubscribing");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stock_side::brokers::RejectReason;
+    use stock_side::codec::Encoding;
+    use stock_side::config::AmqpConfig;
+    use stock_side::memory::MemoryBroker;
+    use stock_side::money::Money;
+
+    /// Answers requests on `rpc_requests` the way Stock_Side does, from
+    /// canned data.
+    async fn serve(transport: Arc<Transport>) {
+        let mut requests = transport.consumer(&transport.amqp().queues.rpc_requests, "test_rpc").await;
+        while let Some(Ok(request)) = requests.next().await {
+            let reply = match request.decode::<RpcRequest>().unwrap() {
+                RpcRequest::Quote { symbol } if symbol == "AAPL" => {
+                    RpcReply::Quote(Quote { symbol, price: Money::from_f64(150.0), available_quantity: 1000 })
+                }
+                RpcRequest::OrderStatus { activity_id } => RpcReply::OrderStatus {
+                    activity_id,
+                    status: OrderStatus::Rejected { reason: RejectReason::InvalidLotSize },
+                },
+                RpcRequest::Quote { symbol } | RpcRequest::Instrument { symbol } => {
+                    RpcReply::Error { code: RpcErrorCode::UnknownSymbol, message: format!("unknown symbol {}", symbol) }
+                }
+            };
+            let encoding = Encoding::of(&request.properties).unwrap_or_default();
+            let properties = encoding
+                .properties()
+                .with_correlation_id(request.properties.correlation_id().clone().unwrap());
+            let reply_to = request.properties.reply_to().clone().unwrap();
+            transport.publish_with("", reply_to.as_str(), encoding.encode(&reply), properties).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_get_their_replies_over_the_in_memory_transport() {
+        let transport = Transport::in_memory(MemoryBroker::new(), AmqpConfig::default());
+        tokio::spawn(serve(Arc::clone(&transport)));
+        let client = RpcClient::start(transport, Duration::from_secs(5));
+
+        let quote = client.quote("AAPL").await.unwrap();
+        assert_eq!((quote.symbol.as_str(), quote.price, quote.available_quantity), ("AAPL", Money::from_f64(150.0), 1000));
+
+        let status = client.order_status("order-1").await.unwrap();
+        assert_eq!(status, OrderStatus::Rejected { reason: RejectReason::InvalidLotSize });
+
+        match client.instrument("MSFT").await {
+            Err(RpcError::UnknownSymbol(message)) => assert_eq!(message, "unknown symbol MSFT"),
+            other => panic!("expected UnknownSymbol, got {:?}", other),
+        }
+    }
+}
@@ -1,30 +1,49 @@
-use crate::broker::Broker;
+use stock_side::money::Money;
+use crate::broker::{Broker, OpenOrder};
 use crate::trading_strategy::Action;
 use crate::messaging::send_broker_action;
 use crate::utils::print_stock_list;
-use lapin::Channel;
-use chrono::{NaiveTime, Timelike};
+use crate::borrow::BorrowDesk;
+use crate::config::Config;
+use crate::margin::MarginStatus;
+use crate::store::Store;
+use stock_side::transport::Transport;
+use stock_side::brokers::OrderStatus;
+use stock_side::instrument::Instrument;
+use crate::rpc::{RpcClient, RpcError};
+use chrono::NaiveTime;
 use tokio::time::{sleep, Duration};
+use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use log::{info, error};
+use tokio::sync::{watch, Mutex};
+use log::{info, warn, error};
 use rand::{thread_rng, Rng};
 use serde::{Serialize, Deserialize};
+use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Stock {
     pub id: String,
-    pub price: f64,
+    pub price: Money,
     pub available_quantity: usize,
 }
 
+/// Trades a round every cycle until the market closes, or until `shutdown`
+/// turns true, then closes out the day. A shutdown never interrupts a round.
 pub async fn run_trading_side(
+    mut shutdown: watch::Receiver<bool>,
     brokers: Arc<Mutex<Vec<Broker>>>,
     stocks: Arc<Mutex<Vec<Stock>>>,
-    channel: Channel,
+    transport: Arc<Transport>,
+    config: &Config,
+    store: Arc<Store>,
+    rpc: Arc<RpcClient>,
 ) {
-    let mut current_time = NaiveTime::from_hms_opt(9, 0, 0).unwrap();
-    
+    let market = &config.market;
+    let mut current_time = market.open;
+    let mut instruments = HashMap::new();
+    let mut borrow_desk = config.borrow.build();
+
     info!("[System] Market Open at {}", current_time.format("%I:%M %p"));
     log_broker_accounts("Broker Accounts Before Market Open", &brokers).await;
 
@@ -37,15 +56,24 @@ pub async fn run_trading_side(
             break;
         }
         drop(stocks_guard);
+        if *shutdown.borrow() {
+            return;
+        }
         info!("Waiting for stock data...");
         sleep(Duration::from_secs(1)).await;
     }
 
     // Main trading loop
-    while current_time.hour() < 16 {
+    while current_time < market.close {
         info!("\n=== Trading Round: {} ===", current_time.format("%I:%M %p"));
+        if !transport.is_connected() {
+            warn!("[System] RabbitMQ link {}; orders are buffered", transport.health());
+        }
         
-        // 1. Show updated broker accounts
+        // 1. Mark positions and show updated broker accounts
+        mark_brokers_to_market(&stocks, &brokers).await;
+        record_borrowed_values(&brokers).await;
+        record_broker_snapshots(&stocks, &brokers, &store, current_time).await;
         log_broker_accounts("Current Broker Accounts", &brokers).await;
         
         // 2. Show updated stock prices
@@ -55,29 +83,55 @@ pub async fn run_trading_side(
             print_stock_list(&stocks_guard);
         }
         
-        // 3. Process broker actions
+        // Instrument limits size the forced trades below
+        refresh_instruments(&stocks, &rpc, &mut instruments).await;
+
+        // 3. Buy in any recalled borrows
+        process_borrow_recalls(&stocks, &brokers, &mut borrow_desk, &instruments, &transport, &store, current_time).await;
+
+        // 4. Mark margin accounts and enforce calls
+        process_margin_checks(&stocks, &brokers, &instruments, &transport, &store, current_time).await;
+
+        // 5. Process broker actions
         info!("=== Broker Actions ===");
-        perform_broker_actions(&stocks, &brokers, current_time, &channel).await;
+        perform_broker_actions(&stocks, &brokers, current_time, &transport, &store, &mut borrow_desk).await;
+        audit_broker_ledgers(&brokers).await;
         
         info!("----------------------------------------");
         
-        sleep(Duration::from_secs(10)).await;
+        tokio::select! {
+            _ = sleep(Duration::from_secs(market.tick_interval_secs)) => {}
+            _ = shutdown.wait_for(|&stop| stop) => {}
+        }
         current_time = current_time
-            .overflowing_add_signed(chrono::Duration::minutes(30))
+            .overflowing_add_signed(chrono::Duration::minutes(market.cycle_minutes))
             .0;
+        if *shutdown.borrow() {
+            info!("[System] Trading stopped early");
+            break;
+        }
     }
 
     info!("[System] Market Close at {}", current_time.format("%I:%M %p"));
+    accrue_borrow_fees(&brokers, &borrow_desk, market.cycles_per_session()).await;
+    accrue_margin_interest(&brokers).await;
+    mark_brokers_to_market(&stocks, &brokers).await;
+    record_broker_snapshots(&stocks, &brokers, &store, current_time).await;
+    log_end_of_day_costs(&stocks, &brokers).await;
+    log_broker_ledgers(&brokers).await;
 }
 
 async fn perform_broker_actions(
     stocks: &Arc<Mutex<Vec<Stock>>>,
     brokers: &Arc<Mutex<Vec<Broker>>>,
-    _current_time: NaiveTime,  // Added underscore to unused variable
-    channel: &Channel,
+    current_time: NaiveTime,
+    transport: &Transport,
+    store: &Store,
+    borrow_desk: &mut BorrowDesk,
 ) {
     let mut brokers_locked = brokers.lock().await;
     let stocks_locked = stocks.lock().await;
+    borrow_desk.reconcile(brokers_locked.iter());
 
     if stocks_locked.is_empty() {
         info!("No stocks available for trading");
@@ -98,23 +152,22 @@ async fn perform_broker_actions(
                     if let Some(stock) = stocks_clone.get(rng.gen_range(0..stocks_len)) {
                         let quantity = rng.gen_range(1..=5);
                         if broker.buy(stock, quantity).is_ok() {
-                            if let Err(e) = send_broker_action(channel, broker.id, "Buy", &stock.id, quantity).await {
-                                error!("Failed to send buy action: {:?}", e);
-                            }
-                            log_broker_action(broker.id, "Buy", &stock.id, quantity);
+                            submit_activity(transport, store, current_time, broker, "Buy", stock, quantity).await;
                         }
                     }
                 }
             }
             Action::Sell => {
-                if !broker.holdings.is_empty() && stocks_len > 0 {
+                if (!broker.holdings.is_empty() || broker.short_selling) && stocks_len > 0 {
                     if let Some(stock) = stocks_clone.get(rng.gen_range(0..stocks_len)) {
                         let quantity = rng.gen_range(1..=5);
-                        if broker.sell(stock, quantity).is_ok() {
-                            if let Err(e) = send_broker_action(channel, broker.id, "Sell", &stock.id, quantity).await {
-                                error!("Failed to send sell action: {:?}", e);
-                            }
-                            log_broker_action(broker.id, "Sell", &stock.id, quantity);
+                        let result = if broker.short_selling {
+                            broker.sell_short(stock, quantity, borrow_desk)
+                        } else {
+                            broker.sell(stock, quantity)
+                        };
+                        if result.is_ok() {
+                            submit_activity(transport, store, current_time, broker, "Sell", stock, quantity).await;
                         }
                     }
                 }
@@ -126,6 +179,274 @@ async fn perform_broker_actions(
     }
 }
 
+/// Publishes a trade the broker has checked, records it and keeps it open
+/// until Stock_Side fills it, which books it, or rejects it.
+async fn submit_activity(
+    transport: &Transport,
+    store: &Store,
+    current_time: NaiveTime,
+    broker: &mut Broker,
+    action: &str,
+    stock: &Stock,
+    quantity: usize,
+) {
+    // Stock_Side applies each id once, however often it is delivered
+    let activity_id = Uuid::new_v4().to_string();
+    // Confirmed by the broker, or buffered by the link until it can be
+    if let Err(e) = send_broker_action(transport, &activity_id, broker.id, action, &stock.id, quantity).await {
+        error!("Failed to send {} action: {:?}", action, e);
+    }
+    let order = OpenOrder {
+        activity_id,
+        stock_id: stock.id.clone(),
+        action: action.to_string(),
+        quantity,
+        price: stock.price,
+    };
+    if let Err(e) = store.record_order(current_time, broker.id, &order) {
+        error!("Failed to record order: {:?}", e);
+    }
+    broker.open_orders.push(order);
+    log_broker_action(broker.id, action, &stock.id, quantity);
+}
+
+/// Settles open orders carried over from the last session that Stock_Side
+/// already filled or rejected, and sends again, under the same id, those it
+/// never received. Orders it cannot be asked about stay open.
+pub async fn reconcile_open_orders(brokers: &Arc<Mutex<Vec<Broker>>>, transport: &Transport, rpc: &RpcClient) {
+    let open_orders: Vec<(u32, OpenOrder)> = {
+        let brokers_guard = brokers.lock().await;
+        brokers_guard
+            .iter()
+            .flat_map(|broker| broker.open_orders.iter().map(|order| (broker.id, order.clone())))
+            .filter(|(_, order)| !order.activity_id.is_empty())
+            .collect()
+    };
+    if open_orders.is_empty() {
+        return;
+    }
+    info!("Checking {} open orders from the last session with Stock_Side", open_orders.len());
+
+    for (broker_id, order) in open_orders {
+        let status = match rpc.order_status(&order.activity_id).await {
+            Ok(status) => status,
+            // Every other order would wait out the same timeout
+            Err(e @ (RpcError::NotConnected | RpcError::Timeout(_))) => {
+                warn!("Could not check open orders with Stock_Side, leaving them open: {}", e);
+                return;
+            }
+            Err(e) => {
+                warn!("Could not check order {}, leaving it open: {}", order.activity_id, e);
+                continue;
+            }
+        };
+        match status {
+            OrderStatus::Unknown => {
+                info!("Resending order {} that Stock_Side never received", order.activity_id);
+                let sent = send_broker_action(
+                    transport,
+                    &order.activity_id,
+                    broker_id,
+                    &order.action,
+                    &order.stock_id,
+                    order.quantity,
+                )
+                .await;
+                if let Err(e) = sent {
+                    error!("Failed to resend order {}: {:?}", order.activity_id, e);
+                }
+            }
+            settled => {
+                info!("Order {} was settled while we were away: {:?}", order.activity_id, settled);
+                let mut brokers_guard = brokers.lock().await;
+                if let Some(broker) = brokers_guard.iter_mut().find(|broker| broker.id == broker_id) {
+                    // Its fill may also be waiting on the queue; whichever
+                    // comes first books it
+                    match settled {
+                        OrderStatus::Filled { quantity, price } => {
+                            broker.fill(&order.activity_id, quantity, price);
+                        }
+                        _ => broker.reject_order(&order.activity_id),
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn process_borrow_recalls(
+    stocks: &Arc<Mutex<Vec<Stock>>>,
+    brokers: &Arc<Mutex<Vec<Broker>>>,
+    borrow_desk: &mut BorrowDesk,
+    instruments: &HashMap<String, Instrument>,
+    transport: &Transport,
+    store: &Store,
+    current_time: NaiveTime,
+) {
+    let mut brokers_locked = brokers.lock().await;
+    let stocks_locked = stocks.lock().await;
+    borrow_desk.reconcile(brokers_locked.iter());
+
+    for stock_id in borrow_desk.recalls() {
+        info!("Borrow recalled for {}, buying in short positions", stock_id);
+    }
+    for stock_id in borrow_desk.recalled() {
+        let Some(stock) = stocks_locked.iter().find(|stock| stock.id == *stock_id) else {
+            continue;
+        };
+        // Without its limits the order could be rejected; retried next round
+        let Some(instrument) = instruments.get(stock_id) else {
+            continue;
+        };
+        for broker in brokers_locked.iter_mut() {
+            let quantity = broker.buy_in(stock, instrument);
+            if quantity > 0 {
+                submit_activity(transport, store, current_time, broker, "Buy", stock, quantity).await;
+            }
+        }
+    }
+}
+
+/// Fetches reference data for symbols we have none for yet. It is fixed for
+/// the session, so each symbol is asked for once; failures are retried the
+/// next round.
+async fn refresh_instruments(
+    stocks: &Arc<Mutex<Vec<Stock>>>,
+    rpc: &RpcClient,
+    instruments: &mut HashMap<String, Instrument>,
+) {
+    let missing: Vec<String> = stocks
+        .lock()
+        .await
+        .iter()
+        .filter(|stock| !instruments.contains_key(&stock.id))
+        .map(|stock| stock.id.clone())
+        .collect();
+    for symbol in missing {
+        match rpc.instrument(&symbol).await {
+            Ok(instrument) => {
+                instruments.insert(symbol, instrument);
+            }
+            // Every other symbol would wait out the same timeout
+            Err(e @ (RpcError::NotConnected | RpcError::Timeout(_))) => {
+                warn!("Could not fetch instrument data from Stock_Side: {}", e);
+                return;
+            }
+            Err(e) => warn!("Could not fetch instrument data for {}: {}", symbol, e),
+        }
+    }
+}
+
+async fn process_margin_checks(
+    stocks: &Arc<Mutex<Vec<Stock>>>,
+    brokers: &Arc<Mutex<Vec<Broker>>>,
+    instruments: &HashMap<String, Instrument>,
+    transport: &Transport,
+    store: &Store,
+    current_time: NaiveTime,
+) {
+    let mut brokers_locked = brokers.lock().await;
+    let stocks_locked = stocks.lock().await;
+
+    for broker in brokers_locked.iter_mut() {
+        match broker.check_margin(&stocks_locked) {
+            MarginStatus::Ok => {}
+            MarginStatus::Call(call) => info!(
+                "Broker {} margin call: ${:.2} short of maintenance, {} rounds to meet it",
+                broker.id, call.amount, call.rounds_remaining
+            ),
+            MarginStatus::Liquidate => {
+                info!("Broker {} failed to meet margin call, liquidating", broker.id);
+                for (stock, action, quantity) in broker.liquidate(&stocks_locked, instruments) {
+                    submit_activity(transport, store, current_time, broker, action, &stock, quantity).await;
+                }
+            }
+        }
+    }
+}
+
+async fn accrue_margin_interest(brokers: &Arc<Mutex<Vec<Broker>>>) {
+    let mut brokers_locked = brokers.lock().await;
+    for broker in brokers_locked.iter_mut().filter(|broker| broker.margin.is_some()) {
+        let interest = broker.accrue_margin_interest();
+        info!("Broker {} charged ${:.2} in margin interest", broker.id, interest);
+    }
+}
+
+async fn record_borrowed_values(brokers: &Arc<Mutex<Vec<Broker>>>) {
+    let mut brokers_locked = brokers.lock().await;
+    for broker in brokers_locked.iter_mut().filter(|broker| broker.short_selling) {
+        broker.record_borrowed_value();
+    }
+}
+
+async fn accrue_borrow_fees(brokers: &Arc<Mutex<Vec<Broker>>>, borrow_desk: &BorrowDesk, rounds_per_day: usize) {
+    let mut brokers_locked = brokers.lock().await;
+    for broker in brokers_locked.iter_mut().filter(|broker| broker.short_selling) {
+        let fee = broker.accrue_borrow_fees(borrow_desk.fee_rate_per_day, rounds_per_day);
+        info!("Broker {} charged ${:.2} in borrow fees", broker.id, fee);
+    }
+}
+
+async fn log_end_of_day_costs(stocks: &Arc<Mutex<Vec<Stock>>>, brokers: &Arc<Mutex<Vec<Broker>>>) {
+    let brokers_guard = brokers.lock().await;
+    let stocks_guard = stocks.lock().await;
+    info!("   === End of Day Costs ===");
+    for broker in brokers_guard.iter() {
+        let total_costs = broker.trading_fees
+            + broker.borrow_fees
+            + broker.margin.as_ref().map_or(Money::ZERO, |margin| margin.interest_charged);
+        info!(
+            "        Broker {}: Fees ${:.2}, Borrow ${:.2}, Total Costs ${:.2}, Net Value ${:.2}",
+            broker.id,
+            broker.trading_fees,
+            broker.borrow_fees,
+            total_costs,
+            broker.get_total_value(&stocks_guard)
+        );
+    }
+}
+
+async fn mark_brokers_to_market(stocks: &Arc<Mutex<Vec<Stock>>>, brokers: &Arc<Mutex<Vec<Broker>>>) {
+    let mut brokers_guard = brokers.lock().await;
+    let stocks_guard = stocks.lock().await;
+    for broker in brokers_guard.iter_mut() {
+        broker.mark_to_market(&stocks_guard);
+    }
+}
+
+async fn audit_broker_ledgers(brokers: &Arc<Mutex<Vec<Broker>>>) {
+    let brokers_guard = brokers.lock().await;
+    for broker in brokers_guard.iter() {
+        if let Err(e) = broker.verify_ledger() {
+            error!("Broker {} ledger invariant violated: {}", broker.id, e);
+        }
+    }
+}
+
+async fn log_broker_ledgers(brokers: &Arc<Mutex<Vec<Broker>>>) {
+    let brokers_guard = brokers.lock().await;
+    for broker in brokers_guard.iter() {
+        info!("   === Broker {} Ledger ===", broker.id);
+        for entry in broker.ledger.entries() {
+            info!("        {}", entry);
+        }
+    }
+}
+
+async fn record_broker_snapshots(
+    stocks: &Arc<Mutex<Vec<Stock>>>,
+    brokers: &Arc<Mutex<Vec<Broker>>>,
+    store: &Store,
+    current_time: NaiveTime,
+) {
+    let brokers_guard = brokers.lock().await;
+    let stocks_guard = stocks.lock().await;
+    if let Err(e) = store.record_broker_snapshots(current_time, &brokers_guard, &stocks_guard) {
+        error!("Failed to record broker snapshots: {:?}", e);
+    }
+}
+
 async fn log_broker_accounts(message: &str, brokers: &Arc<Mutex<Vec<Broker>>>) {
     let brokers_guard = brokers.lock().await;
     info!("   === {} ===", message);
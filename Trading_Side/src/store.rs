@@ -0,0 +1,387 @@
+//! SQLite record of a Trading_Side session.
+//!
+//! Same conventions as Stock_Side's store: one `sessions` row per run, money
+//! as decimal strings, RFC 3339 UTC wall-clock timestamps and `market_time`
+//! as the simulated `HH:MM` clock.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use chrono::{NaiveTime, Utc};
+use rusqlite::{params, Connection};
+use rusqlite::types::Type;
+use crate::broker::{Broker, BrokerState, OpenOrder};
+use stock_side::export::{self, Column, Value};
+use stock_side::store::add_missing_column;
+use stock_side::brokers::{ActivityRejection, Fill};
+use crate::simulation::Stock;
+
+pub const DEFAULT_DATABASE_PATH: &str = "trading_side.db";
+
+pub const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS sessions (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    started_at  TEXT NOT NULL
+);
+
+-- Activities published to Stock_Side
+CREATE TABLE IF NOT EXISTS orders (
+    id           INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_id   INTEGER NOT NULL REFERENCES sessions(id),
+    activity_id  TEXT,
+    sent_at      TEXT NOT NULL,
+    market_time  TEXT NOT NULL,
+    broker_id    INTEGER NOT NULL,
+    stock_id     TEXT NOT NULL,
+    action       TEXT NOT NULL,
+    quantity     INTEGER NOT NULL,
+    price        TEXT NOT NULL
+);
+
+-- Execution reports received from Stock_Side
+CREATE TABLE IF NOT EXISTS fills (
+    id            INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_id    INTEGER NOT NULL REFERENCES sessions(id),
+    activity_id   TEXT,
+    received_at   TEXT NOT NULL,
+    broker_id     INTEGER NOT NULL,
+    stock_id      TEXT NOT NULL,
+    action        TEXT NOT NULL,
+    quantity      INTEGER NOT NULL,
+    price         TEXT NOT NULL,
+    liquidity     TEXT NOT NULL,
+    commission    TEXT NOT NULL,
+    exchange_fee  TEXT NOT NULL,
+    total_fee     TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS rejections (
+    id           INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_id   INTEGER NOT NULL REFERENCES sessions(id),
+    activity_id  TEXT,
+    received_at  TEXT NOT NULL,
+    broker_id    INTEGER NOT NULL,
+    stock_id     TEXT NOT NULL,
+    action       TEXT NOT NULL,
+    quantity     INTEGER NOT NULL,
+    price        TEXT,
+    reason       TEXT NOT NULL
+);
+
+-- Broker state at the start of every round and at market close.
+-- `holdings` is a JSON object of stock id to signed quantity.
+CREATE TABLE IF NOT EXISTS broker_snapshots (
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_id      INTEGER NOT NULL REFERENCES sessions(id),
+    market_time     TEXT NOT NULL,
+    broker_id       INTEGER NOT NULL,
+    cash            TEXT NOT NULL,
+    equity          TEXT NOT NULL,
+    realized_pnl    TEXT NOT NULL,
+    unrealized_pnl  TEXT NOT NULL,
+    trading_fees    TEXT NOT NULL,
+    holdings        TEXT NOT NULL
+);
+
+-- Latest saved state of every broker, carried from one session to the next.
+-- `state` is the JSON-encoded BrokerState.
+CREATE TABLE IF NOT EXISTS broker_accounts (
+    broker_id   INTEGER PRIMARY KEY,
+    session_id  INTEGER NOT NULL REFERENCES sessions(id),
+    saved_at    TEXT NOT NULL,
+    state       TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_orders_session_broker ON orders(session_id, broker_id);
+CREATE INDEX IF NOT EXISTS idx_snapshots_session_broker ON broker_snapshots(session_id, broker_id);
+";
+
+/// Built after `activity_id` is added to the tables of older databases.
+const ACTIVITY_ID_INDEXES: &str = "
+CREATE INDEX IF NOT EXISTS idx_orders_activity ON orders(activity_id);
+CREATE INDEX IF NOT EXISTS idx_fills_activity ON fills(activity_id);
+CREATE INDEX IF NOT EXISTS idx_rejections_activity ON rejections(activity_id);
+";
+
+const ACTIVITY_ID_TABLES: [&str; 3] = ["orders", "fills", "rejections"];
+
+pub struct Store {
+    conn: Mutex<Connection>,
+    session_id: i64,
+}
+
+impl Store {
+    /// Opens (or creates) the database and starts a new session.
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        // Older databases lack `activity_id`, and the indexes on it with it
+        for table in ACTIVITY_ID_TABLES {
+            add_missing_column(&conn, table, "activity_id", "TEXT")?;
+        }
+        conn.execute_batch(ACTIVITY_ID_INDEXES)?;
+        conn.execute(
+            "INSERT INTO sessions (started_at) VALUES (?1)",
+            params![Utc::now().to_rfc3339()],
+        )?;
+        let session_id = conn.last_insert_rowid();
+        Ok(Store {
+            conn: Mutex::new(conn),
+            session_id,
+        })
+    }
+
+    pub fn session_id(&self) -> i64 {
+        self.session_id
+    }
+
+    pub fn record_order(&self, market_time: NaiveTime, broker_id: u32, order: &OpenOrder) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO orders (session_id, activity_id, sent_at, market_time, broker_id, stock_id, action,
+                                 quantity, price)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                self.session_id,
+                order.activity_id,
+                Utc::now().to_rfc3339(),
+                market_time.format("%H:%M").to_string(),
+                broker_id,
+                order.stock_id,
+                order.action,
+                order.quantity as i64,
+                order.price.to_string(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn record_fill(&self, fill: &Fill) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO fills (session_id, activity_id, received_at, broker_id, stock_id, action, quantity,
+                                price, liquidity, commission, exchange_fee, total_fee)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                self.session_id,
+                fill.activity_id,
+                Utc::now().to_rfc3339(),
+                fill.broker_id,
+                fill.stock_id,
+                fill.action,
+                fill.quantity as i64,
+                fill.price.to_string(),
+                fill.liquidity.to_string(),
+                fill.fees.commission.to_string(),
+                fill.fees.exchange_fee.to_string(),
+                fill.fees.total.to_string(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn record_rejection(&self, rejection: &ActivityRejection) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO rejections (session_id, activity_id, received_at, broker_id, stock_id, action, quantity,
+                                     price, reason)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                self.session_id,
+                rejection.activity_id,
+                Utc::now().to_rfc3339(),
+                rejection.broker_id,
+                rejection.stock_id,
+                rejection.action,
+                rejection.quantity as i64,
+                rejection.price.map(|price| price.to_string()),
+                rejection.reason.code(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn record_broker_snapshots(
+        &self,
+        market_time: NaiveTime,
+        brokers: &[Broker],
+        stocks: &[Stock],
+    ) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        {
+            let mut statement = tx.prepare(
+                "INSERT INTO broker_snapshots (session_id, market_time, broker_id, cash, equity,
+                                               realized_pnl, unrealized_pnl, trading_fees, holdings)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )?;
+            let market_time = market_time.format("%H:%M").to_string();
+            for broker in brokers {
+                let holdings: BTreeMap<&String, i64> = broker
+                    .holdings
+                    .iter()
+                    .map(|(stock_id, position)| (stock_id, position.quantity))
+                    .collect();
+                statement.execute(params![
+                    self.session_id,
+                    market_time,
+                    broker.id,
+                    broker.get_cash().to_string(),
+                    broker.get_total_value(stocks).to_string(),
+                    broker.realized_pnl.to_string(),
+                    broker.unrealized_pnl().to_string(),
+                    broker.trading_fees.to_string(),
+                    serde_json::to_string(&holdings).unwrap(),
+                ])?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// Overwrites the saved state of every broker in `brokers`.
+    pub fn save_broker_accounts(&self, brokers: &[Broker]) -> rusqlite::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        {
+            let mut statement = tx.prepare(
+                "INSERT OR REPLACE INTO broker_accounts (broker_id, session_id, saved_at, state)
+                 VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            let saved_at = Utc::now().to_rfc3339();
+            for broker in brokers {
+                let state = serde_json::to_string(&broker.state())
+                    .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+                statement.execute(params![broker.id, self.session_id, saved_at, state])?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// Saved broker states ordered by broker id; empty on a first run.
+    pub fn load_broker_accounts(&self) -> rusqlite::Result<Vec<BrokerState>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare("SELECT state FROM broker_accounts ORDER BY broker_id")?;
+        let states = statement.query_map([], |row| {
+            let state: String = row.get(0)?;
+            serde_json::from_str(&state)
+                .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, Type::Text, Box::new(e)))
+        })?;
+        states.collect()
+    }
+
+    /// Runs `query` with this session's id bound as `?1`; see
+    /// `export::session_rows`.
+    pub fn session_rows(&self, query: &str, columns: &[Column]) -> rusqlite::Result<Vec<Vec<Value>>> {
+        export::session_rows(&self.conn.lock().unwrap(), self.session_id, query, columns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stock_side::brokers::RejectReason;
+    use stock_side::export::{column, ColumnType};
+    use stock_side::fees::{FeeBreakdown, Liquidity};
+    use stock_side::money::Money;
+    use crate::trading_strategy::Strategy;
+
+    fn order(activity_id: &str) -> OpenOrder {
+        OpenOrder {
+            activity_id: activity_id.to_string(),
+            stock_id: "AAPL".to_string(),
+            action: "Buy".to_string(),
+            quantity: 10,
+            price: Money::from_f64(150.25),
+        }
+    }
+
+    #[test]
+    fn orders_fills_and_rejections_read_back_for_the_session() {
+        let store = Store::open(":memory:").unwrap();
+        let open = NaiveTime::from_hms_opt(9, 30, 0).unwrap();
+        store.record_order(open, 7, &order("a")).unwrap();
+        store.record_order(open, 7, &order("b")).unwrap();
+        store
+            .record_fill(&Fill {
+                activity_id: "a".to_string(),
+                broker_id: 7,
+                stock_id: "AAPL".to_string(),
+                action: "Buy".to_string(),
+                quantity: 10,
+                price: Money::from_f64(150.5),
+                liquidity: Liquidity::Taker,
+                fees: FeeBreakdown {
+                    commission: Money::from_f64(1.0),
+                    exchange_fee: Money::from_f64(0.03),
+                    total: Money::from_f64(1.03),
+                },
+            })
+            .unwrap();
+        store
+            .record_rejection(&ActivityRejection {
+                activity_id: "b".to_string(),
+                broker_id: 7,
+                stock_id: "AAPL".to_string(),
+                action: "Buy".to_string(),
+                quantity: 10,
+                price: None,
+                reason: RejectReason::InsufficientQuantity,
+            })
+            .unwrap();
+
+        let text = |name| column(name, ColumnType::Text);
+        let orders = store
+            .session_rows(
+                "SELECT activity_id, market_time, price FROM orders WHERE session_id = ?1 ORDER BY id",
+                &[text("activity_id"), text("market_time"), column("price", ColumnType::Decimal)],
+            )
+            .unwrap();
+        assert_eq!(orders.len(), 2);
+        assert!(matches!(
+            orders[0].as_slice(),
+            [Value::Text(id), Value::Text(time), Value::Decimal(price)]
+                if id == "a" && time == "09:30" && Money::new(*price) == Money::from_f64(150.25)
+        ));
+
+        let fills = store
+            .session_rows(
+                "SELECT activity_id, liquidity, total_fee FROM fills WHERE session_id = ?1",
+                &[text("activity_id"), text("liquidity"), column("total_fee", ColumnType::Decimal)],
+            )
+            .unwrap();
+        assert_eq!(fills.len(), 1);
+        assert!(matches!(
+            fills[0].as_slice(),
+            [Value::Text(id), Value::Text(liquidity), Value::Decimal(fee)]
+                if id == "a" && liquidity == "Taker" && Money::new(*fee) == Money::from_f64(1.03)
+        ));
+
+        let rejections = store
+            .session_rows(
+                "SELECT activity_id, reason FROM rejections WHERE session_id = ?1",
+                &[text("activity_id"), text("reason")],
+            )
+            .unwrap();
+        assert_eq!(rejections.len(), 1);
+        assert!(matches!(
+            rejections[0].as_slice(),
+            [Value::Text(id), Value::Text(reason)] if id == "b" && reason == "INSUFFICIENT_QUANTITY"
+        ));
+    }
+
+    #[test]
+    fn saved_broker_accounts_load_back() {
+        let store = Store::open(":memory:").unwrap();
+        assert!(store.load_broker_accounts().unwrap().is_empty());
+
+        let mut broker = Broker::new(3, Money::from_f64(1_000.0), Strategy::Aggressive);
+        broker.open_orders.push(order("a"));
+        let other = Broker::new(1, Money::from_f64(500.0), Strategy::Random);
+        store.save_broker_accounts(&[broker, other]).unwrap();
+
+        let mut broker = Broker::new(3, Money::from_f64(750.0), Strategy::Aggressive);
+        broker.open_orders.push(order("a"));
+        store.save_broker_accounts(&[broker]).unwrap();
+
+        let states = store.load_broker_accounts().unwrap();
+        assert_eq!(states.iter().map(|state| state.id).collect::<Vec<_>>(), [1, 3]);
+        assert_eq!(states[1].cash, Money::from_f64(750.0));
+        assert_eq!(states[1].open_orders, [order("a")]);
+    }
+}
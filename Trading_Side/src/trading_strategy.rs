@@ -1,8 +1,11 @@
 use rand::{Rng, seq::SliceRandom};
+use stock_side::money::Money;
 use crate::broker::Broker;
 use crate::simulation::Stock;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub enum Strategy {
     Aggressive,
     RiskAverse,
@@ -27,8 +30,8 @@ impl Strategy {
     }
 
     /// Decide action for the aggressive strategy.
-    fn decide_aggressive(broker: &Broker, _stocks: &[Stock]) -> Action {
-        if broker.cash > 0.0 {
+    fn decide_aggressive(broker: &Broker, stocks: &[Stock]) -> Action {
+        if broker.buying_power(stocks) > Money::ZERO {
             Action::Buy
         } else {
             Action::Hold
@@ -36,10 +39,10 @@ impl Strategy {
     }
 
     /// Decide action for the risk-averse strategy.
-    fn decide_risk_averse(broker: &Broker, _stocks: &[Stock]) -> Action {
-        if broker.holdings.iter().any(|(_, &qty)| qty > 0) {
+    fn decide_risk_averse(broker: &Broker, stocks: &[Stock]) -> Action {
+        if broker.holdings.values().any(|position| position.quantity > 0) {
             Action::Sell
-        } else if broker.cash > 0.0 {
+        } else if broker.buying_power(stocks) > Money::ZERO {
             Action::Buy
         } else {
             Action::Hold
@@ -57,6 +60,7 @@ impl Strategy {
     }
 
     /// Execute the trading strategy for a broker.
+    #[allow(dead_code)]
     pub fn execute(&self, broker: &mut Broker, stocks: &mut [Stock]) {
         match self {
             Strategy::Aggressive => self.execute_aggressive(broker, stocks),
@@ -66,11 +70,12 @@ impl Strategy {
     }
 
     /// Aggressive Strategy: Prioritize buying high-value stocks with available cash.
+    #[allow(dead_code)]
     fn execute_aggressive(&self, broker: &mut Broker, stocks: &mut [Stock]) {
         let mut rng = rand::thread_rng();
 
         // Sort stocks by price (descending) for aggressive buying.
-        stocks.sort_by(|a, b| b.price.partial_cmp(&a.price).unwrap());
+        stocks.sort_by_key(|stock| std::cmp::Reverse(stock.price));
 
         for stock in stocks.iter_mut() {
             let quantity = rng.gen_range(1..=5); // Randomize quantity to buy (1-5).
@@ -85,11 +90,12 @@ impl Strategy {
     }
 
     /// Risk-Averse Strategy: Sell high-value stocks or buy small quantities of low-value stocks.
+    #[allow(dead_code)]
     fn execute_risk_averse(&self, broker: &mut Broker, stocks: &mut [Stock]) {
         let mut rng = rand::thread_rng();
 
         // Sort stocks by price (ascending) for cautious buying.
-        stocks.sort_by(|a, b| a.price.partial_cmp(&b.price).unwrap());
+        stocks.sort_by_key(|stock| stock.price);
 
         for stock in stocks.iter_mut() {
             // Prioritize selling high-value stocks.
@@ -114,6 +120,7 @@ impl Strategy {
     }
 
     /// Random Strategy: Perform random buy or sell actions.
+    #[allow(dead_code)]
     fn execute_random(&self, broker: &mut Broker, stocks: &mut [Stock]) {
         let mut rng = rand::thread_rng();
 
@@ -1,4 +1,6 @@
+use stock_side::money::Money;
 use crate::simulation::Stock;
+use crate::position::Position;
 use std::collections::HashMap;
 use log::info;
 
@@ -18,23 +20,25 @@ pub fn print_stock_list(stocks: &[Stock]) {
     println!("{}", "-".repeat(36));
 }
 
-pub fn print_broker_holdings(broker_id: u32, cash: f64, holdings: &HashMap<String, usize>) {
+#[allow(dead_code)]
+pub fn print_broker_holdings(broker_id: u32, cash: Money, holdings: &HashMap<String, Position>) {
     info!("=== Broker {} Status ===", broker_id);
     println!("Available Cash: ${:.2}", cash);
     if !holdings.is_empty() {
         println!("\nCurrent Holdings:");
         println!("{:<10} {:>12}", "Stock ID", "Quantity");
         println!("{}", "-".repeat(24));
-        for (stock_id, quantity) in holdings {
-            println!("{:<10} {:>12}", stock_id, quantity);
+        for (stock_id, position) in holdings {
+            println!("{:<10} {:>12}", stock_id, position.quantity);
         }
     } else {
         println!("No current holdings");
     }
-    println!("");
+    println!();
 }
 
+#[allow(dead_code)]
 pub fn print_market_separator() {
     println!("{}", "-".repeat(50));
-    println!("");
+    println!();
 }
\ No newline at end of file